@@ -0,0 +1,198 @@
+use crate::metadata;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crossterm::{
+    cursor::{MoveTo, RestorePosition, SavePosition},
+    queue, terminal,
+};
+use image::{imageops::FilterType, DynamicImage};
+use ratatui::layout::Rect;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Image id used for the cover in the kitty graphics protocol
+const IMAGE_ID: u32 = 7301;
+
+/// Fallback cell size in pixels when the terminal doesn't report it
+const DEFAULT_CELL_SIZE: (u32, u32) = (8, 16);
+
+/// Terminal graphics protocols Catty can draw images with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicsProtocol {
+    Kitty,
+    Unsupported,
+}
+
+impl GraphicsProtocol {
+    /// Guess the protocol from the environment of the running terminal
+    pub fn detect() -> Self {
+        let term = std::env::var("TERM").unwrap_or_default();
+        let term_program = std::env::var("TERM_PROGRAM").unwrap_or_default();
+
+        if std::env::var_os("KITTY_WINDOW_ID").is_some()
+            || term.contains("kitty")
+            || term.contains("ghostty")
+            || term_program == "WezTerm"
+            || term_program == "ghostty"
+        {
+            GraphicsProtocol::Kitty
+        } else {
+            GraphicsProtocol::Unsupported
+        }
+    }
+}
+
+/// Cover art of the now-playing track, drawn over the terminal cells
+pub struct AlbumArt {
+    enabled: bool,
+    protocol: GraphicsProtocol,
+    track: Option<PathBuf>,
+    image: Option<DynamicImage>,
+    area: Option<Rect>,
+    drawn: Option<Rect>,
+    dirty: bool,
+}
+
+impl AlbumArt {
+    /// Create the cover art state
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            protocol: GraphicsProtocol::detect(),
+            track: None,
+            image: None,
+            area: None,
+            drawn: None,
+            dirty: false,
+        }
+    }
+
+    /// Turn cover art on or off (e.g. after a config reload)
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled != self.enabled {
+            self.enabled = enabled;
+            self.clear();
+        }
+    }
+
+    /// Load the cover of a track, skipping work if it's already loaded
+    pub fn load(&mut self, path: &Path) {
+        if !self.enabled || self.track.as_deref() == Some(path) {
+            return;
+        }
+
+        self.track = Some(path.to_path_buf());
+        self.image = if self.protocol == GraphicsProtocol::Kitty {
+            metadata::read_cover_art(path).and_then(|data| image::load_from_memory(&data).ok())
+        } else {
+            None
+        };
+        self.dirty = true;
+    }
+
+    /// Forget the current cover (nothing playing)
+    pub fn clear(&mut self) {
+        self.track = None;
+        self.image = None;
+        self.dirty = true;
+    }
+
+    /// Whether an image can be drawn for the current track
+    pub fn can_display(&self) -> bool {
+        self.protocol == GraphicsProtocol::Kitty && self.image.is_some()
+    }
+
+    /// Text shown in place of the image
+    pub fn placeholder(&self) -> &'static str {
+        if self.protocol != GraphicsProtocol::Kitty {
+            "Cover art needs a kitty-compatible terminal"
+        } else if self.track.is_none() {
+            "Nothing playing"
+        } else {
+            "No cover art"
+        }
+    }
+
+    /// Set the cell area the image should occupy (None hides it)
+    pub fn set_area(&mut self, area: Option<Rect>) {
+        if area != self.area {
+            self.area = area;
+            self.dirty = true;
+        }
+    }
+
+    /// Write the graphics escape sequences, if anything changed since the last frame
+    pub fn flush<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.dirty = false;
+
+        if self.drawn.take().is_some() {
+            Self::delete(out)?;
+        }
+
+        if let (Some(area), Some(image)) = (self.area, &self.image) {
+            if self.protocol == GraphicsProtocol::Kitty && area.width > 0 && area.height > 0 {
+                Self::transmit(out, image, area)?;
+                self.drawn = Some(area);
+            }
+        }
+
+        out.flush()
+    }
+
+    /// Remove the image from the screen (used on exit)
+    pub fn delete<W: Write>(out: &mut W) -> io::Result<()> {
+        write!(out, "\x1b_Ga=d,d=I,i={},q=2\x1b\\", IMAGE_ID)?;
+        out.flush()
+    }
+
+    /// Scale the image to the area and send it with the kitty graphics protocol
+    fn transmit<W: Write>(out: &mut W, image: &DynamicImage, area: Rect) -> io::Result<()> {
+        let (cell_w, cell_h) = Self::cell_size();
+        let max_w = area.width as u32 * cell_w;
+        let max_h = area.height as u32 * cell_h;
+
+        let scaled = image.resize(max_w, max_h, FilterType::Triangle).to_rgb8();
+        let (w, h) = scaled.dimensions();
+
+        // Center the picture inside the area
+        let used_cols = w.div_ceil(cell_w) as u16;
+        let used_rows = h.div_ceil(cell_h) as u16;
+        let x = area.x + area.width.saturating_sub(used_cols) / 2;
+        let y = area.y + area.height.saturating_sub(used_rows) / 2;
+
+        let encoded = STANDARD.encode(scaled.as_raw());
+        let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(4096).collect();
+
+        queue!(out, SavePosition, MoveTo(x, y))?;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let more = if i + 1 < chunks.len() { 1 } else { 0 };
+            if i == 0 {
+                write!(
+                    out,
+                    "\x1b_Ga=T,f=24,s={},v={},i={},q=2,C=1,m={};",
+                    w, h, IMAGE_ID, more
+                )?;
+            } else {
+                write!(out, "\x1b_Gm={};", more)?;
+            }
+            out.write_all(chunk)?;
+            out.write_all(b"\x1b\\")?;
+        }
+        queue!(out, RestorePosition)?;
+
+        Ok(())
+    }
+
+    /// Size of one terminal cell in pixels
+    fn cell_size() -> (u32, u32) {
+        match terminal::window_size() {
+            Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => (
+                (size.width / size.columns) as u32,
+                (size.height / size.rows) as u32,
+            ),
+            _ => DEFAULT_CELL_SIZE,
+        }
+    }
+}
@@ -1,14 +1,21 @@
-use anyhow::Result;
-use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
-use std::io::{BufReader, Cursor};
+use crate::equalizer::{Equalizer, EqualizerSettings};
+use crate::visualizer::{SampleRing, SampleTap};
+use anyhow::{anyhow, Result};
+use rodio::cpal::{
+    self,
+    traits::{DeviceTrait, HostTrait},
+};
+use rodio::decoder::DecoderError;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source, StreamError};
+use std::fs::File;
+use std::io::BufReader;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
-/// Get system volume from PulseAudio using pactl (0.0 to 1.0)
-pub fn get_system_volume() -> f32 {
+/// Get system volume from PulseAudio using pactl (0.0 to 1.0), if it can be read
+pub fn get_system_volume() -> Option<f32> {
     use std::process::Command;
 
     match Command::new("pactl")
@@ -23,7 +30,7 @@ pub fn get_system_volume() -> f32 {
                     .and_then(|p| p.split_whitespace().last())
                 {
                     if let Ok(pct) = pct_str.parse::<f32>() {
-                        return (pct / 100.0).clamp(0.0, 1.0);
+                        return Some((pct / 100.0).clamp(0.0, 1.0));
                     }
                 }
             }
@@ -31,215 +38,444 @@ pub fn get_system_volume() -> f32 {
         Err(_) => {}
     }
 
-    1.0 // default
+    None
+}
+
+/// Channel layout and rate the decoder produces for the playing track
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+/// Open output device; the stream must stay alive for the handle to work
+struct Output {
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
 }
 
 /// Audio player using rodio with sample capturing for visualization
 pub struct AudioPlayer {
-    _stream: OutputStream,
-    stream_handle: OutputStreamHandle,
+    output: Option<Output>,
+    device_name: Option<String>, // None when on the default device
+    device_error: Option<String>,
+    device_warning: Option<String>,
     sink: Arc<Mutex<Sink>>,
     current_duration: Arc<Mutex<Option<Duration>>>,
-    sample_buffer: Arc<Mutex<Vec<f32>>>,
-    elapsed_millis: Arc<AtomicU64>,
-    start_time: Arc<Mutex<Option<Instant>>>,
-    pause_elapsed: Arc<AtomicU64>,
+    current_format: Arc<Mutex<Option<StreamFormat>>>,
+    sample_buffer: Arc<Mutex<SampleRing>>,
+    tap_cancel: Mutex<Arc<AtomicBool>>, // Cancels the playing track's sample tap
+    seek_offset: Arc<AtomicU64>,        // Track position (ms) the sink started at
     current_track: Arc<Mutex<Option<PathBuf>>>,
+    track_loaded: Arc<AtomicBool>, // Sink holds the current track's audio
+    volume: Arc<Mutex<f32>>,
+    gain: Arc<Mutex<f32>>, // Per-track loudness multiplier (ReplayGain)
+    fade: Arc<Mutex<f32>>, // Fade-out multiplier (sleep timer)
+    equalizer: Arc<EqualizerSettings>,
+    muted: Arc<AtomicBool>,
+}
+
+/// Everything the player needs from an audio engine, so `PlayerState` can run
+/// on a different one, or on a stand-in without a sound device
+pub trait AudioBackend {
+    fn output_devices(&self) -> Vec<String>;
+    fn set_output_device(&mut self, name: Option<&str>) -> Result<()>;
+    fn device_name(&self) -> Option<&str>;
+    fn device_warning(&self) -> Option<&str>;
+    fn device_error(&self) -> Option<&str>;
+
+    fn play(&self, path: &Path) -> Result<()>;
+    fn seek_to(&self, millis: u64) -> Result<()>;
+    fn pause(&self);
+    fn resume(&self);
+    fn stop(&self);
+    fn get_elapsed_millis(&self) -> u64;
+    fn get_duration(&self) -> Option<Duration>;
+    fn stream_format(&self) -> Option<StreamFormat>;
+    fn is_paused(&self) -> bool;
+    fn is_finished(&self) -> bool;
+    fn get_sample_buffer(&self) -> Arc<Mutex<SampleRing>>;
+
+    fn set_volume(&self, volume: f32);
+    fn set_gain(&self, gain: f32);
+    fn set_fade(&self, fade: f32);
+    fn set_equalizer(&self, enabled: bool, gains: &[f32]);
+    fn toggle_equalizer(&self) -> bool;
+    fn toggle_mute(&self) -> bool;
+
+    /// Seek forward/backward by `step`, stopping at the start or end of the track
+    fn seek_forward(&self, step: Duration) -> Result<()> {
+        let current = self.get_elapsed_millis();
+        self.seek_to(current + step.as_millis() as u64)
+    }
+
+    fn seek_backward(&self, step: Duration) -> Result<()> {
+        let current = self.get_elapsed_millis();
+        self.seek_to(current.saturating_sub(step.as_millis() as u64))
+    }
 }
 
 impl AudioPlayer {
-    /// Create a new player
-    pub fn new() -> Result<Self> {
-        let (stream, stream_handle) = OutputStream::try_default()?;
-        let sink = Sink::try_new(&stream_handle)?;
-
-        Ok(Self {
-            _stream: stream,
-            stream_handle,
+    /// Create a new player.
+    ///
+    /// `device` picks an output device by name; an unknown name falls back to
+    /// the default device and sets `device_warning`. If no output device can be
+    /// opened the player still comes up, but in a "no audio" mode where
+    /// playback calls fail and `device_error` says why.
+    pub fn new(device: Option<&str>) -> Self {
+        let found = device.and_then(Self::find_device);
+        let device_warning = match (device, &found) {
+            (Some(name), None) => Some(format!(
+                "Output device \"{}\" not found, using the default",
+                name
+            )),
+            _ => None,
+        };
+
+        let (output, sink, device_error) = match Self::open_output(found.as_ref()) {
+            Ok((output, sink)) => (Some(output), sink, None),
+            Err(e) => (None, Sink::new_idle().0, Some(e)),
+        };
+
+        Self {
+            output,
+            device_name: found.and(device.map(String::from)),
+            device_error,
+            device_warning,
             sink: Arc::new(Mutex::new(sink)),
             current_duration: Arc::new(Mutex::new(None)),
-            sample_buffer: Arc::new(Mutex::new(Vec::new())),
-            elapsed_millis: Arc::new(AtomicU64::new(0)),
-            start_time: Arc::new(Mutex::new(None)),
-            pause_elapsed: Arc::new(AtomicU64::new(0)),
+            current_format: Arc::new(Mutex::new(None)),
+            sample_buffer: Arc::new(Mutex::new(SampleRing::default())),
+            tap_cancel: Mutex::new(Arc::new(AtomicBool::new(false))),
+            seek_offset: Arc::new(AtomicU64::new(0)),
             current_track: Arc::new(Mutex::new(None)),
+            track_loaded: Arc::new(AtomicBool::new(false)),
+            volume: Arc::new(Mutex::new(1.0)),
+            gain: Arc::new(Mutex::new(1.0)),
+            fade: Arc::new(Mutex::new(1.0)),
+            equalizer: Arc::new(EqualizerSettings::default()),
+            muted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Look up an output device by name
+    fn find_device(name: &str) -> Option<cpal::Device> {
+        cpal::default_host()
+            .output_devices()
+            .ok()?
+            .find(|d| d.name().is_ok_and(|n| n == name))
+    }
+
+    /// Open an output device (the default if `None`) and a sink on it
+    fn open_output(device: Option<&cpal::Device>) -> std::result::Result<(Output, Sink), String> {
+        let stream = match device {
+            Some(device) => OutputStream::try_from_device(device),
+            None => OutputStream::try_default(),
+        };
+        let (stream, handle) = stream.map_err(|e| match e {
+            StreamError::NoDevice => "no output device found".to_string(),
+            other => other.to_string(),
+        })?;
+        let sink = Sink::try_new(&handle).map_err(|e| e.to_string())?;
+
+        Ok((
+            Output {
+                _stream: stream,
+                handle,
+            },
+            sink,
+        ))
+    }
+
+    /// Handle of the output stream, or an error in no-audio mode
+    fn stream_handle(&self) -> Result<&OutputStreamHandle> {
+        self.output
+            .as_ref()
+            .map(|o| &o.handle)
+            .ok_or_else(|| anyhow!("no audio output device available"))
+    }
+
+    /// Route a new source's samples to the visualizer, cutting off the previous one
+    fn tap<S: Source<Item = f32>>(&self, source: S) -> SampleTap<S> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let previous = std::mem::replace(
+            &mut *self.tap_cancel.lock().unwrap(),
+            Arc::clone(&cancelled),
+        );
+        previous.store(true, Ordering::Relaxed);
+        SampleTap::new(source, Arc::clone(&self.sample_buffer), cancelled)
+    }
+
+    /// Open a file for decoding, streaming it from disk. Files none of the
+    /// built-in decoders understand fail with "unsupported format".
+    fn open_decoder(path: &Path) -> Result<Decoder<BufReader<File>>> {
+        let file = File::open(path)?;
+        Decoder::new(BufReader::new(file)).map_err(|e| match e {
+            DecoderError::UnrecognizedFormat | DecoderError::NoStreams => {
+                anyhow!("unsupported format")
+            }
+            e => e.into(),
         })
     }
 
+    /// Volume the sink should play at right now
+    fn effective_volume(&self) -> f32 {
+        if self.muted.load(Ordering::Relaxed) {
+            0.0
+        } else {
+            *self.volume.lock().unwrap() * *self.gain.lock().unwrap() * *self.fade.lock().unwrap()
+        }
+    }
+}
+
+impl AudioBackend for AudioPlayer {
+    /// Names of the output devices that can be picked
+    fn output_devices(&self) -> Vec<String> {
+        cpal::default_host()
+            .output_devices()
+            .map(|devices| devices.filter_map(|d| d.name().ok()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Switch output to the named device (the default if `None`).
+    /// The current track is unloaded; the caller restarts it.
+    fn set_output_device(&mut self, name: Option<&str>) -> Result<()> {
+        let device = match name {
+            Some(name) => Some(
+                Self::find_device(name)
+                    .ok_or_else(|| anyhow!("output device \"{}\" not found", name))?,
+            ),
+            None => None,
+        };
+        let (output, sink) = Self::open_output(device.as_ref()).map_err(|e| anyhow!(e))?;
+
+        self.track_loaded.store(false, Ordering::Release);
+        sink.set_volume(self.effective_volume());
+        *self.sink.lock().unwrap() = sink;
+        self.output = Some(output);
+        self.device_name = name.map(String::from);
+        self.device_error = None;
+        self.device_warning = None;
+        Ok(())
+    }
+
+    /// Name of the configured device in use (`None` for the default)
+    fn device_name(&self) -> Option<&str> {
+        self.device_name.as_deref()
+    }
+
+    /// Why the configured device wasn't used, if it wasn't
+    fn device_warning(&self) -> Option<&str> {
+        self.device_warning.as_deref()
+    }
+
+    /// Why the output device couldn't be opened, if it couldn't
+    fn device_error(&self) -> Option<&str> {
+        self.device_error.as_deref()
+    }
+
     /// Play a track
-    pub fn play(&self, path: &Path) -> Result<()> {
-        let data = std::fs::read(path)?;
+    fn play(&self, path: &Path) -> Result<()> {
+        let stream_handle = self.stream_handle()?;
+        // Decode straight from disk so large files aren't held in memory
+        let decoder = Self::open_decoder(path)?;
+        let format = StreamFormat {
+            channels: decoder.channels(),
+            sample_rate: decoder.sample_rate(),
+        };
+        let playback_decoder = decoder.convert_samples::<f32>();
 
         // Store current track path
         *self.current_track.lock().unwrap() = Some(path.to_path_buf());
-
-        // Playback decoder
-        let playback_cursor = Cursor::new(data.clone());
-        let playback_decoder =
-            Decoder::new(BufReader::new(playback_cursor))?.convert_samples::<f32>();
-
-        // Visualization decoder
-        let vis_cursor = Cursor::new(data);
-        let mut vis_decoder = Decoder::new(BufReader::new(vis_cursor))?.convert_samples::<f32>();
+        *self.current_format.lock().unwrap() = Some(format);
 
         // Duration
         *self.current_duration.lock().unwrap() = playback_decoder.total_duration();
 
-        self.elapsed_millis.store(0, Ordering::Relaxed);
-        *self.start_time.lock().unwrap() = Some(Instant::now());
+        self.seek_offset.store(0, Ordering::Relaxed);
 
         // Stop old sink
+        self.track_loaded.store(false, Ordering::Release);
         self.sink.lock().unwrap().stop();
-        let new_sink = Sink::try_new(&self.stream_handle)?;
-        new_sink.append(playback_decoder);
+        self.sample_buffer.lock().unwrap().clear();
+        let new_sink = Sink::try_new(stream_handle)?;
+        new_sink.set_volume(self.effective_volume());
+        // Tap after the equalizer so the visualizer shows what's heard
+        new_sink.append(self.tap(Equalizer::new(
+            playback_decoder,
+            Arc::clone(&self.equalizer),
+        )));
         new_sink.play();
         *self.sink.lock().unwrap() = new_sink;
-
-        // Background thread for visualizer
-        let sample_buffer = Arc::clone(&self.sample_buffer);
-        thread::spawn(move || {
-            let channels = vis_decoder.channels() as usize;
-            let sample_rate = vis_decoder.sample_rate();
-            let chunk_frames = 1024;
-
-            loop {
-                let mut tmp = Vec::with_capacity(chunk_frames * channels);
-                for _ in 0..(chunk_frames * channels) {
-                    if let Some(s) = vis_decoder.next() {
-                        tmp.push(s);
-                    } else {
-                        break;
-                    }
-                }
-                if tmp.is_empty() {
-                    break;
-                }
-
-                // convert to mono
-                let mono = if channels > 1 {
-                    let frames = tmp.len() / channels;
-                    let mut mono = Vec::with_capacity(frames);
-                    for frame_idx in 0..frames {
-                        let sum: f32 = (0..channels).map(|ch| tmp[frame_idx * channels + ch]).sum();
-                        mono.push(sum / channels as f32);
-                    }
-                    mono
-                } else {
-                    tmp
-                };
-
-                let mut buf = sample_buffer.lock().unwrap();
-                buf.extend_from_slice(&mono);
-                if buf.len() > 8192 {
-                    buf.drain(..4096);
-                }
-
-                let sleep_ms = ((chunk_frames as f32 / sample_rate as f32) * 1000.0) as u64;
-                thread::sleep(Duration::from_millis(sleep_ms.max(10)));
-            }
-        });
+        self.track_loaded.store(true, Ordering::Release);
 
         Ok(())
     }
 
     /// Seek to specific position
-    pub fn seek_to(&self, millis: u64) -> Result<()> {
-        if let Some(duration) = *self.current_duration.lock().unwrap() {
-            let target = millis.min(duration.as_millis() as u64);
-
-            // Stop current sink
-            self.sink.lock().unwrap().stop();
-
-            // Recreate decoder
-            if let Some(track_path) = &*self.current_track.lock().unwrap() {
-                let data = std::fs::read(track_path)?;
-                let cursor = Cursor::new(data);
-                let mut decoder = Decoder::new(cursor)?.convert_samples::<f32>();
-
-                // skip samples
-                let sample_rate = decoder.sample_rate() as u64;
-                let channels = decoder.channels() as u64;
-                let frames_to_skip = (target * sample_rate) / 1000;
-                let samples_to_skip = frames_to_skip * channels;
-                for _ in 0..samples_to_skip {
-                    if decoder.next().is_none() {
-                        break;
-                    }
-                }
-
-                let new_sink = Sink::try_new(&self.stream_handle)?;
-                new_sink.append(decoder);
-                new_sink.play();
-                *self.sink.lock().unwrap() = new_sink;
+    fn seek_to(&self, millis: u64) -> Result<()> {
+        let Some(duration) = *self.current_duration.lock().unwrap() else {
+            return Ok(());
+        };
+        let target = millis.min(duration.as_millis() as u64);
+
+        // Most formats seek in place, and the sink then reports the new position itself.
+        // An empty sink (track already over) silently ignores seeks, so rebuild it instead.
+        {
+            let sink = self.sink.lock().unwrap();
+            if !sink.empty() && sink.try_seek(Duration::from_millis(target)).is_ok() {
+                self.seek_offset.store(0, Ordering::Relaxed);
+                return Ok(());
+            }
+        }
 
-                self.elapsed_millis.store(target, Ordering::Relaxed);
-                *self.start_time.lock().unwrap() =
-                    Some(Instant::now() - Duration::from_millis(target));
+        // Otherwise decode from the start, skip up to the target and
+        // remember where the new sink begins
+        let Some(track_path) = self.current_track.lock().unwrap().clone() else {
+            return Ok(());
+        };
+        let mut decoder = Self::open_decoder(&track_path)?.convert_samples::<f32>();
+
+        // skip samples
+        let sample_rate = decoder.sample_rate() as u64;
+        let channels = decoder.channels() as u64;
+        let frames_to_skip = (target * sample_rate) / 1000;
+        let samples_to_skip = frames_to_skip * channels;
+        for _ in 0..samples_to_skip {
+            if decoder.next().is_none() {
+                break;
             }
         }
-        Ok(())
-    }
 
-    /// Seek forward/backward
-    pub fn seek_forward(&self) -> Result<()> {
-        let current = self.get_elapsed_millis();
-        self.seek_to(current + 10_000)
-    }
+        // Stop current sink, keeping it paused if it was
+        self.track_loaded.store(false, Ordering::Release);
+        let paused = {
+            let sink = self.sink.lock().unwrap();
+            sink.stop();
+            sink.is_paused()
+        };
+
+        let new_sink = Sink::try_new(self.stream_handle()?)?;
+        new_sink.set_volume(self.effective_volume());
+        new_sink.append(self.tap(Equalizer::new(decoder, Arc::clone(&self.equalizer))));
+        if paused {
+            new_sink.pause();
+        }
+        *self.sink.lock().unwrap() = new_sink;
+        self.seek_offset.store(target, Ordering::Relaxed);
+        self.track_loaded.store(true, Ordering::Release);
 
-    pub fn seek_backward(&self) -> Result<()> {
-        let current = self.get_elapsed_millis();
-        self.seek_to(current.saturating_sub(10_000))
+        Ok(())
     }
 
     /// Pause/resume/stop
-    pub fn pause(&self) {
-        self.pause_elapsed
-            .store(self.get_elapsed_millis(), Ordering::Relaxed);
+    fn pause(&self) {
         self.sink.lock().unwrap().pause();
     }
 
-    pub fn resume(&self) {
-        let frozen = self.pause_elapsed.load(Ordering::Relaxed);
-        *self.start_time.lock().unwrap() = Some(Instant::now() - Duration::from_millis(frozen));
+    fn resume(&self) {
         self.sink.lock().unwrap().play();
     }
 
-    pub fn stop(&self) {
+    fn stop(&self) {
+        self.track_loaded.store(false, Ordering::Release);
         self.sink.lock().unwrap().stop();
+        self.tap_cancel
+            .lock()
+            .unwrap()
+            .store(true, Ordering::Relaxed);
         self.sample_buffer.lock().unwrap().clear();
-        self.elapsed_millis.store(0, Ordering::Relaxed);
-        *self.start_time.lock().unwrap() = None;
+        self.seek_offset.store(0, Ordering::Relaxed);
+        *self.current_duration.lock().unwrap() = None;
+        *self.current_format.lock().unwrap() = None;
     }
 
-    /// Utilities
-    pub fn get_elapsed_millis(&self) -> u64 {
-        if self.sink.lock().unwrap().is_paused() {
-            return self.pause_elapsed.load(Ordering::Relaxed);
-        }
-        if let Some(start) = *self.start_time.lock().unwrap() {
-            start.elapsed().as_millis() as u64
-        } else {
-            self.elapsed_millis.load(Ordering::Relaxed)
+    /// Playback position as reported by the sink, never past the end of the track
+    fn get_elapsed_millis(&self) -> u64 {
+        let duration = self.get_duration().map(|d| d.as_millis() as u64);
+
+        // Once the audio has run out the position is the end of the track
+        if let (true, Some(duration)) = (self.is_finished(), duration) {
+            return duration;
         }
+
+        let pos = self.seek_offset.load(Ordering::Relaxed)
+            + self.sink.lock().unwrap().get_pos().as_millis() as u64;
+        duration.map_or(pos, |d| pos.min(d))
     }
 
-    pub fn get_sample_buffer(&self) -> Arc<Mutex<Vec<f32>>> {
+    /// Ring the visualizer reads the playing track's samples from
+    fn get_sample_buffer(&self) -> Arc<Mutex<SampleRing>> {
         Arc::clone(&self.sample_buffer)
     }
 
-    pub fn set_volume(&self, volume: f32) {
-        self.sink.lock().unwrap().set_volume(volume);
+    /// Set the playback volume (kept while muted, applied on unmute)
+    fn set_volume(&self, volume: f32) {
+        *self.volume.lock().unwrap() = volume;
+        self.sink
+            .lock()
+            .unwrap()
+            .set_volume(self.effective_volume());
+    }
+
+    /// Set the loudness adjustment applied on top of the volume
+    fn set_gain(&self, gain: f32) {
+        *self.gain.lock().unwrap() = gain;
+        self.sink
+            .lock()
+            .unwrap()
+            .set_volume(self.effective_volume());
     }
 
-    pub fn get_duration(&self) -> Option<Duration> {
+    /// Scale the volume down for a fade-out (1.0 is full volume)
+    fn set_fade(&self, fade: f32) {
+        *self.fade.lock().unwrap() = fade.clamp(0.0, 1.0);
+        self.sink
+            .lock()
+            .unwrap()
+            .set_volume(self.effective_volume());
+    }
+
+    /// Set the equalizer band gains (dB, lowest band first) and switch it on or off
+    fn set_equalizer(&self, enabled: bool, gains: &[f32]) {
+        self.equalizer.set_gains(gains);
+        self.equalizer.set_enabled(enabled);
+    }
+
+    /// Switch the equalizer on or off, returning whether it's now on
+    fn toggle_equalizer(&self) -> bool {
+        let enabled = !self.equalizer.is_enabled();
+        self.equalizer.set_enabled(enabled);
+        enabled
+    }
+
+    /// Mute or unmute, returning whether output is now muted
+    fn toggle_mute(&self) -> bool {
+        let muted = !self.muted.fetch_xor(true, Ordering::Relaxed);
+        self.sink
+            .lock()
+            .unwrap()
+            .set_volume(self.effective_volume());
+        muted
+    }
+
+    fn get_duration(&self) -> Option<Duration> {
         *self.current_duration.lock().unwrap()
     }
 
-    pub fn is_paused(&self) -> bool {
+    fn stream_format(&self) -> Option<StreamFormat> {
+        *self.current_format.lock().unwrap()
+    }
+
+    fn is_paused(&self) -> bool {
         self.sink.lock().unwrap().is_paused()
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.sink.lock().unwrap().empty()
+    /// Whether the current track has played through to the end.
+    ///
+    /// Only a sink that was actually given the track counts, so the gap while
+    /// `play` or `seek_to` swap sinks never looks like the end of a track.
+    fn is_finished(&self) -> bool {
+        self.track_loaded.load(Ordering::Acquire) && self.sink.lock().unwrap().empty()
     }
 }
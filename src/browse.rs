@@ -0,0 +1,170 @@
+use crate::database::Track;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+const UNKNOWN_ARTIST: &str = "Unknown Artist";
+const UNKNOWN_ALBUM: &str = "Unknown Album";
+
+/// How the library is presented
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BrowseMode {
+    /// Plain list of every track
+    #[default]
+    Flat,
+    /// Artist → album → track hierarchy
+    Tree,
+    /// Tracks that have been played, most plays first
+    MostPlayed,
+}
+
+impl BrowseMode {
+    /// Next mode in the cycle
+    pub fn next(self) -> Self {
+        match self {
+            BrowseMode::Flat => BrowseMode::Tree,
+            BrowseMode::Tree => BrowseMode::MostPlayed,
+            BrowseMode::MostPlayed => BrowseMode::Flat,
+        }
+    }
+}
+
+/// One visible line of the library tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeRow {
+    Artist {
+        name: String,
+        collapsed: bool,
+    },
+    Album {
+        artist: String,
+        name: String,
+        collapsed: bool,
+    },
+    /// Index into the library tracks
+    Track(usize),
+}
+
+/// What a tree row shows, which unlike its position survives library changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeRowId {
+    Group(String),
+    Track(PathBuf),
+}
+
+impl TreeRow {
+    pub fn id(&self, tracks: &[Track]) -> Option<TreeRowId> {
+        match self {
+            TreeRow::Track(idx) => tracks.get(*idx).map(|t| TreeRowId::Track(t.path.clone())),
+            _ => self.group_key().map(TreeRowId::Group),
+        }
+    }
+
+    /// Key used to remember whether this header is collapsed
+    pub fn group_key(&self) -> Option<String> {
+        match self {
+            TreeRow::Artist { name, .. } => Some(artist_key(name)),
+            TreeRow::Album { artist, name, .. } => Some(album_key(artist, name)),
+            TreeRow::Track(_) => None,
+        }
+    }
+}
+
+fn artist_key(artist: &str) -> String {
+    artist.to_string()
+}
+
+fn album_key(artist: &str, album: &str) -> String {
+    format!("{}\u{0}{}", artist, album)
+}
+
+/// Keys of the artist and album headers a track is listed under
+pub fn group_keys(track: &Track) -> [String; 2] {
+    let artist = artist_of(track);
+    [artist_key(artist), album_key(artist, album_of(track))]
+}
+
+/// Artist name a track is grouped under
+pub fn artist_of(track: &Track) -> &str {
+    track.artist.as_deref().unwrap_or(UNKNOWN_ARTIST)
+}
+
+/// Album name a track is grouped under
+pub fn album_of(track: &Track) -> &str {
+    track.album.as_deref().unwrap_or(UNKNOWN_ALBUM)
+}
+
+/// Library indices grouped by (artist, album) and in album track order
+fn grouped_indices(tracks: &[Track]) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..tracks.len()).collect();
+    indices.sort_by(|&a, &b| {
+        let (ta, tb) = (&tracks[a], &tracks[b]);
+        artist_of(ta)
+            .to_lowercase()
+            .cmp(&artist_of(tb).to_lowercase())
+            .then_with(|| {
+                album_of(ta)
+                    .to_lowercase()
+                    .cmp(&album_of(tb).to_lowercase())
+            })
+            .then_with(|| ta.track_number.cmp(&tb.track_number))
+            .then_with(|| ta.title.to_lowercase().cmp(&tb.title.to_lowercase()))
+    });
+    indices
+}
+
+/// Build the visible rows, skipping children of collapsed headers
+pub fn build_rows(tracks: &[Track], collapsed: &HashSet<String>) -> Vec<TreeRow> {
+    let mut rows = Vec::new();
+    let mut current_artist: Option<&str> = None;
+    let mut current_album: Option<&str> = None;
+
+    for i in grouped_indices(tracks) {
+        let track = &tracks[i];
+        let artist = artist_of(track);
+        let album = album_of(track);
+
+        if current_artist != Some(artist) {
+            current_artist = Some(artist);
+            current_album = None;
+            rows.push(TreeRow::Artist {
+                name: artist.to_string(),
+                collapsed: collapsed.contains(&artist_key(artist)),
+            });
+        }
+        if collapsed.contains(&artist_key(artist)) {
+            continue;
+        }
+
+        if current_album != Some(album) {
+            current_album = Some(album);
+            rows.push(TreeRow::Album {
+                artist: artist.to_string(),
+                name: album.to_string(),
+                collapsed: collapsed.contains(&album_key(artist, album)),
+            });
+        }
+        if collapsed.contains(&album_key(artist, album)) {
+            continue;
+        }
+
+        rows.push(TreeRow::Track(i));
+    }
+
+    rows
+}
+
+/// Library indices of an artist's tracks, album by album
+pub fn artist_tracks(tracks: &[Track], artist: &str) -> Vec<usize> {
+    grouped_indices(tracks)
+        .into_iter()
+        .filter(|&i| artist_of(&tracks[i]) == artist)
+        .collect()
+}
+
+/// Library indices of an album's tracks, in track order
+pub fn album_tracks(tracks: &[Track], artist: &str, album: &str) -> Vec<usize> {
+    grouped_indices(tracks)
+        .into_iter()
+        .filter(|&i| artist_of(&tracks[i]) == artist && album_of(&tracks[i]) == album)
+        .collect()
+}
@@ -1,29 +1,242 @@
+use crate::database::{DuplicateMode, DuplicatePreference, SortKey};
+use crate::keybind::{Action, KeyBinding};
+use crate::visualizer::{VisualizerMode, VisualizerOrientation};
+use anyhow::Result;
 use ratatui::style::Color;
 use serde::{Deserialize, Serialize};
-use std::{fs, path::Path};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Config file given with `--config`, used instead of the usual location
+static CONFIG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Whether Catty runs on the Linux console, checked once
+static LINUX_CONSOLE: OnceLock<bool> = OnceLock::new();
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub colors: ColorConfig,
     pub keybinds: KeybindConfig,
     pub visualizer: VisualizerConfig,
     pub watermark: WatermarkConfig,
+    pub symbols: SymbolConfig,
+    pub album_art: AlbumArtConfig,
+    pub library: LibraryConfig,
+    pub history: HistoryConfig,
+    pub playlist: PlaylistConfig,
+    pub audio: AudioConfig,
+    pub playback: PlaybackConfig,
+    pub notifications: NotificationConfig,
+    pub control: ControlConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ColorConfig {
     pub foreground: String,
     pub background: String,
     pub accent: String,
     pub visualizer_foreground: String,
     pub visualizer_background: String,
+    pub selected_foreground: String,
+    pub selected_background: String,
+    pub playing_foreground: String,
+    /// Library tracks that are in the queue
+    pub queued_foreground: String,
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WatermarkConfig {
     pub water_mark: bool,
 }
 
+/// Glyphs drawn in the lists and visualizer, for fonts that lack the defaults
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SymbolConfig {
+    pub playing: String,
+    pub paused: String,
+    /// Before the selected row, unless another marker is shown there
+    pub selected: String,
+    pub unplayable: String,
+    pub duplicate: String,
+    pub favorite: String,
+    /// Fills the visualizer bars; anything but "█" also drops the partial blocks
+    pub bar: String,
+    pub watermark: String,
+    /// Draw with ASCII only, for terminals without Unicode glyphs; unset turns
+    /// it on for the Linux console
+    pub ascii_mode: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlbumArtConfig {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LibraryConfig {
+    pub sort_key: SortKey,
+    pub sort_descending: bool,
+    /// Moving past either end of a list continues from the other end
+    pub wrap_around: bool,
+    /// File extensions scanned on top of the ones Catty can decode
+    pub extra_extensions: Vec<String>,
+    /// Where the library cache, history and session are kept (`~` is expanded);
+    /// unset uses ~/.cache/catty, and `CATTY_CACHE_DIR` overrides both
+    pub cache_dir: Option<String>,
+    /// Whether extra copies of a song are looked for, and then marked or hidden
+    pub duplicates: DuplicateMode,
+    /// Which copy of a duplicated song counts as the original
+    pub duplicate_preference: DuplicatePreference,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Number of recently played tracks to remember
+    pub max_entries: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlaylistConfig {
+    /// Where the save-playlist keybind writes the queue (`~` is expanded)
+    pub save_path: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlaybackConfig {
+    /// Start playing on launch: the queue, else the last played track, else the first one
+    pub autoplay: bool,
+    /// Past this many seconds into a track, previous restarts it instead; 0 always goes back
+    pub previous_restart_seconds: u64,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> Self {
+        Self {
+            autoplay: false,
+            previous_restart_seconds: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationConfig {
+    /// Show a desktop notification (via notify-send) when the track changes
+    pub enabled: bool,
+    /// Include the track's embedded cover in the notification
+    pub cover_art: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlConfig {
+    /// Unix socket scripts can send commands to (`~` is expanded); unset is off
+    pub socket: Option<String>,
+}
+
+impl ControlConfig {
+    pub fn socket_path(&self) -> Option<PathBuf> {
+        self.socket
+            .as_deref()
+            .filter(|path| !path.trim().is_empty())
+            .map(|path| PathBuf::from(&*shellexpand::tilde(path)))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub replaygain: ReplayGainMode,
+    /// Output device name; unset uses the system default
+    pub output_device: Option<String>,
+    pub seek_seconds: u64,
+    /// Step of the big seek keys, for long tracks like podcasts
+    pub big_seek_seconds: u64,
+    /// Volume (0.0 to 1.0) on the first launch; later launches restore the last one
+    pub initial_volume: f32,
+    /// Volume change of the volume keys, and of the fine ones for precise leveling
+    pub volume_step: f32,
+    pub fine_volume_step: f32,
+    /// Also show the system output volume, read from PulseAudio
+    pub show_system_volume: bool,
+    pub equalizer: EqualizerConfig,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            replaygain: ReplayGainMode::default(),
+            output_device: None,
+            seek_seconds: 10,
+            big_seek_seconds: 60,
+            initial_volume: 0.2,
+            volume_step: 0.05,
+            fine_volume_step: 0.01,
+            show_system_volume: false,
+            equalizer: EqualizerConfig::default(),
+        }
+    }
+}
+
+impl AudioConfig {
+    pub fn seek_step(&self) -> Duration {
+        Duration::from_secs(self.seek_seconds)
+    }
+
+    pub fn big_seek_step(&self) -> Duration {
+        Duration::from_secs(self.big_seek_seconds)
+    }
+
+    pub fn volume_step(&self) -> f32 {
+        self.volume_step.clamp(0.0, 1.0)
+    }
+
+    pub fn fine_volume_step(&self) -> f32 {
+        self.fine_volume_step.clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EqualizerConfig {
+    pub enabled: bool,
+    /// Band gains in dB from bass to treble; the outer bands are shelves
+    pub bands: Vec<f32>,
+}
+
+impl Default for EqualizerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bands: vec![0.0; 5],
+        }
+    }
+}
+
+/// Which ReplayGain tag evens out loudness between tracks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayGainMode {
+    /// Level each track on its own
+    #[default]
+    Track,
+    /// Keep the relative loudness of tracks within an album
+    Album,
+    Off,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct KeybindConfig {
     pub quit: String,
     pub play_pause: String,
@@ -32,19 +245,84 @@ pub struct KeybindConfig {
     pub shuffle: String,
     pub volume_up: String,
     pub volume_down: String,
+    pub fine_volume_up: String,
+    pub fine_volume_down: String,
     pub select: String,
     pub clear: String,
     pub search: String,
+    pub filter: String,
+    pub favorite: String,
+    pub favorites_filter: String,
     pub LoopC: String,
     pub seek_forward: String,
     pub seek_backward: String,
+    pub big_seek_forward: String,
+    pub big_seek_backward: String,
     pub help: String,
+    pub visualizer_mode: String,
+    pub toggle_visualizer: String,
+    pub sort: String,
+    pub browse_mode: String,
+    pub history: String,
+    pub scroll_up: String,
+    pub scroll_down: String,
+    pub page_up: String,
+    pub page_down: String,
+    pub first: String,
+    pub last: String,
+    pub mute: String,
+    pub trim_up: String,
+    pub trim_down: String,
+    pub save_playlist: String,
+    pub output_device: String,
+    pub queue: String,
+    pub move_up: String,
+    pub move_down: String,
+    pub queue_focus: String,
+    pub enqueue: String,
+    pub remove_from_queue: String,
+    pub stop_after_current: String,
+    pub sleep_timer: String,
+    pub equalizer: String,
+    pub loop_a: String,
+    pub loop_b: String,
+    pub loop_clear: String,
+    pub select_current: String,
+    pub play_random: String,
+    pub rescan: String,
+    pub refresh_tags: String,
+    pub track_info: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct VisualizerConfig {
+    /// Show the visualizer; toggling it with its key is remembered in state.json instead
+    pub enabled: bool,
+    /// Spectrum bars, up to one per FFT frequency bin (1024)
     pub bar_count: usize,
+    /// Columns each bar is drawn across (rows when horizontal)
+    pub bar_width: usize,
+    /// Blank columns between bars (rows when horizontal)
+    pub bar_gap: usize,
     pub smoothing: f32,
+    /// Smoothing while bars rise (lower snaps up faster); defaults to `smoothing`
+    pub attack: Option<f32>,
+    /// Smoothing while bars fall (higher decays slower); defaults to `smoothing`
+    pub release: Option<f32>,
+    /// Scale bars to the loudest recent audio instead of a fixed level
+    pub auto_gain: bool,
+    pub mode: VisualizerMode,
+    pub orientation: VisualizerOrientation,
+    /// Show the left and right channels as separate spectra
+    pub stereo: bool,
+    /// Colors from quiet (bottom) to loud (peak); empty uses `visualizer_foreground`
+    pub gradient: Vec<String>,
+    /// Multipliers from the lowest to the highest bar, stretched across all
+    /// bars; empty is flat
+    pub eq: Vec<f32>,
+    /// Redraws per second; higher is smoother but costs more CPU
+    pub fps: u32,
 }
 
 /* ---------------------- Default Implementations ---------------------- */
@@ -53,30 +331,184 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             watermark: WatermarkConfig::default(),
+            symbols: SymbolConfig::default(),
             colors: ColorConfig::default(),
             keybinds: KeybindConfig::default(),
             visualizer: VisualizerConfig::default(),
+            album_art: AlbumArtConfig::default(),
+            library: LibraryConfig::default(),
+            history: HistoryConfig::default(),
+            playlist: PlaylistConfig::default(),
+            audio: AudioConfig::default(),
+            playback: PlaybackConfig::default(),
+            notifications: NotificationConfig::default(),
+            control: ControlConfig::default(),
         }
     }
 }
 
+const DEFAULT_PLAYING_SYMBOL: &str = "▶";
+const DEFAULT_PAUSED_SYMBOL: &str = "⏸";
+const DEFAULT_UNPLAYABLE_SYMBOL: &str = "✗";
+const DEFAULT_DUPLICATE_SYMBOL: &str = "≈";
+const DEFAULT_FAVORITE_SYMBOL: &str = "★";
+const DEFAULT_BAR_SYMBOL: &str = "█";
+const DEFAULT_WATERMARK_SYMBOL: &str = "󰎆";
+
+const DEFAULT_FOREGROUND: &str = "white";
+const DEFAULT_BACKGROUND: &str = "black";
+const DEFAULT_ACCENT: &str = "cyan";
+const DEFAULT_VISUALIZER_FOREGROUND: &str = "LightBlue";
+const DEFAULT_VISUALIZER_BACKGROUND: &str = "black";
+const DEFAULT_SELECTED_FOREGROUND: &str = "yellow";
+const DEFAULT_SELECTED_BACKGROUND: &str = "reset";
+const DEFAULT_PLAYING_FOREGROUND: &str = "green";
+const DEFAULT_QUEUED_FOREGROUND: &str = "magenta";
+
 impl Default for ColorConfig {
     fn default() -> Self {
         Self {
-            foreground: "white".into(),
-            background: "black".into(),
-            accent: "cyan".into(),
-            visualizer_foreground: "LightBlue".into(),
-            visualizer_background: "black".into(),
+            foreground: DEFAULT_FOREGROUND.into(),
+            background: DEFAULT_BACKGROUND.into(),
+            accent: DEFAULT_ACCENT.into(),
+            visualizer_foreground: DEFAULT_VISUALIZER_FOREGROUND.into(),
+            visualizer_background: DEFAULT_VISUALIZER_BACKGROUND.into(),
+            selected_foreground: DEFAULT_SELECTED_FOREGROUND.into(),
+            selected_background: DEFAULT_SELECTED_BACKGROUND.into(),
+            playing_foreground: DEFAULT_PLAYING_FOREGROUND.into(),
+            queued_foreground: DEFAULT_QUEUED_FOREGROUND.into(),
         }
     }
 }
+
+/// Parsed theme colors; an invalid value falls back to that field's default
+impl ColorConfig {
+    fn resolve(value: &str, default: &str) -> Color {
+        Config::try_parse_color(value).unwrap_or_else(|_| Config::parse_color(default))
+    }
+
+    pub fn foreground(&self) -> Color {
+        Self::resolve(&self.foreground, DEFAULT_FOREGROUND)
+    }
+
+    pub fn accent(&self) -> Color {
+        Self::resolve(&self.accent, DEFAULT_ACCENT)
+    }
+
+    pub fn visualizer_foreground(&self) -> Color {
+        Self::resolve(&self.visualizer_foreground, DEFAULT_VISUALIZER_FOREGROUND)
+    }
+
+    pub fn visualizer_background(&self) -> Color {
+        Self::resolve(&self.visualizer_background, DEFAULT_VISUALIZER_BACKGROUND)
+    }
+
+    pub fn selected_foreground(&self) -> Color {
+        Self::resolve(&self.selected_foreground, DEFAULT_SELECTED_FOREGROUND)
+    }
+
+    pub fn selected_background(&self) -> Color {
+        Self::resolve(&self.selected_background, DEFAULT_SELECTED_BACKGROUND)
+    }
+
+    pub fn playing_foreground(&self) -> Color {
+        Self::resolve(&self.playing_foreground, DEFAULT_PLAYING_FOREGROUND)
+    }
+
+    pub fn queued_foreground(&self) -> Color {
+        Self::resolve(&self.queued_foreground, DEFAULT_QUEUED_FOREGROUND)
+    }
+}
 impl Default for WatermarkConfig {
     fn default() -> Self {
         Self { water_mark: true }
     }
 }
 
+impl Default for SymbolConfig {
+    fn default() -> Self {
+        Self {
+            playing: DEFAULT_PLAYING_SYMBOL.into(),
+            paused: DEFAULT_PAUSED_SYMBOL.into(),
+            selected: String::new(),
+            unplayable: DEFAULT_UNPLAYABLE_SYMBOL.into(),
+            duplicate: DEFAULT_DUPLICATE_SYMBOL.into(),
+            favorite: DEFAULT_FAVORITE_SYMBOL.into(),
+            bar: DEFAULT_BAR_SYMBOL.into(),
+            watermark: DEFAULT_WATERMARK_SYMBOL.into(),
+            ascii_mode: None,
+        }
+    }
+}
+
+impl SymbolConfig {
+    /// Whether to draw with ASCII only, as set or detected
+    pub fn ascii(&self) -> bool {
+        self.ascii_mode.unwrap_or_else(|| {
+            *LINUX_CONSOLE.get_or_init(|| std::env::var("TERM").is_ok_and(|term| term == "linux"))
+        })
+    }
+
+    pub fn playing(&self) -> &str {
+        self.pick(&self.playing, DEFAULT_PLAYING_SYMBOL, ">")
+    }
+
+    pub fn paused(&self) -> &str {
+        self.pick(&self.paused, DEFAULT_PAUSED_SYMBOL, "||")
+    }
+
+    pub fn selected(&self) -> &str {
+        &self.selected
+    }
+
+    pub fn unplayable(&self) -> &str {
+        self.pick(&self.unplayable, DEFAULT_UNPLAYABLE_SYMBOL, "x")
+    }
+
+    pub fn duplicate(&self) -> &str {
+        self.pick(&self.duplicate, DEFAULT_DUPLICATE_SYMBOL, "~")
+    }
+
+    pub fn favorite(&self) -> &str {
+        self.pick(&self.favorite, DEFAULT_FAVORITE_SYMBOL, "*")
+    }
+
+    pub fn watermark(&self) -> &str {
+        self.pick(&self.watermark, DEFAULT_WATERMARK_SYMBOL, "=^.^=")
+    }
+
+    /// Character the visualizer fills its bars with
+    pub fn bar(&self) -> char {
+        self.pick(&self.bar, DEFAULT_BAR_SYMBOL, "#")
+            .chars()
+            .next()
+            .unwrap_or('█')
+    }
+
+    /// In ASCII mode a symbol left at its default gets an ASCII stand-in
+    fn pick<'a>(&'a self, symbol: &'a str, default: &str, ascii: &'static str) -> &'a str {
+        if self.ascii() && symbol == default {
+            ascii
+        } else {
+            symbol
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self { max_entries: 100 }
+    }
+}
+
+impl Default for PlaylistConfig {
+    fn default() -> Self {
+        Self {
+            save_path: "~/Music/Playlists/catty-queue.m3u8".into(),
+        }
+    }
+}
+
 impl Default for KeybindConfig {
     fn default() -> Self {
         Self {
@@ -87,49 +519,253 @@ impl Default for KeybindConfig {
             shuffle: "s".into(),
             volume_up: "+".into(),
             volume_down: "-".into(),
+            fine_volume_up: "alt++".into(),
+            fine_volume_down: "alt+-".into(),
             select: "enter".into(),
             clear: "c".into(),
             seek_forward: "f".into(),
             seek_backward: "h".into(),
+            big_seek_forward: "shift+f".into(),
+            big_seek_backward: "shift+h".into(),
             help: "?".into(),
             search: "/".into(),
+            filter: "ctrl+f".into(),
+            favorite: "*".into(),
+            favorites_filter: "ctrl+s".into(),
             LoopC: "l".into(),
+            visualizer_mode: "v".into(),
+            toggle_visualizer: "shift+v".into(),
+            sort: "t".into(),
+            browse_mode: "b".into(),
+            history: "y".into(),
+            scroll_up: "up".into(),
+            scroll_down: "down".into(),
+            page_up: "pageup".into(),
+            page_down: "pagedown".into(),
+            first: "home".into(),
+            last: "end".into(),
+            mute: "m".into(),
+            trim_up: ">".into(),
+            trim_down: "<".into(),
+            save_playlist: "w".into(),
+            output_device: "o".into(),
+            queue: "u".into(),
+            move_up: "shift+up".into(),
+            move_down: "shift+down".into(),
+            queue_focus: "tab".into(),
+            enqueue: "a".into(),
+            remove_from_queue: "x".into(),
+            stop_after_current: "z".into(),
+            sleep_timer: "ctrl+t".into(),
+            equalizer: "e".into(),
+            loop_a: "[".into(),
+            loop_b: "]".into(),
+            loop_clear: "\\".into(),
+            select_current: "g".into(),
+            play_random: "r".into(),
+            rescan: "shift+r".into(),
+            refresh_tags: "ctrl+r".into(),
+            track_info: "i".into(),
         }
     }
 }
 
+impl KeybindConfig {
+    /// Every action paired with the key bound to it, in config.toml order
+    pub fn bindings(&self) -> Vec<(Action, &str)> {
+        vec![
+            (Action::Quit, &self.quit),
+            (Action::PlayPause, &self.play_pause),
+            (Action::Next, &self.next),
+            (Action::Previous, &self.previous),
+            (Action::Shuffle, &self.shuffle),
+            (Action::VolumeUp, &self.volume_up),
+            (Action::VolumeDown, &self.volume_down),
+            (Action::FineVolumeUp, &self.fine_volume_up),
+            (Action::FineVolumeDown, &self.fine_volume_down),
+            (Action::Select, &self.select),
+            (Action::Clear, &self.clear),
+            (Action::Search, &self.search),
+            (Action::Filter, &self.filter),
+            (Action::Favorite, &self.favorite),
+            (Action::FavoritesFilter, &self.favorites_filter),
+            (Action::LoopCurrent, &self.LoopC),
+            (Action::SeekForward, &self.seek_forward),
+            (Action::SeekBackward, &self.seek_backward),
+            (Action::BigSeekForward, &self.big_seek_forward),
+            (Action::BigSeekBackward, &self.big_seek_backward),
+            (Action::Help, &self.help),
+            (Action::VisualizerMode, &self.visualizer_mode),
+            (Action::ToggleVisualizer, &self.toggle_visualizer),
+            (Action::Sort, &self.sort),
+            (Action::BrowseMode, &self.browse_mode),
+            (Action::History, &self.history),
+            (Action::ScrollUp, &self.scroll_up),
+            (Action::ScrollDown, &self.scroll_down),
+            (Action::PageUp, &self.page_up),
+            (Action::PageDown, &self.page_down),
+            (Action::First, &self.first),
+            (Action::Last, &self.last),
+            (Action::Mute, &self.mute),
+            (Action::TrimUp, &self.trim_up),
+            (Action::TrimDown, &self.trim_down),
+            (Action::SavePlaylist, &self.save_playlist),
+            (Action::OutputDevice, &self.output_device),
+            (Action::Queue, &self.queue),
+            (Action::MoveUp, &self.move_up),
+            (Action::MoveDown, &self.move_down),
+            (Action::QueueFocus, &self.queue_focus),
+            (Action::Enqueue, &self.enqueue),
+            (Action::RemoveFromQueue, &self.remove_from_queue),
+            (Action::StopAfterCurrent, &self.stop_after_current),
+            (Action::SleepTimer, &self.sleep_timer),
+            (Action::Equalizer, &self.equalizer),
+            (Action::LoopA, &self.loop_a),
+            (Action::LoopB, &self.loop_b),
+            (Action::LoopClear, &self.loop_clear),
+            (Action::SelectCurrent, &self.select_current),
+            (Action::PlayRandom, &self.play_random),
+            (Action::Rescan, &self.rescan),
+            (Action::RefreshTags, &self.refresh_tags),
+            (Action::TrackInfo, &self.track_info),
+        ]
+    }
+}
+
 impl Default for VisualizerConfig {
     fn default() -> Self {
         Self {
+            enabled: true,
             bar_count: 100,
+            bar_width: 1,
+            bar_gap: 0,
             smoothing: 0.7,
+            attack: None,
+            release: None,
+            auto_gain: true,
+            mode: VisualizerMode::default(),
+            orientation: VisualizerOrientation::default(),
+            stereo: false,
+            gradient: Vec::new(),
+            eq: Vec::new(),
+            fps: 20,
         }
     }
 }
 
+impl VisualizerConfig {
+    /// Effective rise smoothing
+    pub fn attack(&self) -> f32 {
+        self.attack.unwrap_or(self.smoothing)
+    }
+
+    /// Effective fall smoothing
+    pub fn release(&self) -> f32 {
+        self.release.unwrap_or(self.smoothing)
+    }
+
+    /// Time between redraws, from `fps` kept within 1 to 60
+    pub fn draw_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.fps.clamp(1, 60)
+    }
+}
+
+/* ---------------------- Config Hot Reload ---------------------- */
+
+/// Polls config.toml for changes so edits apply without a restart
+pub struct ConfigWatcher {
+    path: PathBuf,
+    last_modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl ConfigWatcher {
+    /// How often the file's mtime is checked
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    pub fn new() -> Self {
+        let path = Config::path();
+        let last_modified = Self::modified(&path);
+        Self {
+            path,
+            last_modified,
+            last_check: Instant::now(),
+        }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Returns the reparsed config (or the parse error) when the file changed
+    pub fn poll(&mut self) -> Option<Result<Config, String>> {
+        if self.last_check.elapsed() < Self::POLL_INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+
+        let modified = Self::modified(&self.path);
+        if modified.is_none() || modified == self.last_modified {
+            return None;
+        }
+        self.last_modified = modified;
+
+        Some(Config::read(&self.path))
+    }
+}
+
 /* ---------------------- Config Load and Create ---------------------- */
 
+/// What `Config::load` found on disk
+#[derive(Debug)]
+pub enum ConfigStatus {
+    /// No config.toml existed; defaults were written
+    Missing,
+    /// config.toml parsed fine
+    Valid,
+    /// config.toml couldn't be used; defaults apply until it's fixed
+    Invalid {
+        error: String,
+        backup: Option<PathBuf>,
+    },
+}
+
 impl Config {
-    pub fn load() -> Self {
-        // Expand ~ to home directory
-        let path_str = "~/.config/catty-player/config.toml";
-        let path_expanded = shellexpand::tilde(path_str);
-        let path = Path::new(&*path_expanded);
+    /// Path of the config file: the one set with `--config`, otherwise
+    /// catty-player/config.toml under `$XDG_CONFIG_HOME` or ~/.config
+    pub fn path() -> PathBuf {
+        if let Some(path) = CONFIG_FILE.get() {
+            return path.clone();
+        }
+        // Not dirs::config_dir(), which would move the file elsewhere on macOS
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .unwrap_or_else(|| PathBuf::from(&*shellexpand::tilde("~/.config")));
+        config_home.join("catty-player").join("config.toml")
+    }
+
+    /// Read and write `path` instead of the usual config file; only the first
+    /// call counts
+    pub fn set_path(path: PathBuf) {
+        let _ = CONFIG_FILE.set(path);
+    }
+
+    /// Load config.toml, writing defaults only when the file doesn't exist.
+    /// An unreadable or invalid file is left in place (with a `.bak` copy)
+    /// and defaults are used until it's fixed.
+    pub fn load() -> (Self, ConfigStatus) {
+        let path = Self::path();
 
-        // Try loading existing config
         if path.exists() {
-            if let Ok(content) = fs::read_to_string(path) {
-                if let Ok(cfg) = toml::from_str::<Config>(&content) {
-                    return cfg;
-                } else {
-                    eprintln!(
-                        "config.toml found but invalid. Regenerating with defaults in {}",
-                        path_str
-                    );
+            return match Self::read(&path) {
+                Ok(cfg) => (cfg, ConfigStatus::Valid),
+                Err(error) => {
+                    let backup = path.with_extension("toml.bak");
+                    let backup = fs::copy(&path, &backup).ok().map(|_| backup);
+                    (Config::default(), ConfigStatus::Invalid { error, backup })
                 }
-            } else {
-                eprintln!("Failed to read config.toml. Regenerating with defaults.");
-            }
+            };
         }
 
         // Create default config
@@ -145,15 +781,30 @@ impl Config {
         }
 
         // Write default config (ignore error if it fails)
-        if let Err(e) = fs::write(path, serialized) {
+        if let Err(e) = fs::write(&path, serialized) {
             eprintln!("Failed to write default config.toml: {}", e);
         }
 
-        default
+        (default, ConfigStatus::Missing)
+    }
+
+    /// Read and parse a config file, describing what went wrong on failure
+    fn read(path: &Path) -> Result<Self, String> {
+        let content =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read config.toml: {}", e))?;
+        // toml's message already points at the offending line and column
+        toml::from_str(&content).map_err(|e| format!("Invalid config.toml: {}", e))
     }
 
+    /// Parse a color, falling back to white if it's invalid
     pub fn parse_color(color_str: &str) -> Color {
-        match color_str.to_lowercase().as_str() {
+        Self::try_parse_color(color_str).unwrap_or(Color::White)
+    }
+
+    /// Parse a color name or a `#RGB`, `#RRGGBB` or `#RRGGBBAA` hex string.
+    /// Alpha is blended against black, the usual terminal background.
+    pub fn try_parse_color(color_str: &str) -> Result<Color, String> {
+        let color = match color_str.trim().to_lowercase().as_str() {
             "black" => Color::Black,
             "red" => Color::Red,
             "green" => Color::Green,
@@ -170,18 +821,278 @@ impl Config {
             "lightblue" => Color::LightBlue,
             "lightmagenta" => Color::LightMagenta,
             "lightcyan" => Color::LightCyan,
-            s if s.starts_with('#') && s.len() == 7 => {
-                if let (Ok(r), Ok(g), Ok(b)) = (
-                    u8::from_str_radix(&s[1..3], 16),
-                    u8::from_str_radix(&s[3..5], 16),
-                    u8::from_str_radix(&s[5..7], 16),
-                ) {
-                    Color::Rgb(r, g, b)
-                } else {
-                    Color::White
+            // The terminal's own color
+            "reset" | "default" | "none" => Color::Reset,
+            s => match s.strip_prefix('#').and_then(Self::parse_hex) {
+                Some(color) => color,
+                None => return Err(format!("invalid color \"{}\"", color_str)),
+            },
+        };
+        Ok(color)
+    }
+
+    /// Parse the digits of a hex color (without the leading `#`)
+    fn parse_hex(hex: &str) -> Option<Color> {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |i: usize, width: usize| u8::from_str_radix(&hex[i..i + width], 16).ok();
+
+        let (r, g, b, a) = match hex.len() {
+            // Shorthand: each digit is doubled, `#f80` == `#ff8800`
+            3 => {
+                let short = |i| channel(i, 1).map(|v| v * 17);
+                (short(0)?, short(1)?, short(2)?, 255)
+            }
+            6 => (channel(0, 2)?, channel(2, 2)?, channel(4, 2)?, 255),
+            8 => (
+                channel(0, 2)?,
+                channel(2, 2)?,
+                channel(4, 2)?,
+                channel(6, 2)?,
+            ),
+            _ => return None,
+        };
+
+        let mix = |v: u8| (v as u16 * a as u16 / 255) as u8;
+        Some(Color::Rgb(mix(r), mix(g), mix(b)))
+    }
+
+    /// Describe the first color setting that can't be parsed, if any
+    pub fn color_warning(&self) -> Option<String> {
+        let colors = &self.colors;
+        let fields = [
+            ("colors.foreground", &colors.foreground),
+            ("colors.background", &colors.background),
+            ("colors.accent", &colors.accent),
+            (
+                "colors.visualizer_foreground",
+                &colors.visualizer_foreground,
+            ),
+            (
+                "colors.visualizer_background",
+                &colors.visualizer_background,
+            ),
+            ("colors.selected_foreground", &colors.selected_foreground),
+            ("colors.selected_background", &colors.selected_background),
+            ("colors.playing_foreground", &colors.playing_foreground),
+            ("colors.queued_foreground", &colors.queued_foreground),
+        ];
+        let gradient = self
+            .visualizer
+            .gradient
+            .iter()
+            .map(|stop| ("visualizer.gradient", stop));
+
+        fields
+            .into_iter()
+            .chain(gradient)
+            .find_map(|(name, value)| {
+                Self::try_parse_color(value)
+                    .err()
+                    .map(|e| format!("{}: {}", name, e))
+            })
+    }
+
+    /// Describe every keybind that can't work: keys that don't parse, and
+    /// keys bound to more than one action (only the first one listed runs)
+    pub fn keybind_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut seen: HashMap<KeyBinding, Action> = HashMap::new();
+        for (action, keybind) in self.keybinds.bindings() {
+            match KeyBinding::parse(keybind) {
+                None => warnings.push(format!(
+                    "keybinds.{}: unrecognized key \"{}\"",
+                    action.name(),
+                    keybind
+                )),
+                Some(binding) => match seen.get(&binding) {
+                    Some(first) => warnings.push(format!(
+                        "keybinds.{}: \"{}\" is already bound to {}",
+                        action.name(),
+                        keybind,
+                        first.name()
+                    )),
+                    None => {
+                        seen.insert(binding, action);
+                    }
+                },
+            }
+        }
+        warnings
+    }
+
+    /// Color at position `t` (0.0 to 1.0) along a list of gradient stops
+    pub fn gradient_color(stops: &[Color], t: f32) -> Color {
+        match stops {
+            [] => Color::Reset,
+            [only] => *only,
+            _ => {
+                let pos = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+                let idx = (pos as usize).min(stops.len() - 2);
+                let frac = pos - idx as f32;
+
+                match (Self::to_rgb(stops[idx]), Self::to_rgb(stops[idx + 1])) {
+                    (Some((r1, g1, b1)), Some((r2, g2, b2))) => {
+                        let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * frac) as u8;
+                        Color::Rgb(lerp(r1, r2), lerp(g1, g2), lerp(b1, b2))
+                    }
+                    // Can't blend, use the nearest stop
+                    _ if frac < 0.5 => stops[idx],
+                    _ => stops[idx + 1],
                 }
             }
-            _ => Color::White,
         }
     }
+
+    /// Approximate RGB value of a color (standard xterm palette for named colors)
+    fn to_rgb(color: Color) -> Option<(u8, u8, u8)> {
+        match color {
+            Color::Rgb(r, g, b) => Some((r, g, b)),
+            Color::Black => Some((0, 0, 0)),
+            Color::Red => Some((205, 0, 0)),
+            Color::Green => Some((0, 205, 0)),
+            Color::Yellow => Some((205, 205, 0)),
+            Color::Blue => Some((0, 0, 238)),
+            Color::Magenta => Some((205, 0, 205)),
+            Color::Cyan => Some((0, 205, 205)),
+            Color::Gray => Some((229, 229, 229)),
+            Color::DarkGray => Some((127, 127, 127)),
+            Color::LightRed => Some((255, 0, 0)),
+            Color::LightGreen => Some((0, 255, 0)),
+            Color::LightYellow => Some((255, 255, 0)),
+            Color::LightBlue => Some((92, 92, 255)),
+            Color::LightMagenta => Some((255, 0, 255)),
+            Color::LightCyan => Some((0, 255, 255)),
+            Color::White => Some((255, 255, 255)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_named_colors() {
+        assert_eq!(Config::try_parse_color("cyan"), Ok(Color::Cyan));
+        assert_eq!(Config::try_parse_color("LightBlue"), Ok(Color::LightBlue));
+        assert_eq!(Config::try_parse_color(" grey "), Ok(Color::Gray));
+    }
+
+    #[test]
+    fn parses_six_digit_hex() {
+        assert_eq!(
+            Config::try_parse_color("#ff8800"),
+            Ok(Color::Rgb(255, 136, 0))
+        );
+        assert_eq!(
+            Config::try_parse_color("#FF8800"),
+            Ok(Color::Rgb(255, 136, 0))
+        );
+    }
+
+    #[test]
+    fn parses_three_digit_hex() {
+        assert_eq!(
+            Config::try_parse_color("#fff"),
+            Ok(Color::Rgb(255, 255, 255))
+        );
+        assert_eq!(Config::try_parse_color("#F80"), Ok(Color::Rgb(255, 136, 0)));
+    }
+
+    #[test]
+    fn parses_eight_digit_hex_blending_alpha() {
+        assert_eq!(
+            Config::try_parse_color("#ff8800ff"),
+            Ok(Color::Rgb(255, 136, 0))
+        );
+        assert_eq!(
+            Config::try_parse_color("#ffffff00"),
+            Ok(Color::Rgb(0, 0, 0))
+        );
+        assert_eq!(
+            Config::try_parse_color("#FF000080"),
+            Ok(Color::Rgb(128, 0, 0))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_colors() {
+        for bad in [
+            "#ff", "#ff88", "#ggg", "#ff88001", "ff8800", "#+f8", "purplish", "",
+        ] {
+            assert!(
+                Config::try_parse_color(bad).is_err(),
+                "{:?} should be invalid",
+                bad
+            );
+        }
+        assert_eq!(Config::parse_color("#ggg"), Color::White);
+    }
+
+    #[test]
+    fn highlight_colors_default_to_yellow_and_green() {
+        let colors = ColorConfig::default();
+        assert_eq!(colors.selected_foreground(), Color::Yellow);
+        assert_eq!(colors.selected_background(), Color::Reset);
+        assert_eq!(colors.playing_foreground(), Color::Green);
+        assert_eq!(Config::try_parse_color("Default"), Ok(Color::Reset));
+        assert_eq!(Config::try_parse_color("none"), Ok(Color::Reset));
+    }
+
+    #[test]
+    fn invalid_field_falls_back_to_its_default() {
+        let colors = ColorConfig {
+            accent: "#nope".into(),
+            ..ColorConfig::default()
+        };
+        assert_eq!(colors.accent(), Color::Cyan);
+
+        let config = Config {
+            colors,
+            ..Config::default()
+        };
+        assert_eq!(
+            config.color_warning().as_deref(),
+            Some("colors.accent: invalid color \"#nope\"")
+        );
+        assert_eq!(Config::default().color_warning(), None);
+    }
+
+    #[test]
+    fn keybind_warnings_list_unknown_and_duplicate_keys() {
+        let mut config = Config::default();
+        assert!(config.keybind_warnings().is_empty());
+
+        config.keybinds.mute = "spacebar".into();
+        config.keybinds.history = "N".into();
+        assert_eq!(
+            config.keybind_warnings(),
+            vec![
+                "keybinds.history: \"N\" is already bound to next".to_string(),
+                "keybinds.mute: unrecognized key \"spacebar\"".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn sections_missing_fields_use_their_defaults() {
+        let config: Config = toml::from_str("[album_art]\n[playback]\nautoplay = true\n").unwrap();
+        assert!(!config.album_art.enabled);
+        assert!(config.playback.autoplay);
+        assert_eq!(config.playback.previous_restart_seconds, 3);
+    }
+
+    #[test]
+    fn draw_interval_keeps_fps_in_range() {
+        let mut visualizer = VisualizerConfig::default();
+        assert_eq!(visualizer.draw_interval(), Duration::from_millis(50));
+
+        visualizer.fps = 0;
+        assert_eq!(visualizer.draw_interval(), Duration::from_secs(1));
+        visualizer.fps = 240;
+        assert_eq!(visualizer.draw_interval(), Duration::from_secs(1) / 60);
+    }
 }
@@ -0,0 +1,180 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How long a client gets to send its command before it's dropped
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Longest command line read; anything longer is answered as unknown
+const MAX_LINE: usize = 1024;
+
+/// Commands the control socket accepts, one per line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCommand {
+    Status,
+    Play,
+    Pause,
+    Toggle,
+    Next,
+    Previous,
+}
+
+impl ControlCommand {
+    /// Parse a command line like `"status"` or `"next"`, ignoring case
+    pub fn parse(line: &str) -> Option<Self> {
+        match line.trim().to_lowercase().as_str() {
+            "status" => Some(Self::Status),
+            "play" => Some(Self::Play),
+            "pause" => Some(Self::Pause),
+            "toggle" => Some(Self::Toggle),
+            "next" => Some(Self::Next),
+            "previous" | "prev" => Some(Self::Previous),
+            _ => None,
+        }
+    }
+}
+
+/// A command read from a client, answered once it has been carried out
+pub struct ControlRequest {
+    pub command: Option<ControlCommand>, // None when the line wasn't a known command
+    stream: UnixStream,
+}
+
+impl ControlRequest {
+    /// Send one line back and close the connection
+    pub fn reply(mut self, response: &str) {
+        let _ = writeln!(self.stream, "{}", response);
+    }
+}
+
+/// A connected client whose command line hasn't fully arrived yet
+struct PendingClient {
+    stream: UnixStream,
+    line: Vec<u8>,
+    connected: Instant,
+}
+
+impl PendingClient {
+    /// Read whatever has arrived without waiting; Ok(true) once the line is
+    /// complete, Err when the client should be dropped
+    fn read(&mut self) -> io::Result<bool> {
+        let mut buf = [0; 256];
+        loop {
+            match self.stream.read(&mut buf) {
+                // A client that closes its end without a newline still gets an answer
+                Ok(0) => return Ok(true),
+                Ok(n) => {
+                    self.line.extend_from_slice(&buf[..n]);
+                    if let Some(end) = self.line.iter().position(|&b| b == b'\n') {
+                        self.line.truncate(end);
+                        return Ok(true);
+                    }
+                    if self.line.len() > MAX_LINE {
+                        return Ok(true);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if self.connected.elapsed() > READ_TIMEOUT {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    return Ok(false);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Unix socket that scripts use to query and control playback
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    pending: Vec<PendingClient>, // Partial lines are kept across polls so the UI never waits
+}
+
+impl ControlSocket {
+    /// Listen on `path`, replacing a socket left behind by an instance that crashed
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        // A live instance still answers, and then the bind below fails
+        if path.exists() && UnixStream::connect(path).is_err() {
+            fs::remove_file(path)?;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+            pending: Vec::new(),
+        })
+    }
+
+    /// Commands whose line has arrived since the last poll
+    pub fn poll(&mut self) -> Vec<ControlRequest> {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.pending.push(PendingClient {
+                    stream,
+                    line: Vec::new(),
+                    connected: Instant::now(),
+                });
+            }
+        }
+
+        let mut requests = Vec::new();
+        let mut waiting = Vec::new();
+        for mut client in self.pending.drain(..) {
+            match client.read() {
+                Ok(true) => requests.push(ControlRequest {
+                    command: ControlCommand::parse(&String::from_utf8_lossy(&client.line)),
+                    stream: client.stream,
+                }),
+                Ok(false) => waiting.push(client),
+                Err(_) => {}
+            }
+        }
+        self.pending = waiting;
+        requests
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn commands_split_across_polls_are_put_together() {
+        let path = std::env::temp_dir()
+            .join("catty-player-tests")
+            .join("control-split.sock");
+        let mut socket = ControlSocket::bind(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+
+        client.write_all(b"sta").unwrap();
+        assert!(socket.poll().is_empty());
+        client.write_all(b"tus\n").unwrap();
+        let requests = socket.poll();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].command, Some(ControlCommand::Status));
+
+        for request in requests {
+            request.reply("ok");
+        }
+        let mut reply = String::new();
+        BufReader::new(&client).read_line(&mut reply).unwrap();
+        assert_eq!(reply, "ok\n");
+    }
+}
@@ -1,7 +1,15 @@
+use crate::metadata;
 use anyhow::Result;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use walkdir::WalkDir;
 
 /// Represents a music track with metadata
@@ -10,94 +18,576 @@ pub struct Track {
     pub path: PathBuf,
     pub title: String,
     pub artist: Option<String>,
+    #[serde(default)]
+    pub album: Option<String>,
+    #[serde(default)]
+    pub track_number: Option<u32>,
     pub duration: Option<u64>, // in seconds
+    #[serde(default)]
+    pub modified: Option<u64>, // file mtime, seconds since epoch
+    #[serde(default)]
+    pub size: Option<u64>, // file size in bytes, for spotting untagged duplicates
+    #[serde(default)]
+    pub track_gain: Option<f32>, // ReplayGain in dB
+    #[serde(default)]
+    pub album_gain: Option<f32>,
+    #[serde(default)]
+    scan_version: u32, // SCAN_VERSION the tags were read with
+}
+
+/// Bumped when new tags are read, so cached tracks get rescanned
+const SCAN_VERSION: u32 = 2;
+
+/// Bumped when the layout of the cache file changes
+const CACHE_VERSION: u32 = 4;
+
+/// Furthest a track's volume trim goes either way, in dB
+const MAX_TRIM_DB: f32 = 12.0;
+
+/// Cache directory from the config, used unless `CATTY_CACHE_DIR` is set
+static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    /// Directory holding the cache and Music folders of the test running on
+    /// this thread, so tests never share files or scan a real library
+    static TEST_HOME: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Audio files read between progress updates of a scan
+const SCAN_BATCH: usize = 100;
+
+/// File extensions the built-in decoders can play. rodio handles MP3, FLAC,
+/// Vorbis and WAV itself and hands everything else to symphonia, which adds
+/// AAC and ALAC (in MP4 or ADTS), AIFF and CAF. Opus has no decoder yet.
+const AUDIO_EXTENSIONS: [&str; 11] = [
+    "mp3", "flac", "ogg", "oga", "wav", "m4a", "m4b", "aac", "aif", "aiff", "caf",
+];
+
+/// Copies of a song whose lengths differ by at most this many seconds are
+/// treated as the same recording
+const DUPLICATE_SLACK_SECS: u64 = 2;
+
+/// Extensions of lossless formats, preferred when keeping one of a duplicate set
+const LOSSLESS_EXTENSIONS: [&str; 4] = ["flac", "wav", "aif", "aiff"];
+
+/// Library cache as written to disk
+#[derive(Serialize)]
+struct CacheFile<'a> {
+    version: u32,
+    tracks: Vec<&'a Track>,
+    play_counts: &'a HashMap<PathBuf, u32>,
+    favorites: &'a HashSet<PathBuf>,
+    trims: &'a HashMap<PathBuf, f32>,
+}
+
+/// What a loaded cache holds
+#[derive(Default)]
+struct Cache {
+    tracks: Vec<Track>,
+    play_counts: HashMap<PathBuf, u32>,
+    favorites: HashSet<PathBuf>,
+    trims: HashMap<PathBuf, f32>,
+}
+
+/// Library cache as read back, in either layout
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LoadedCache {
+    Versioned {
+        version: u32,
+        tracks: Vec<Track>,
+        #[serde(default)]
+        play_counts: HashMap<PathBuf, u32>,
+        #[serde(default)]
+        favorites: HashSet<PathBuf>,
+        #[serde(default)]
+        trims: HashMap<PathBuf, f32>,
+    },
+    /// Caches from before versioning are a bare track list, only recognized
+    /// so they're replaced by a rescan
+    #[allow(dead_code)]
+    Legacy(Vec<serde::de::IgnoredAny>),
+}
+
+/// Progress of a background library scan
+pub enum ScanUpdate {
+    /// Audio files seen so far, and the tracks read since the last update
+    Progress { files: usize, tracks: Vec<Track> },
+    /// The complete library
+    Finished(Vec<Track>),
+    /// There's no music directory to scan; the cached library stays as it is
+    MissingDirectory(PathBuf),
+}
+
+impl Track {
+    /// Build a track from a file's tags, titled after the file if untagged.
+    /// Its mtime and size come from `info` when given.
+    pub fn from_tags(path: &Path, tags: metadata::TrackTags, info: Option<&fs::Metadata>) -> Self {
+        let title = tags.title.unwrap_or_else(|| {
+            path.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+
+        Self {
+            path: path.to_path_buf(),
+            title,
+            artist: tags.artist,
+            album: tags.album,
+            track_number: tags.track_number,
+            duration: tags.duration,
+            modified: info.and_then(mtime_secs),
+            size: info.map(fs::Metadata::len),
+            track_gain: tags.track_gain,
+            album_gain: tags.album_gain,
+            scan_version: SCAN_VERSION,
+        }
+    }
+}
+
+/// Field the library list is ordered by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Title,
+    Artist,
+    Duration,
+    DateAdded,
+}
+
+impl SortKey {
+    /// Next key in the cycle
+    pub fn next(self) -> Self {
+        match self {
+            SortKey::Title => SortKey::Artist,
+            SortKey::Artist => SortKey::Duration,
+            SortKey::Duration => SortKey::DateAdded,
+            SortKey::DateAdded => SortKey::Title,
+        }
+    }
+
+    /// Display name for the list title
+    pub fn name(self) -> &'static str {
+        match self {
+            SortKey::Title => "Title",
+            SortKey::Artist => "Artist",
+            SortKey::Duration => "Duration",
+            SortKey::DateAdded => "Date added",
+        }
+    }
+}
+
+/// What to do with extra copies of a song found in the library
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateMode {
+    /// Don't look for duplicates
+    #[default]
+    Off,
+    /// List every copy, marking the extra ones
+    Mark,
+    /// Leave the extra copies out of the library
+    Hide,
+}
+
+/// Which copy of a duplicated song is kept as the original
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePreference {
+    /// The copy whose path sorts first
+    #[default]
+    First,
+    /// A lossless copy (FLAC, WAV, AIFF) over a lossy one, then the first
+    Lossless,
 }
 
 /// Music database with caching support
 pub struct MusicDatabase {
     pub tracks: Vec<Track>,
+    play_counts: HashMap<PathBuf, u32>, // Keyed by path so they outlive rescans
+    favorites: HashSet<PathBuf>,
+    trims: HashMap<PathBuf, f32>, // Volume trims in dB set by hand
     cache_path: PathBuf,
+    extra_extensions: Vec<String>, // From `library.extra_extensions`, lowercase without the dot
+    duplicate_mode: DuplicateMode,
+    duplicate_preference: DuplicatePreference,
+    duplicates: HashSet<PathBuf>, // Extra copies of a song, marked or hidden
+    hidden: Vec<Track>,           // Duplicates left out of `tracks` while hiding them
 }
 
 impl MusicDatabase {
+    /// Directory holding Catty's cache files
+    pub fn cache_dir() -> PathBuf {
+        #[cfg(test)]
+        if let Some(home) = TEST_HOME.with_borrow(Clone::clone) {
+            return home.join("cache");
+        }
+        if let Some(dir) = std::env::var_os("CATTY_CACHE_DIR").filter(|dir| !dir.is_empty()) {
+            return PathBuf::from(dir);
+        }
+        if let Some(dir) = CACHE_DIR.get() {
+            return dir.clone();
+        }
+        dirs::cache_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("catty")
+    }
+
+    /// Keep the cache in `dir` instead of the default; only the first call counts
+    pub fn set_cache_dir(dir: PathBuf) {
+        let _ = CACHE_DIR.set(dir);
+    }
+
+    fn cache_path() -> PathBuf {
+        Self::cache_dir().join("music_cache.json")
+    }
+
+    /// Forget the cached tracks so the next scan reads every file again. Play
+    /// counts, favorites and trims are kept unless the cache can't be read
+    pub fn clear_cache() -> Result<()> {
+        match Self::new() {
+            Ok(mut database) => {
+                database.tracks.clear();
+                database.save_cache()
+            }
+            Err(_) => Ok(fs::remove_file(Self::cache_path())?),
+        }
+    }
+
     /// Create a new database instance
     pub fn new() -> Result<Self> {
-        let cache_dir = dirs::cache_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("catty");
-        
+        let cache_dir = Self::cache_dir();
+
         fs::create_dir_all(&cache_dir)?;
-        let cache_path = cache_dir.join("music_cache.json");
+        let cache_path = Self::cache_path();
 
         // Try to load from cache
-        let tracks = if cache_path.exists() {
+        let cache = if cache_path.exists() {
             Self::load_cache(&cache_path)?
         } else {
-            Vec::new()
+            Cache::default()
         };
 
-        Ok(Self { tracks, cache_path })
-    }
-
-    /// Scan XDG Music directory for audio files
-    pub fn scan_music_directory(&mut self) -> Result<()> {
-        // Get XDG music directory
-        let music_dir = dirs::audio_dir()
-            .or_else(|| dirs::home_dir().map(|h| h.join("Music")))
-            .unwrap_or_else(|| PathBuf::from("."));
-
-        if !music_dir.exists() {
-            eprintln!("Music directory not found: {:?}", music_dir);
-            return Ok(());
-        }
-
-        // Scan for audio files
-        let mut tracks = Vec::new();
-        for entry in WalkDir::new(music_dir)
-            .follow_links(true)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            let path = entry.path();
-            if let Some(ext) = path.extension() {
-                let ext = ext.to_string_lossy().to_lowercase();
-                if ["mp3", "flac", "ogg", "wav", "m4a", "opus"].contains(&ext.as_str()) {
-                    let title = path
-                        .file_stem()
-                        .unwrap_or_default()
-                        .to_string_lossy()
-                        .to_string();
-
-                    tracks.push(Track {
-                        path: path.to_path_buf(),
-                        title,
-                        artist: None,
-                        duration: None,
-                    });
-                }
-            }
+        Ok(Self {
+            tracks: cache.tracks,
+            play_counts: cache.play_counts,
+            favorites: cache.favorites,
+            trims: cache.trims,
+            cache_path,
+            extra_extensions: Vec::new(),
+            duplicate_mode: DuplicateMode::default(),
+            duplicate_preference: DuplicatePreference::default(),
+            duplicates: HashSet::new(),
+            hidden: Vec::new(),
+        })
+    }
+
+    /// Look for duplicates with these settings and mark or hide them
+    pub fn set_duplicates(&mut self, mode: DuplicateMode, preference: DuplicatePreference) {
+        self.duplicate_mode = mode;
+        self.duplicate_preference = preference;
+        self.find_duplicates();
+    }
+
+    /// Whether a track is an extra copy of a song already in the library
+    pub fn is_duplicate(&self, path: &Path) -> bool {
+        self.duplicates.contains(path)
+    }
+
+    /// Number of duplicates left out of the library
+    pub fn hidden_count(&self) -> usize {
+        self.hidden.len()
+    }
+
+    /// Put hidden duplicates back in the library
+    fn restore_hidden(&mut self) {
+        self.tracks.append(&mut self.hidden);
+    }
+
+    /// Recompute which tracks are duplicates, hiding them if asked to
+    fn find_duplicates(&mut self) {
+        self.restore_hidden();
+        self.duplicates.clear();
+        if self.duplicate_mode == DuplicateMode::Off {
+            return;
+        }
+
+        for mut group in duplicate_groups(&self.tracks) {
+            // The preferred copy goes first and is kept
+            group.sort_by_key(|&i| {
+                let track = &self.tracks[i];
+                let lossy = self.duplicate_preference == DuplicatePreference::Lossless
+                    && !is_lossless(&track.path);
+                (lossy, &track.path)
+            });
+            self.duplicates
+                .extend(group[1..].iter().map(|&i| self.tracks[i].path.clone()));
         }
 
+        if self.duplicate_mode == DuplicateMode::Hide {
+            let (hidden, kept) = std::mem::take(&mut self.tracks)
+                .into_iter()
+                .partition(|t| self.duplicates.contains(&t.path));
+            self.tracks = kept;
+            self.hidden = hidden;
+        }
+    }
+
+    /// Scan files with these extensions too. Returns whether the set changed,
+    /// in which case a rescan picks up (or drops) the matching files.
+    pub fn set_extra_extensions(&mut self, extensions: &[String]) -> bool {
+        let extensions: Vec<String> = extensions
+            .iter()
+            .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+            .filter(|e| !e.is_empty())
+            .collect();
+        let changed = extensions != self.extra_extensions;
+        self.extra_extensions = extensions;
+        changed
+    }
+
+    /// Scan the music directory on a background thread. Cached tags are
+    /// reused for unchanged files; the library only changes when the caller
+    /// applies the updates.
+    pub fn start_scan(&self) -> Receiver<ScanUpdate> {
+        let (tx, rx) = mpsc::channel();
+        let cached: HashMap<PathBuf, Track> = self
+            .tracks
+            .iter()
+            .chain(&self.hidden)
+            .map(|t| (t.path.clone(), t.clone()))
+            .collect();
+        let extra = self.extra_extensions.clone();
+        let dir = music_dir();
+
+        thread::spawn(move || scan_music_directory(&dir, cached, &extra, tx));
+        rx
+    }
+
+    /// Read the tags of every track again on a background thread, for files
+    /// retagged without their mtime changing. The music directory isn't
+    /// walked; tracks whose file is gone are dropped.
+    pub fn start_tag_refresh(&self) -> Receiver<ScanUpdate> {
+        let (tx, rx) = mpsc::channel();
+        let paths: Vec<PathBuf> = self
+            .tracks
+            .iter()
+            .chain(&self.hidden)
+            .map(|t| t.path.clone())
+            .collect();
+
+        thread::spawn(move || refresh_tags(paths, tx));
+        rx
+    }
+
+    /// Add tracks found by a running scan that aren't in the library yet
+    pub fn add_tracks(&mut self, tracks: Vec<Track>) {
+        let known: HashSet<PathBuf> = self
+            .tracks
+            .iter()
+            .chain(&self.hidden)
+            .map(|t| t.path.clone())
+            .collect();
+        self.tracks
+            .extend(tracks.into_iter().filter(|t| !known.contains(&t.path)));
+    }
+
+    /// Replace the library with the result of a finished scan and cache it
+    pub fn finish_scan(&mut self, tracks: Vec<Track>) -> Result<()> {
         self.tracks = tracks;
-        self.save_cache()?;
+        self.hidden.clear();
+        self.find_duplicates();
+        self.save_cache()
+    }
 
-        Ok(())
+    /// Bring changed files and directories up to date: audio files that exist
+    /// are (re)read, anything that's gone is dropped. Returns whether the
+    /// library changed; the cache is saved if it did.
+    pub fn apply_changes(&mut self, paths: &[PathBuf]) -> Result<bool> {
+        self.restore_hidden();
+        let before = self.tracks.len();
+        let mut changed = false;
+
+        for path in paths {
+            if path.exists() {
+                // A directory moved in may only report itself
+                for entry in WalkDir::new(path)
+                    .follow_links(true)
+                    .into_iter()
+                    .filter_map(|e| e.ok())
+                {
+                    // Files that haven't changed keep their tags without a re-read
+                    let info = entry.metadata().ok();
+                    let modified = info.as_ref().and_then(mtime_secs);
+                    let existing = self.tracks.iter().position(|t| t.path == entry.path());
+                    if existing.is_some_and(|i| self.tracks[i].modified == modified) {
+                        continue;
+                    }
+                    let Some(track) =
+                        read_track(entry.path(), info.as_ref(), &self.extra_extensions)
+                    else {
+                        continue;
+                    };
+                    match existing {
+                        Some(i) => self.tracks[i] = track,
+                        None => self.tracks.push(track),
+                    }
+                    changed = true;
+                }
+            } else {
+                // Covers both deleted files and deleted directories
+                self.tracks.retain(|t| !t.path.starts_with(path));
+            }
+        }
+
+        changed |= self.tracks.len() != before;
+        self.find_duplicates();
+        if changed {
+            self.save_cache()?;
+        }
+        Ok(changed)
     }
 
-    /// Load tracks from cache
-    fn load_cache(path: &PathBuf) -> Result<Vec<Track>> {
+    /// Load tracks, play counts, favorites and trims from cache
+    fn load_cache(path: &PathBuf) -> Result<Cache> {
         let data = fs::read_to_string(path)?;
-        let tracks: Vec<Track> = serde_json::from_str(&data)?;
-        Ok(tracks)
+        let cache = match serde_json::from_str(&data)? {
+            // Written by a newer version; rescan rather than misread it
+            LoadedCache::Versioned { version, .. } if version > CACHE_VERSION => Cache::default(),
+            // Tracks from an older layout are rescanned; the per-path data
+            // beside them reads the same in every version
+            LoadedCache::Versioned {
+                version,
+                tracks,
+                play_counts,
+                favorites,
+                trims,
+            } => Cache {
+                tracks: if version == CACHE_VERSION {
+                    tracks
+                } else {
+                    Vec::new()
+                },
+                play_counts,
+                favorites,
+                trims,
+            },
+            LoadedCache::Legacy(_) => Cache::default(),
+        };
+        Ok(cache)
     }
 
-    /// Save tracks to cache
+    /// Save tracks, play counts, favorites and trims to cache
     fn save_cache(&self) -> Result<()> {
-        let data = serde_json::to_string(&self.tracks)?;
+        let cache = CacheFile {
+            version: CACHE_VERSION,
+            tracks: self.tracks.iter().chain(&self.hidden).collect(),
+            play_counts: &self.play_counts,
+            favorites: &self.favorites,
+            trims: &self.trims,
+        };
+        let data = serde_json::to_string(&cache)?;
         fs::write(&self.cache_path, data)?;
         Ok(())
     }
 
+    /// Count a play of the track at `path` and save it
+    pub fn record_play(&mut self, path: &Path) -> Result<()> {
+        *self.play_counts.entry(path.to_path_buf()).or_default() += 1;
+        self.save_cache()
+    }
+
+    /// Times the track at `path` has been played
+    pub fn play_count(&self, path: &Path) -> u32 {
+        self.play_counts.get(path).copied().unwrap_or(0)
+    }
+
+    /// Volume trim of the track at `path` in dB, 0.0 if it has none
+    pub fn trim(&self, path: &Path) -> f32 {
+        self.trims.get(path).copied().unwrap_or(0.0)
+    }
+
+    /// Nudge the trim of the track at `path` by `delta` dB, within
+    /// ±MAX_TRIM_DB, and save it. Returns the new trim.
+    pub fn adjust_trim(&mut self, path: &Path, delta: f32) -> Result<f32> {
+        let trim = (self.trim(path) + delta).clamp(-MAX_TRIM_DB, MAX_TRIM_DB);
+        // Steps can leave float dust instead of an exact zero
+        if trim.abs() < 0.01 {
+            self.trims.remove(path);
+        } else {
+            self.trims.insert(path.to_path_buf(), trim);
+        }
+        self.save_cache()?;
+        Ok(self.trim(path))
+    }
+
+    /// Star or unstar the track at `path` and save it, returning whether it's
+    /// now a favorite
+    pub fn toggle_favorite(&mut self, path: &Path) -> Result<bool> {
+        let favorite = !self.favorites.remove(path);
+        if favorite {
+            self.favorites.insert(path.to_path_buf());
+        }
+        self.save_cache()?;
+        Ok(favorite)
+    }
+
+    pub fn is_favorite(&self, path: &Path) -> bool {
+        self.favorites.contains(path)
+    }
+
+    /// Indices of starred tracks, in library order
+    pub fn favorite_indices(&self) -> Vec<usize> {
+        (0..self.tracks.len())
+            .filter(|&i| self.is_favorite(&self.tracks[i].path))
+            .collect()
+    }
+
+    /// Indices of tracks played at least once, most plays first
+    pub fn most_played(&self) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..self.tracks.len())
+            .filter(|&i| self.play_count(&self.tracks[i].path) > 0)
+            .collect();
+        // Stable, so equal counts keep the library order
+        indices.sort_by_key(|&i| Reverse(self.play_count(&self.tracks[i].path)));
+        indices
+    }
+
+    /// Sort the library, ties broken by title
+    pub fn sort(&mut self, key: SortKey, descending: bool) {
+        let by_title = |a: &Track, b: &Track| a.title.to_lowercase().cmp(&b.title.to_lowercase());
+
+        self.tracks.sort_by(|a, b| {
+            let ordering = match key {
+                SortKey::Title => Ordering::Equal,
+                // Tracks without an artist go last
+                SortKey::Artist => match (&a.artist, &b.artist) {
+                    (Some(x), Some(y)) => x.to_lowercase().cmp(&y.to_lowercase()),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                },
+                SortKey::Duration => a.duration.cmp(&b.duration),
+                SortKey::DateAdded => a.modified.cmp(&b.modified),
+            }
+            .then_with(|| by_title(a, b));
+
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+
+    /// Find a track's index by its path
+    pub fn index_of(&self, path: &Path) -> Option<usize> {
+        self.tracks.iter().position(|t| t.path == path)
+    }
+
     /// Get all tracks
     pub fn get_tracks(&self) -> &[Track] {
         &self.tracks
@@ -108,3 +598,288 @@ impl MusicDatabase {
         self.tracks.len()
     }
 }
+
+/// Walk the music directory for audio files, reporting as it goes
+fn scan_music_directory(
+    music_dir: &Path,
+    cached: HashMap<PathBuf, Track>,
+    extra: &[String],
+    tx: Sender<ScanUpdate>,
+) {
+    if !music_dir.exists() {
+        let _ = tx.send(ScanUpdate::MissingDirectory(music_dir.to_path_buf()));
+        return;
+    }
+
+    let mut tracks = Vec::new();
+    let mut reported = 0;
+    for entry in WalkDir::new(music_dir)
+        .follow_links(true)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if !is_audio_file(path, extra) {
+            continue;
+        }
+        let info = entry.metadata().ok();
+        let modified = info.as_ref().and_then(mtime_secs);
+
+        // Reuse cached tags for files that haven't changed since the last scan
+        let track = match cached.get(path) {
+            Some(track)
+                if track.modified.is_some()
+                    && track.modified == modified
+                    && track.scan_version == SCAN_VERSION =>
+            {
+                // Tracks cached before sizes were stored pick theirs up here
+                Track {
+                    size: info.as_ref().map(fs::Metadata::len),
+                    ..track.clone()
+                }
+            }
+            _ => Track::from_tags(path, metadata::read_tags(path), info.as_ref()),
+        };
+        tracks.push(track);
+
+        if tracks.len() - reported >= SCAN_BATCH {
+            let update = ScanUpdate::Progress {
+                files: tracks.len(),
+                tracks: tracks[reported..].to_vec(),
+            };
+            reported = tracks.len();
+            // The player has gone away
+            if tx.send(update).is_err() {
+                return;
+            }
+        }
+    }
+
+    let _ = tx.send(ScanUpdate::Finished(tracks));
+}
+
+/// Indices of tracks that look like copies of the same song, in groups of two
+/// or more. Tagged tracks match on artist, title and length; untagged ones
+/// match on file size, which identical copies share.
+fn duplicate_groups(tracks: &[Track]) -> Vec<Vec<usize>> {
+    let mut by_tags: HashMap<(String, String), Vec<usize>> = HashMap::new();
+    let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, track) in tracks.iter().enumerate() {
+        match (&track.artist, track.duration) {
+            (Some(artist), Some(_)) => by_tags
+                .entry((artist.to_lowercase(), track.title.to_lowercase()))
+                .or_default()
+                .push(i),
+            _ => {
+                if let Some(size) = track.size {
+                    by_size.entry(size).or_default().push(i);
+                }
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for mut same_tags in by_tags.into_values() {
+        // Different recordings of a song (live, remix) differ in length
+        same_tags.sort_by_key(|&i| tracks[i].duration);
+        let mut group: Vec<usize> = Vec::new();
+        for i in same_tags {
+            let close = group.last().is_some_and(|&last| {
+                tracks[i].duration.unwrap_or(0) - tracks[last].duration.unwrap_or(0)
+                    <= DUPLICATE_SLACK_SECS
+            });
+            if !close {
+                groups.push(std::mem::take(&mut group));
+            }
+            group.push(i);
+        }
+        groups.push(group);
+    }
+    groups.extend(by_size.into_values());
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// Whether a file is in a lossless format, judging by its extension
+fn is_lossless(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        LOSSLESS_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str())
+    })
+}
+
+/// Re-read the tags of the given files, reporting progress like a scan
+fn refresh_tags(paths: Vec<PathBuf>, tx: Sender<ScanUpdate>) {
+    let mut tracks = Vec::with_capacity(paths.len());
+    for (i, path) in paths.iter().enumerate() {
+        let Ok(info) = fs::metadata(path) else {
+            continue;
+        };
+        let tags = metadata::read_tags(path);
+        tracks.push(Track::from_tags(path, tags, Some(&info)));
+
+        if (i + 1) % SCAN_BATCH == 0 {
+            let update = ScanUpdate::Progress {
+                files: i + 1,
+                tracks: Vec::new(),
+            };
+            if tx.send(update).is_err() {
+                return;
+            }
+        }
+    }
+
+    let _ = tx.send(ScanUpdate::Finished(tracks));
+}
+
+/// Keep the cache in `dir`/cache and scan `dir`/Music for the rest of the
+/// test running on this thread
+#[cfg(test)]
+pub fn set_test_home(dir: PathBuf) {
+    TEST_HOME.set(Some(dir));
+}
+
+/// The XDG Music directory, falling back to ~/Music
+pub fn music_dir() -> PathBuf {
+    #[cfg(test)]
+    if let Some(home) = TEST_HOME.with_borrow(Clone::clone) {
+        return home.join("Music");
+    }
+    dirs::audio_dir()
+        .or_else(|| dirs::home_dir().map(|h| h.join("Music")))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Whether a file's extension is one Catty can play or one the config adds
+fn is_audio_file(path: &Path, extra: &[String]) -> bool {
+    path.extension().is_some_and(|ext| {
+        let ext = ext.to_string_lossy().to_lowercase();
+        AUDIO_EXTENSIONS.contains(&ext.as_str()) || extra.contains(&ext)
+    })
+}
+
+/// Mtime in seconds since the epoch from a file's metadata
+fn mtime_secs(info: &fs::Metadata) -> Option<u64> {
+    info.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+}
+
+/// Read an audio file's tags into a track (None for non-audio files)
+fn read_track(path: &Path, info: Option<&fs::Metadata>, extra: &[String]) -> Option<Track> {
+    is_audio_file(path, extra).then(|| Track::from_tags(path, metadata::read_tags(path), info))
+}
+
+/// Watches the music directory for added, changed and removed files
+pub struct LibraryWatcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+    pending: HashSet<PathBuf>,
+    last_event: Instant,
+}
+
+impl LibraryWatcher {
+    /// Quiet time before a burst of changes (e.g. copying an album) is applied
+    const DEBOUNCE: Duration = Duration::from_millis(750);
+
+    /// Start watching; None if the directory can't be watched
+    pub fn new(dir: &Path) -> Option<Self> {
+        let (tx, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx).ok()?;
+        watcher.watch(dir, RecursiveMode::Recursive).ok()?;
+
+        Some(Self {
+            _watcher: watcher,
+            events,
+            pending: HashSet::new(),
+            last_event: Instant::now(),
+        })
+    }
+
+    /// Paths that changed, once no new events have come in for a moment
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        for event in self.events.try_iter().flatten() {
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+            self.pending.extend(event.paths);
+            self.last_event = Instant::now();
+        }
+
+        if self.pending.is_empty() || self.last_event.elapsed() < Self::DEBOUNCE {
+            return Vec::new();
+        }
+        self.pending.drain().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::TrackTags;
+
+    /// A library holding just `tracks`, without touching the cache on disk
+    fn database(tracks: Vec<Track>) -> MusicDatabase {
+        MusicDatabase {
+            tracks,
+            play_counts: HashMap::new(),
+            favorites: HashSet::new(),
+            trims: HashMap::new(),
+            cache_path: PathBuf::new(),
+            extra_extensions: Vec::new(),
+            duplicate_mode: DuplicateMode::default(),
+            duplicate_preference: DuplicatePreference::default(),
+            duplicates: HashSet::new(),
+            hidden: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn duplicates_hide_all_but_the_preferred_copy() {
+        let song = |path: &str, title: &str, duration: u64| {
+            let tags = TrackTags {
+                title: Some(title.to_string()),
+                artist: Some("Band".to_string()),
+                duration: Some(duration),
+                ..TrackTags::default()
+            };
+            Track::from_tags(Path::new(path), tags, None)
+        };
+        let mut database = database(vec![
+            song("/music/a/song.mp3", "Song", 200),
+            song("/music/b/song.flac", "song", 201),
+            song("/music/c/song (live).mp3", "Song", 260),
+        ]);
+
+        database.set_duplicates(DuplicateMode::Mark, DuplicatePreference::First);
+        assert!(database.is_duplicate(Path::new("/music/b/song.flac")));
+        assert!(!database.is_duplicate(Path::new("/music/c/song (live).mp3")));
+
+        database.set_duplicates(DuplicateMode::Hide, DuplicatePreference::Lossless);
+        assert_eq!(database.track_count(), 2);
+        assert_eq!(database.hidden_count(), 1);
+        assert!(database.index_of(Path::new("/music/b/song.flac")).is_some());
+
+        // Turning it off brings the hidden copy back
+        database.set_duplicates(DuplicateMode::Off, DuplicatePreference::Lossless);
+        assert_eq!(database.track_count(), 3);
+    }
+
+    #[test]
+    fn untagged_copies_match_by_stored_size() {
+        let file = |path: &str, size: u64| Track {
+            size: Some(size),
+            ..Track::from_tags(Path::new(path), TrackTags::default(), None)
+        };
+        let mut database = database(vec![
+            file("/music/a/track01.mp3", 4_000_000),
+            file("/music/b/track01.mp3", 4_000_000),
+            file("/music/c/track01.mp3", 3_999_999),
+        ]);
+
+        // The files don't exist, so only the sizes kept on the tracks count
+        database.set_duplicates(DuplicateMode::Mark, DuplicatePreference::First);
+        assert!(database.is_duplicate(Path::new("/music/b/track01.mp3")));
+        assert!(!database.is_duplicate(Path::new("/music/c/track01.mp3")));
+    }
+}
@@ -0,0 +1,326 @@
+use rodio::source::SeekError;
+use rodio::Source;
+use std::f32::consts::{PI, SQRT_2};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Centre frequencies of the lowest and highest bands (Hz); bands in
+/// between are spread evenly on a log scale
+const MIN_FREQUENCY: f32 = 60.0;
+const MAX_FREQUENCY: f32 = 12_000.0;
+/// Width of the peaking bands, roughly an octave and a third
+const BAND_Q: f32 = 1.0;
+/// Frames between checks for changed settings
+const REFRESH_FRAMES: usize = 1024;
+
+/// Band gains and the on/off switch, shared with the source that's playing
+#[derive(Default)]
+pub struct EqualizerSettings {
+    enabled: AtomicBool,
+    gains: Mutex<Vec<f32>>,
+    version: AtomicU64, // Bumped whenever the gains change
+}
+
+impl EqualizerSettings {
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Set the band gains in dB, lowest band first
+    pub fn set_gains(&self, gains: &[f32]) {
+        *self.gains.lock().unwrap() = gains.to_vec();
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum BandKind {
+    LowShelf,
+    Peak,
+    HighShelf,
+}
+
+/// Biquad coefficients, normalised so that a0 is 1
+#[derive(Clone, Copy)]
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Coefficients {
+    /// Filter from the Audio EQ Cookbook (shelves use a slope of 1)
+    fn new(kind: BandKind, frequency: f32, gain_db: f32, sample_rate: f32) -> Self {
+        let a = 10f32.powf(gain_db / 40.0);
+        let (sin, cos) = (2.0 * PI * frequency / sample_rate).sin_cos();
+
+        let (b0, b1, b2, a0, a1, a2) = match kind {
+            BandKind::Peak => {
+                let alpha = sin / (2.0 * BAND_Q);
+                (
+                    1.0 + alpha * a,
+                    -2.0 * cos,
+                    1.0 - alpha * a,
+                    1.0 + alpha / a,
+                    -2.0 * cos,
+                    1.0 - alpha / a,
+                )
+            }
+            BandKind::LowShelf => {
+                let k = a.sqrt() * sin * SQRT_2;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + k),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - k),
+                    (a + 1.0) + (a - 1.0) * cos + k,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - k,
+                )
+            }
+            BandKind::HighShelf => {
+                let k = a.sqrt() * sin * SQRT_2;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + k),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - k),
+                    (a + 1.0) - (a - 1.0) * cos + k,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - k,
+                )
+            }
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+/// Memory of one filter on one channel (transposed direct form II)
+#[derive(Clone, Copy, Default)]
+struct FilterState {
+    z1: f32,
+    z2: f32,
+}
+
+impl FilterState {
+    fn process(&mut self, c: &Coefficients, x: f32) -> f32 {
+        let y = c.b0 * x + self.z1;
+        self.z1 = c.b1 * x - c.a1 * y + self.z2;
+        self.z2 = c.b2 * x - c.a2 * y;
+        y
+    }
+}
+
+/// Filters for each band; the outer bands are shelves, flat bands are skipped
+fn design(gains: &[f32], sample_rate: u32) -> Vec<Coefficients> {
+    let last = gains.len().saturating_sub(1);
+    let nyquist = sample_rate as f32 / 2.0;
+
+    gains
+        .iter()
+        .enumerate()
+        .filter(|(_, gain)| gain.abs() >= 0.01)
+        .filter_map(|(i, &gain)| {
+            let position = if last > 0 {
+                i as f32 / last as f32
+            } else {
+                0.5
+            };
+            let frequency = MIN_FREQUENCY * (MAX_FREQUENCY / MIN_FREQUENCY).powf(position);
+            if frequency >= nyquist {
+                return None;
+            }
+
+            let kind = match i {
+                _ if last == 0 => BandKind::Peak,
+                0 => BandKind::LowShelf,
+                i if i == last => BandKind::HighShelf,
+                _ => BandKind::Peak,
+            };
+            Some(Coefficients::new(kind, frequency, gain, sample_rate as f32))
+        })
+        .collect()
+}
+
+/// Source adapter that runs samples through the equalizer bands.
+///
+/// Each track gets its own adapter, so filter memory never carries over
+/// from the previous track.
+pub struct Equalizer<S> {
+    source: S,
+    settings: Arc<EqualizerSettings>,
+    version: u64,
+    channels: usize,
+    sample_rate: u32,
+    filters: Vec<Coefficients>,
+    states: Vec<FilterState>, // One per filter per channel, grouped by channel
+    channel: usize,           // Channel of the next sample
+    frames_until_refresh: usize,
+    was_enabled: bool,
+}
+
+impl<S: Source<Item = f32>> Equalizer<S> {
+    pub fn new(source: S, settings: Arc<EqualizerSettings>) -> Self {
+        let mut equalizer = Self {
+            source,
+            settings,
+            version: 0,
+            channels: 0,
+            sample_rate: 0,
+            filters: Vec::new(),
+            states: Vec::new(),
+            channel: 0,
+            frames_until_refresh: 0,
+            was_enabled: false,
+        };
+        equalizer.refresh();
+        equalizer
+    }
+
+    /// Rebuild the filters if the gains or the stream format changed
+    fn refresh(&mut self) {
+        let version = self.settings.version.load(Ordering::Relaxed);
+        let channels = self.source.channels().max(1) as usize;
+        let sample_rate = self.source.sample_rate();
+        // Channels start at 0, so the first call always builds the filters
+        if version == self.version && channels == self.channels && sample_rate == self.sample_rate {
+            return;
+        }
+
+        self.filters = design(&self.settings.gains.lock().unwrap(), sample_rate);
+        self.version = version;
+        self.channels = channels;
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    /// Clear filter memory, e.g. after a jump in the stream
+    fn reset(&mut self) {
+        self.states = vec![FilterState::default(); self.filters.len() * self.channels];
+        self.channel = 0;
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Equalizer<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.source.next()?;
+
+        if self.channel == 0 {
+            if self.frames_until_refresh == 0 {
+                self.refresh();
+                self.frames_until_refresh = REFRESH_FRAMES;
+            }
+            self.frames_until_refresh -= 1;
+        }
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % self.channels;
+
+        let enabled = self.settings.is_enabled();
+        if enabled != self.was_enabled {
+            // Start from silence rather than stale memory when switched back on
+            self.was_enabled = enabled;
+            self.states.fill(FilterState::default());
+        }
+        if !enabled || self.filters.is_empty() {
+            return Some(sample);
+        }
+
+        let bands = self.filters.len();
+        let states = &mut self.states[channel * bands..(channel + 1) * bands];
+        let out = self
+            .filters
+            .iter()
+            .zip(states)
+            .fold(sample, |x, (filter, state)| state.process(filter, x));
+        Some(out.clamp(-1.0, 1.0))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.source.size_hint()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Equalizer<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.reset();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const SAMPLE_RATE: u32 = 44_100;
+
+    /// One second of a mono sine at `frequency`
+    fn sine(frequency: f32) -> Vec<f32> {
+        (0..SAMPLE_RATE)
+            .map(|i| 0.25 * (2.0 * PI * frequency * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    /// Run `samples` through an enabled equalizer with these band gains
+    fn equalize(samples: &[f32], gains: &[f32]) -> Vec<f32> {
+        let settings = Arc::new(EqualizerSettings::default());
+        settings.set_enabled(true);
+        settings.set_gains(gains);
+        let source = SamplesBuffer::new(1, SAMPLE_RATE, samples.to_vec());
+        Equalizer::new(source, settings).collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn flat_gains_pass_samples_through_unchanged() {
+        let input = sine(440.0);
+        assert_eq!(equalize(&input, &[0.0; 5]), input);
+    }
+
+    #[test]
+    fn boosted_band_raises_a_sine_at_its_centre() {
+        // The middle of three bands sits halfway up the log scale
+        let centre = MIN_FREQUENCY * (MAX_FREQUENCY / MIN_FREQUENCY).sqrt();
+        let input = sine(centre);
+        let output = equalize(&input, &[0.0, 6.0, 0.0]);
+
+        // Past the filter's settling time +6 dB is about double the level
+        let half = input.len() / 2;
+        let ratio = rms(&output[half..]) / rms(&input[half..]);
+        assert!((1.9..2.1).contains(&ratio), "ratio {}", ratio);
+    }
+}
@@ -0,0 +1,460 @@
+use crate::config::KeybindConfig;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+
+/// Modifiers that take part in matching (others, like Super, are ignored)
+const MATCHED_MODIFIERS: KeyModifiers = KeyModifiers::CONTROL
+    .union(KeyModifiers::ALT)
+    .union(KeyModifiers::SHIFT);
+
+/// Everything a keybind can trigger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    PlayPause,
+    Next,
+    Previous,
+    Shuffle,
+    VolumeUp,
+    VolumeDown,
+    FineVolumeUp,
+    FineVolumeDown,
+    Select,
+    Clear,
+    Search,
+    Filter,
+    Favorite,
+    FavoritesFilter,
+    LoopCurrent,
+    SeekForward,
+    SeekBackward,
+    BigSeekForward,
+    BigSeekBackward,
+    Help,
+    VisualizerMode,
+    ToggleVisualizer,
+    Sort,
+    BrowseMode,
+    History,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    First,
+    Last,
+    Mute,
+    TrimUp,
+    TrimDown,
+    SavePlaylist,
+    OutputDevice,
+    Queue,
+    MoveUp,
+    MoveDown,
+    QueueFocus,
+    Enqueue,
+    RemoveFromQueue,
+    StopAfterCurrent,
+    SleepTimer,
+    Equalizer,
+    LoopA,
+    LoopB,
+    LoopClear,
+    SelectCurrent,
+    PlayRandom,
+    Rescan,
+    RefreshTags,
+    TrackInfo,
+}
+
+impl Action {
+    /// Name of the action's setting under `[keybinds]` in config.toml
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Quit => "quit",
+            Self::PlayPause => "play_pause",
+            Self::Next => "next",
+            Self::Previous => "previous",
+            Self::Shuffle => "shuffle",
+            Self::VolumeUp => "volume_up",
+            Self::VolumeDown => "volume_down",
+            Self::FineVolumeUp => "fine_volume_up",
+            Self::FineVolumeDown => "fine_volume_down",
+            Self::Select => "select",
+            Self::Clear => "clear",
+            Self::Search => "search",
+            Self::Filter => "filter",
+            Self::Favorite => "favorite",
+            Self::FavoritesFilter => "favorites_filter",
+            Self::LoopCurrent => "LoopC",
+            Self::SeekForward => "seek_forward",
+            Self::SeekBackward => "seek_backward",
+            Self::BigSeekForward => "big_seek_forward",
+            Self::BigSeekBackward => "big_seek_backward",
+            Self::Help => "help",
+            Self::VisualizerMode => "visualizer_mode",
+            Self::ToggleVisualizer => "toggle_visualizer",
+            Self::Sort => "sort",
+            Self::BrowseMode => "browse_mode",
+            Self::History => "history",
+            Self::ScrollUp => "scroll_up",
+            Self::ScrollDown => "scroll_down",
+            Self::PageUp => "page_up",
+            Self::PageDown => "page_down",
+            Self::First => "first",
+            Self::Last => "last",
+            Self::Mute => "mute",
+            Self::TrimUp => "trim_up",
+            Self::TrimDown => "trim_down",
+            Self::SavePlaylist => "save_playlist",
+            Self::OutputDevice => "output_device",
+            Self::Queue => "queue",
+            Self::MoveUp => "move_up",
+            Self::MoveDown => "move_down",
+            Self::QueueFocus => "queue_focus",
+            Self::Enqueue => "enqueue",
+            Self::RemoveFromQueue => "remove_from_queue",
+            Self::StopAfterCurrent => "stop_after_current",
+            Self::SleepTimer => "sleep_timer",
+            Self::Equalizer => "equalizer",
+            Self::LoopA => "loop_a",
+            Self::LoopB => "loop_b",
+            Self::LoopClear => "loop_clear",
+            Self::SelectCurrent => "select_current",
+            Self::PlayRandom => "play_random",
+            Self::Rescan => "rescan",
+            Self::RefreshTags => "refresh_tags",
+            Self::TrackInfo => "track_info",
+        }
+    }
+
+    /// What the action does, as listed in the help overlay
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Quit => "Quit Application",
+            Self::PlayPause => "Toggle Play/Pause",
+            Self::Next => "Next Track",
+            Self::Previous => "Restart or Previous Track",
+            Self::Shuffle => "Toggle Shuffle",
+            Self::VolumeUp => "Increase Volume",
+            Self::VolumeDown => "Decrease Volume",
+            Self::FineVolumeUp => "Increase Volume Slightly",
+            Self::FineVolumeDown => "Decrease Volume Slightly",
+            Self::Select => "Play Selected Track",
+            Self::Clear => "Clear Queue",
+            Self::Search => "Search and Play (Alt+Enter: Queue All Matches)",
+            Self::Filter => "Filter Track List (Esc: Clear Filter)",
+            Self::Favorite => "Star Selected Track",
+            Self::FavoritesFilter => "Show Only Favorites",
+            Self::LoopCurrent => "Toggle Loop",
+            Self::SeekForward => "Seek Forward",
+            Self::SeekBackward => "Seek Backward",
+            Self::BigSeekForward => "Seek Forward",
+            Self::BigSeekBackward => "Seek Backward",
+            Self::Help => "Toggle Help",
+            Self::VisualizerMode => "Cycle Visualizer Mode",
+            Self::ToggleVisualizer => "Show or Hide Visualizer",
+            Self::Sort => "Cycle Sort Order",
+            Self::BrowseMode => "Cycle List/Artist Tree/Most Played",
+            Self::History => "Recently Played",
+            Self::ScrollUp => "Move Up",
+            Self::ScrollDown => "Move Down",
+            Self::PageUp => "Page Up",
+            Self::PageDown => "Page Down",
+            Self::First => "Go to Top",
+            Self::Last => "Go to Bottom",
+            Self::Mute => "Mute/Unmute",
+            Self::TrimUp => "Make This Track Louder (Remembered)",
+            Self::TrimDown => "Make This Track Quieter (Remembered)",
+            Self::SavePlaylist => "Save Queue as Playlist",
+            Self::OutputDevice => "Switch Output Device",
+            Self::Queue => "Show Queue",
+            Self::MoveUp => "Move Queued Track Up",
+            Self::MoveDown => "Move Queued Track Down",
+            Self::QueueFocus => "Switch Between Library and Queue",
+            Self::Enqueue => "Add to Queue",
+            Self::RemoveFromQueue => "Remove from Queue",
+            Self::StopAfterCurrent => "Stop After Current Track",
+            Self::SleepTimer => "Sleep Timer",
+            Self::Equalizer => "Toggle Equalizer",
+            Self::LoopA => "Set Loop Start",
+            Self::LoopB => "Set Loop End",
+            Self::LoopClear => "Clear Loop",
+            Self::SelectCurrent => "Jump to Playing Track",
+            Self::PlayRandom => "Play a Random Track",
+            Self::Rescan => "Rescan the Music Directory",
+            Self::RefreshTags => "Re-read All Tags",
+            Self::TrackInfo => "Track Format and Tags",
+        }
+    }
+}
+
+/// A key plus the modifiers that must be held with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyBinding {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    /// Parse a keybind string like `"q"`, `"space"`, `"pageup"`, `"f5"`, `"ctrl+q"` or `"shift+tab"`
+    pub fn parse(keybind: &str) -> Option<Self> {
+        let mut rest = keybind.trim();
+        let mut modifiers = KeyModifiers::NONE;
+
+        // Leading "name+" parts are modifiers; whatever is left is the key,
+        // so "+" and "ctrl++" still bind the plus key
+        while let Some((name, key)) = rest.split_once('+') {
+            let modifier = match name.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => break,
+            };
+            modifiers |= modifier;
+            rest = key;
+        }
+
+        let name = rest.to_lowercase();
+        let code = match name.as_str() {
+            "space" => KeyCode::Char(' '),
+            "enter" | "return" => KeyCode::Enter,
+            // Terminals report Shift+Tab as a separate key
+            "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "esc" | "escape" => KeyCode::Esc,
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" | "ins" => KeyCode::Insert,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" | "pgup" => KeyCode::PageUp,
+            "pagedown" | "pgdn" => KeyCode::PageDown,
+            key => {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    // Function keys: "f1" to "f24"
+                    _ => match key.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                        Some(n @ 1..=24) => KeyCode::F(n),
+                        _ => return None,
+                    },
+                }
+            }
+        };
+
+        Some(Self { code, modifiers })
+    }
+}
+
+/// Lookup table from key presses to the actions bound to them
+#[derive(Debug, Default)]
+pub struct Keymap {
+    bindings: HashMap<KeyBinding, Action>,
+}
+
+impl Keymap {
+    /// Build the table from the configured keybinds; keys that don't parse are
+    /// skipped, and a key bound twice goes to the action listed first
+    pub fn new(keybinds: &KeybindConfig) -> Self {
+        let mut bindings = HashMap::new();
+        for (action, keybind) in keybinds.bindings() {
+            if let Some(binding) = KeyBinding::parse(keybind) {
+                bindings.entry(binding).or_insert(action);
+            }
+        }
+        Self { bindings }
+    }
+
+    /// Action a key press triggers, if any
+    pub fn action(&self, key: &KeyEvent) -> Option<Action> {
+        let modifiers = key.modifiers & MATCHED_MODIFIERS;
+        match key.code {
+            KeyCode::Char(c) => {
+                // Shift is already part of the typed character, so a plain
+                // binding matches shifted letters too unless a binding asks
+                // for shift explicitly
+                let code = KeyCode::Char(c.to_lowercase().next().unwrap_or(c));
+                self.get(code, modifiers)
+                    .or_else(|| self.get(code, modifiers.difference(KeyModifiers::SHIFT)))
+            }
+            code => self.get(code, modifiers),
+        }
+    }
+
+    fn get(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<Action> {
+        self.bindings.get(&KeyBinding { code, modifiers }).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(code: KeyCode, modifiers: KeyModifiers) -> Option<KeyBinding> {
+        Some(KeyBinding { code, modifiers })
+    }
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> Option<Action> {
+        Keymap::new(&KeybindConfig::default()).action(&KeyEvent::new(code, modifiers))
+    }
+
+    #[test]
+    fn plus_is_a_key_after_the_modifiers() {
+        let plus = KeyCode::Char('+');
+        assert_eq!(KeyBinding::parse("+"), binding(plus, KeyModifiers::NONE));
+        assert_eq!(
+            KeyBinding::parse("ctrl++"),
+            binding(plus, KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            KeyBinding::parse("Ctrl+Alt++"),
+            binding(plus, KeyModifiers::CONTROL | KeyModifiers::ALT)
+        );
+    }
+
+    #[test]
+    fn shift_tab_becomes_backtab() {
+        assert_eq!(
+            KeyBinding::parse("shift+tab").map(|b| b.code),
+            Some(KeyCode::BackTab)
+        );
+
+        let keybinds = KeybindConfig {
+            queue_focus: "shift+tab".into(),
+            ..KeybindConfig::default()
+        };
+        let keymap = Keymap::new(&keybinds);
+        let back_tab = KeyEvent::new(KeyCode::BackTab, KeyModifiers::SHIFT);
+        assert_eq!(keymap.action(&back_tab), Some(Action::QueueFocus));
+        assert_eq!(keymap.action(&KeyEvent::from(KeyCode::Tab)), None);
+    }
+
+    #[test]
+    fn letters_are_case_folded() {
+        assert_eq!(KeyBinding::parse("N"), KeyBinding::parse("n"));
+        assert_eq!(
+            press(KeyCode::Char('n'), KeyModifiers::NONE),
+            Some(Action::Next)
+        );
+        // Some terminals send the capital letter without the shift modifier
+        assert_eq!(
+            press(KeyCode::Char('N'), KeyModifiers::NONE),
+            Some(Action::Next)
+        );
+    }
+
+    #[test]
+    fn shift_is_dropped_when_nothing_asks_for_it() {
+        assert_eq!(
+            press(KeyCode::Char('N'), KeyModifiers::SHIFT),
+            Some(Action::Next)
+        );
+        assert_eq!(
+            press(KeyCode::Char('?'), KeyModifiers::SHIFT),
+            Some(Action::Help)
+        );
+        assert_eq!(
+            press(KeyCode::Char('+'), KeyModifiers::ALT | KeyModifiers::SHIFT),
+            Some(Action::FineVolumeUp)
+        );
+        // Only shift is dropped, never the other modifiers
+        assert_eq!(press(KeyCode::Char('n'), KeyModifiers::CONTROL), None);
+        // Named keys keep shift, so Shift+Up isn't plain Up
+        assert_eq!(
+            press(KeyCode::Up, KeyModifiers::SHIFT),
+            Some(Action::MoveUp)
+        );
+    }
+
+    #[test]
+    fn shift_binding_wins_over_the_plain_one() {
+        assert_eq!(
+            press(KeyCode::Char('V'), KeyModifiers::SHIFT),
+            Some(Action::ToggleVisualizer)
+        );
+        assert_eq!(
+            press(KeyCode::Char('v'), KeyModifiers::NONE),
+            Some(Action::VisualizerMode)
+        );
+    }
+
+    #[test]
+    fn named_keys_parse() {
+        let named = [
+            ("space", KeyCode::Char(' ')),
+            ("Enter", KeyCode::Enter),
+            ("return", KeyCode::Enter),
+            ("tab", KeyCode::Tab),
+            ("backtab", KeyCode::BackTab),
+            ("esc", KeyCode::Esc),
+            ("escape", KeyCode::Esc),
+            ("backspace", KeyCode::Backspace),
+            ("delete", KeyCode::Delete),
+            ("insert", KeyCode::Insert),
+            ("up", KeyCode::Up),
+            ("down", KeyCode::Down),
+            ("left", KeyCode::Left),
+            ("right", KeyCode::Right),
+            ("home", KeyCode::Home),
+            ("END", KeyCode::End),
+            ("pageup", KeyCode::PageUp),
+            ("pagedown", KeyCode::PageDown),
+        ];
+        for (name, code) in named {
+            assert_eq!(
+                KeyBinding::parse(name),
+                binding(code, KeyModifiers::NONE),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(KeyBinding::parse("spacebar"), None);
+        assert_eq!(KeyBinding::parse(""), None);
+    }
+
+    #[test]
+    fn function_keys_run_from_f1_to_f24() {
+        for n in 1..=24 {
+            let name = format!("f{}", n);
+            assert_eq!(
+                KeyBinding::parse(&name),
+                binding(KeyCode::F(n), KeyModifiers::NONE)
+            );
+        }
+        assert_eq!(
+            KeyBinding::parse("shift+F5"),
+            binding(KeyCode::F(5), KeyModifiers::SHIFT)
+        );
+        assert_eq!(KeyBinding::parse("f0"), None);
+        assert_eq!(KeyBinding::parse("f25"), None);
+        // A lone "f" is still the letter
+        assert_eq!(
+            KeyBinding::parse("f"),
+            binding(KeyCode::Char('f'), KeyModifiers::NONE)
+        );
+    }
+
+    #[test]
+    fn short_aliases_match_the_full_names() {
+        let aliases = [
+            ("pgup", "pageup"),
+            ("pgdn", "pagedown"),
+            ("del", "delete"),
+            ("ins", "insert"),
+            ("control+q", "ctrl+q"),
+        ];
+        for (alias, name) in aliases {
+            assert_eq!(
+                KeyBinding::parse(alias),
+                KeyBinding::parse(name),
+                "{}",
+                alias
+            );
+        }
+    }
+}
@@ -4,8 +4,11 @@
 //!
 //! ## Configuration
 //!
-//! Catty reads a `config.toml` file that is `~/.config/catty-player/config.toml`.
-//! If the file is missing or invalid, Catty will create one with default values.
+//! Catty reads a `config.toml` file that is `~/.config/catty-player/config.toml`
+//! (under `$XDG_CONFIG_HOME` instead of `~/.config` when that is set).
+//! If the file is missing, Catty creates one with default values. An invalid file
+//! is kept as it is: Catty copies it to `config.toml.bak`, shows the error in a
+//! dialog and runs with defaults until it's fixed.
 //!
 //! ### Example `config.toml`
 //!
@@ -16,6 +19,10 @@
 //! accent = "cyan"
 //! visualizer_foreground = "LightBlue"
 //! visualizer_background = "black"
+//! selected_foreground = "yellow" # highlighted row in lists
+//! selected_background = "reset" # "reset" keeps the terminal's background
+//! playing_foreground = "green" # the track that's playing
+//! queued_foreground = "magenta" # library tracks added to the queue
 //!
 //! [keybinds]
 //! quit = "q"
@@ -23,123 +30,392 @@
 //! next = "n"
 //! previous = "p"
 //! shuffle = "s"
+//! stop_after_current = "z" # stop when the current track ends
+//! equalizer = "e" # switch the equalizer on and off
+//! sleep_timer = "ctrl+t" # minutes until playback stops, "t" for end of track, empty to cancel
+//! loop_a = "[" # mark the start of a section to repeat
+//! loop_b = "]" # mark its end; playback jumps back to A from here
+//! loop_clear = "\\"
 //! volume_up = "+"
 //! volume_down = "-"
+//! fine_volume_up = "alt++" # change the volume by fine_volume_step for precise leveling
+//! fine_volume_down = "alt+-"
+//! mute = "m"
+//! trim_up = ">" # make the playing track louder by 1 dB, remembered for that file
+//! trim_down = "<"
+//! save_playlist = "w"
+//! output_device = "o" # cycle through output devices
+//! queue = "u" # show the queue panel beside the library
+//! queue_focus = "tab" # switch keys between the library and the queue
+//! enqueue = "a" # add the selected track, album or artist to the queue
+//! remove_from_queue = "x"
+//! move_up = "shift+up" # reorder the selected queue entry
+//! move_down = "shift+down"
 //! select = "enter"
 //! clear = "c"
 //! seek_forward = "l"
 //! seek_backward = "h"
+//! big_seek_forward = "shift+f" # seek by big_seek_seconds
+//! big_seek_backward = "shift+h"
 //! help = "?"
+//! search = "/" # play the best match on enter, or queue every match with alt+enter
+//! filter = "ctrl+f" # like search, but enter keeps the matches listed to browse
+//! favorite = "*" # star or unstar the selected track
+//! favorites_filter = "ctrl+s" # list only starred tracks
+//! visualizer_mode = "v"
+//! toggle_visualizer = "shift+v" # hide the visualizer (and cover art) to give the list more room
+//! sort = "t"
+//! browse_mode = "b" # flat list, artist/album tree or most played
+//! history = "y"
+//! scroll_up = "up"
+//! scroll_down = "down"
+//! page_up = "pageup"
+//! page_down = "pagedown"
+//! first = "home" # jump to the top of the list
+//! last = "end"
+//! select_current = "g" # jump to the playing track
+//! play_random = "r" # play a random track from the library, leaving shuffle as it is
+//! rescan = "shift+r" # scan the music directory again for added or removed files
+//! refresh_tags = "ctrl+r" # read every track's tags again, e.g. after retagging
+//! track_info = "i" # codec, sample rate, path and tags of the playing track
 //!
 //! [visualizer]
-//! bar_count = 50
-//! smoothing = 0.7
+//! enabled = true # false hides the panel; a toggle_visualizer flip is kept in state.json
+//! bar_count = 50 # at most 1024, one bar per frequency bin of the 2048-sample FFT
+//! bar_width = 1 # columns per bar (rows when horizontal)
+//! bar_gap = 0 # blank columns between bars
+//! smoothing = 0.7 # share of the old bar height kept every 50 ms, the same at any fps
+//! attack = 0.2 # optional, smoothing while bars rise (defaults to smoothing)
+//! release = 0.85 # optional, smoothing while bars fall (defaults to smoothing)
+//! auto_gain = true # scale bars to the track's loudness; false uses a fixed level
+//! mode = "spectrum" # spectrum, waveform, mirror or spectrogram
+//! orientation = "vertical" # or horizontal: one bar per row, growing left to right
+//! stereo = false # true splits the bars into left and right channel halves
+//! gradient = ["green", "yellow", "#FF0000"] # optional, colors bars from quiet to loud
+//! eq = [1.0, 1.0, 1.5, 2.0] # optional, bar multipliers from bass to treble, e.g. to lift the highs
+//! fps = 20 # redraws per second, 1 to 60; lower saves CPU
 //! [watermark]
 //! water_mark = true /false #toggles samsit-phew mark on help section
 //!
+//! [library]
+//! sort_key = "title" # title, artist, duration or date_added; a cycled order is kept in state.json
+//! sort_descending = false
+//! wrap_around = false # up at the top goes to the bottom and vice versa
+//! extra_extensions = [] # more file types to list, e.g. ["mp2"]; ones Catty can't decode show as unsupported
+//! cache_dir = "~/.cache/catty-work" # optional, where the cache lives; CATTY_CACHE_DIR takes precedence
+//! duplicates = "off" # off, mark (≈ before extra copies) or hide; matches artist, title and length
+//! duplicate_preference = "first" # first (path order) or lossless (FLAC/WAV/AIFF over lossy copies)
+//!
+//! [history]
+//! max_entries = 100 # recently played tracks kept in ~/.cache/catty/history.json
+//!
+//! [symbols]
+//! playing = "▶" # e.g. ">" and "||" on fonts without these glyphs
+//! paused = "⏸"
+//! selected = "" # before the highlighted row, e.g. "*"
+//! unplayable = "✗"
+//! duplicate = "≈"
+//! favorite = "★"
+//! bar = "█" # visualizer bars; another character also drops the partial blocks
+//! watermark = "󰎆" # nerd font icon in the help overlay
+//! ascii_mode = true # optional, ASCII stand-ins for glyphs and emoji; on by default on the Linux console
+//!
+//! [album_art]
+//! enabled = false # show the cover of the playing track (kitty graphics protocol)
+//!
+//! [audio]
+//! replaygain = "track" # track, album or off; evens out loudness using ReplayGain tags
+//! output_device = "USB Audio DAC" # optional, defaults to the system output
+//! seek_seconds = 10 # step of seek_forward and seek_backward
+//! big_seek_seconds = 60 # step of the big seek keys
+//! initial_volume = 0.2 # volume on the first launch; after that the last volume is restored
+//! volume_step = 0.05 # change of volume_up and volume_down
+//! fine_volume_step = 0.01 # change of the fine volume keys
+//! show_system_volume = false # also show the PulseAudio output volume (checked twice a second)
+//!
+//! [audio.equalizer]
+//! enabled = false
+//! bands = [3.0, 0.0, 0.0, 0.0, -2.0] # dB from bass to treble, any number of bands
+//!
+//! [playlist]
+//! save_path = "~/Music/Playlists/catty-queue.m3u8" # where save_playlist writes the queue
+//!
+//! [playback]
+//! autoplay = false # start playing on launch: the --playlist queue, else the last played track
+//! previous_restart_seconds = 3 # previous restarts a track played longer than this; 0 always goes back
+//!
+//! [control]
+//! socket = "~/.cache/catty/control.sock" # optional, off unless set; lets scripts query and control playback
+//!
+//! [notifications]
+//! enabled = false # desktop notification on track change, needs notify-send
+//! cover_art = false # show the embedded cover as the notification icon
+//!
 //!
 //! ```
 //!
 //! ### Editing the Configuration
 //!
-//! - **Colors**: Use standard color names or hex codes (e.g., `"red"` or `"#FF0000"`).  
-//! - **Keybinds**: Use strings like `"space"`, `"enter"`, `"q"`.  
-//! - **Visualizer**: Adjust `bar_count` and `smoothing`.  
+//! - **Colors**: Use standard color names or hex codes (`"red"`, `"#F00"`, `"#FF0000"`, or `"#FF000080"` with alpha).
+//!   `"default"`, `"reset"` or `"none"` use the terminal's own color, which suits transparent or themed terminals.  
+//! - **Keybinds**: Use strings like `"space"`, `"enter"`, `"q"`, optionally with modifiers
+//!   (`"ctrl+q"`, `"alt+n"`, `"shift+tab"`). A plain key doesn't fire while Ctrl or Alt is held.
+//!   Non-character keys are named `"up"`, `"down"`, `"left"`, `"right"`, `"pageup"`, `"pagedown"`,
+//!   `"home"`, `"end"`, `"tab"`, `"esc"`, `"backspace"`, `"delete"`, `"insert"` and `"f1"`-`"f24"`.
+//!   Unrecognized keys and keys bound to two actions are listed in a warning at startup;
+//!   a key bound twice goes to the action listed first in `[keybinds]`.
+//!   Left/Right seek by `seek_seconds` unless bound to something else, except in the artist tree
+//!   where they collapse and expand.
+//!   Digits not bound to an action type a count, like in vi: `5` then `scroll_down` moves five
+//!   rows, and `3` then `seek_forward` seeks three steps. Esc drops a typed count.  
+//! - **Visualizer**: Adjust `bar_count` and `smoothing` (or `attack`/`release` separately), and pick the starting `mode`.
+//!   Set `gradient` to color bars by amplitude instead of using `visualizer_foreground`.
+//!   The spectrogram shades each cell along the `gradient`, or from `visualizer_background` to
+//!   `visualizer_foreground` without one.
+//!   On wide but short terminals, `orientation = "horizontal"` fits the bars to the rows instead.
+//!   With `stereo = true` the bar modes show the left channel above the middle (or left of it
+//!   when horizontal) and the right channel below it; mono files show the same bars on both sides.  
+//! - **Symbols**: The markers in the lists and the visualizer's bar character can be changed under
+//!   `[symbols]`. `ascii_mode` swaps the default glyphs and emoji for plain ASCII (`>`, `||`, `#`, ...)
+//!   for terminals that show them as boxes; it is on for the Linux console unless set.  
+//! - **Album art**: Set `enabled = true` to show embedded covers next to the visualizer.
+//!   Terminals without the kitty graphics protocol get a text placeholder instead.  
+//! - **Notifications**: Set `enabled = true` under `[notifications]` to get a desktop notification
+//!   with the title and artist of each new track. It waits a second after a skip, so skipping
+//!   through several tracks only announces the one that stays.  
+//! - **Control socket**: With `socket` set under `[control]`, Catty listens there for one command
+//!   per connection (`status`, `play`, `pause`, `toggle`, `next` or `previous`) and answers with
+//!   the state, track, position and volume as JSON, e.g.
+//!   `echo status | socat - UNIX-CONNECT:$HOME/.cache/catty/control.sock`.  
 //!
 //! ### Usage
 //!
 //! ```bash
 //! # Run the player
 //! catty-player
+//!
+//! # Start with an .m3u/.m3u8 playlist as the queue
+//! catty-player --playlist ~/Music/Playlists/road-trip.m3u8
+//!
+//! # Forget the cached library and read every file again
+//! catty-player --clear-cache
+//!
+//! # Use another config file, e.g. to try out a setup
+//! catty-player --config ~/catty-test.toml
+//!
+//! # Play without the terminal UI, controlled through the [control] socket
+//! catty-player --headless --playlist ~/Music/Playlists/road-trip.m3u8
 //! ```
 //! The program will automatically load `config.toml` or generate defaults if missing.
+//! Edits to the file are picked up while Catty is running; an invalid file is reported
+//! in the title bar and the previous settings stay active.
+//! If `config.toml` can't be parsed at startup, Catty shows the error, keeps a copy of
+//! the file as `config.toml.bak` and runs with defaults without overwriting it.
 
+mod album_art;
 mod audio;
+mod browse;
 mod config;
+mod control;
 mod database;
+mod equalizer;
+mod keybind;
+mod metadata;
+mod notification;
 mod player;
+mod playlist;
 mod ui;
 mod visualizer;
 
 use anyhow::Result;
-use config::Config;
+use config::{Config, ConfigStatus, ConfigWatcher};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
+    cursor,
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{backend::CrosstermBackend, Terminal};
 use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
+use album_art::AlbumArt;
 use audio::AudioPlayer;
 use database::MusicDatabase;
+use keybind::Action;
 use player::PlayerState;
 use ui::UI;
 
+/// Longest wait for input while idle, so config and library changes are still seen
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Check interval while playing headless, so A-B loops and track ends are caught on time
+const HEADLESS_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Redraw interval while idle, enough to let notices expire on time
+const IDLE_REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+/// Step of the track trim keys, in dB
+const TRIM_STEP_DB: f32 = 1.0;
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let playlist = path_arg("--playlist", "-p");
+
     // Load configuration
-    let config = Config::load();
+    if let Some(path) = path_arg("--config", "-c") {
+        Config::set_path(path);
+    }
+    let (config, config_status) = Config::load();
 
-    // Initialize database and scan music
-    let mut database = MusicDatabase::new()?;
-    database.scan_music_directory()?;
+    // Load the cached library; PlayerState rescans it in the background
+    if let Some(dir) = &config.library.cache_dir {
+        MusicDatabase::set_cache_dir(PathBuf::from(&*shellexpand::tilde(dir)));
+    }
+    if has_flag("--clear-cache") {
+        MusicDatabase::clear_cache()?;
+    }
+    let database = MusicDatabase::new()?;
 
-    // Initialize audio player
-    let audio_player = AudioPlayer::new()?;
+    // Initialize audio player (falls back to a silent mode without an output device)
+    let audio_player = AudioPlayer::new(config.audio.output_device.as_deref());
 
     // Initialize player state
-    let mut player_state = PlayerState::new(database, audio_player, config.clone());
+    let mut player_state = PlayerState::new(database, Box::new(audio_player), config);
+    if let ConfigStatus::Invalid { error, backup } = config_status {
+        let mut message = format!(
+            "{}\n\nUsing default settings until the file is fixed.",
+            error
+        );
+        if let Some(backup) = backup {
+            message.push_str(&format!("\nA copy was saved to {}.", backup.display()));
+        }
+        player_state.config_error = Some(error);
+        player_state.config_dialog = Some(message);
+    }
+    if let Some(path) = playlist {
+        player_state.load_playlist(&path)?;
+    }
+    let mut config_watcher = ConfigWatcher::new();
+
+    // Ctrl-C from outside the terminal, SIGTERM and SIGHUP end the main loop
+    // so the session is saved on the way out
+    let (shutdown_tx, mut shutdown_rx) = mpsc::channel(1);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = shutdown_tx.send(()).await;
+    });
 
-    // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
+    if has_flag("--headless") {
+        return run_headless(player_state, config_watcher, shutdown_rx).await;
+    }
+
+    // Setup terminal; the guard puts it back however we leave main
+    let terminal_guard = TerminalGuard::new()?;
+    let backend = CrosstermBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
 
     // Create async event channel
     let (tx, mut rx) = mpsc::channel(100);
 
-    // Spawn event listener
-    tokio::spawn(async move {
-        loop {
-            if event::poll(Duration::from_millis(100)).unwrap() {
-                if let Ok(evt) = event::read() {
-                    if tx.send(evt).await.is_err() {
-                        break;
+    // Spawn event listener. Polling blocks, so it gets a plain thread rather
+    // than a runtime worker; the runtime would also wait on it at exit
+    std::thread::spawn(move || {
+        while !tx.is_closed() {
+            match event::poll(Duration::from_millis(100)) {
+                Ok(true) => match event::read() {
+                    Ok(evt) => {
+                        if tx.blocking_send(evt).is_err() {
+                            break;
+                        }
                     }
-                }
+                    Err(_) => break,
+                },
+                Ok(false) => {}
+                Err(_) => break,
             }
         }
     });
 
     // Main loop
     let mut last_draw = Instant::now();
-    let draw_interval = Duration::from_millis(50); // 20 FPS for smooth visualization
 
     loop {
-        // Update visualizer data
-        player_state.update_visualizer();
+        if shutdown_rx.try_recv().is_ok() {
+            break;
+        }
+
+        update_playback(&mut player_state, &mut config_watcher);
+
+        // While paused or stopped nothing animates, so skip the visualizer and
+        // only redraw on changes, waking for input or the occasional check
+        let idle = player_state.is_idle();
+        if !idle {
+            player_state.update_visualizer();
+        }
 
         // Optimized redraw - only when needed
+        let draw_interval = if idle {
+            IDLE_REDRAW_INTERVAL
+        } else {
+            player_state.config.visualizer.draw_interval()
+        };
         let needs_redraw = player_state.needs_redraw() || last_draw.elapsed() >= draw_interval;
 
         if needs_redraw {
             terminal.draw(|f| {
                 UI::render(f, &mut player_state);
             })?;
+            player_state.album_art.flush(terminal.backend_mut())?;
             last_draw = Instant::now();
             player_state.clear_redraw_flag();
         }
 
         // Handle events
-        match tokio::time::timeout(Duration::from_millis(16), rx.recv()).await {
+        let poll_interval = if idle {
+            IDLE_POLL_INTERVAL
+        } else {
+            Duration::from_millis(16)
+        };
+        match tokio::time::timeout(poll_interval, rx.recv()).await {
             Ok(Some(Event::Key(key))) => {
+                let action = player_state.keymap.action(&key);
                 // If search mode is active, route keys to search input
-                let handled = if player_state.search_mode {
+                let handled = if player_state.config_dialog.is_some() {
+                    // Startup config error dialog
+                    match (key.code, action) {
+                        (KeyCode::Enter | KeyCode::Esc, _) => {
+                            player_state.config_dialog = None;
+                            true
+                        }
+                        (_, Some(Action::Quit)) => {
+                            break;
+                        }
+                        _ => false,
+                    }
+                } else if player_state.sleep_prompt.is_some() {
+                    match key.code {
+                        KeyCode::Char(c) => {
+                            player_state.sleep_prompt_add_char(c);
+                            true
+                        }
+                        KeyCode::Backspace => {
+                            player_state.sleep_prompt_backspace();
+                            true
+                        }
+                        KeyCode::Enter => {
+                            player_state.submit_sleep_prompt();
+                            true
+                        }
+                        KeyCode::Esc => {
+                            player_state.cancel_sleep_prompt();
+                            true
+                        }
+                        _ => false,
+                    }
+                } else if player_state.search_mode {
                     match key.code {
                         KeyCode::Char(c) => {
                             player_state.search_add_char(c);
@@ -149,6 +425,10 @@ async fn main() -> Result<()> {
                             player_state.search_backspace();
                             true
                         }
+                        KeyCode::Enter if key.modifiers.contains(KeyModifiers::ALT) => {
+                            player_state.search_submit_all();
+                            true
+                        }
                         KeyCode::Enter => {
                             player_state.search_submit();
                             true
@@ -159,107 +439,308 @@ async fn main() -> Result<()> {
                         }
                         _ => false,
                     }
+                } else if player_state.track_info.is_some() {
+                    match (key.code, action) {
+                        (KeyCode::Esc, _) | (_, Some(Action::TrackInfo)) => {
+                            player_state.toggle_track_info();
+                            true
+                        }
+                        (_, Some(Action::Quit)) => {
+                            break;
+                        }
+                        _ => false,
+                    }
+                } else if player_state.show_history {
+                    // History panel navigation
+                    match (key.code, action) {
+                        (_, Some(Action::ScrollUp)) => {
+                            player_state.history_up();
+                            true
+                        }
+                        (_, Some(Action::ScrollDown)) => {
+                            player_state.history_down();
+                            true
+                        }
+                        (KeyCode::Enter, _) => {
+                            player_state.play_history_selected();
+                            true
+                        }
+                        (KeyCode::Esc, _) => {
+                            player_state.toggle_history();
+                            true
+                        }
+                        (_, Some(Action::History)) => {
+                            player_state.toggle_history();
+                            true
+                        }
+                        (_, Some(Action::Quit)) => {
+                            break;
+                        }
+                        _ => false,
+                    }
+                } else if let (None, Some(digit)) = (action, count_digit(&key)) {
+                    player_state.push_count_digit(digit);
+                    true
                 } else {
-                    // Normal key handling
-                    match key.code {
-                        KeyCode::Char(c)
-                            if matches_keybind(&config.keybinds.quit, c, key.modifiers) =>
-                        {
+                    // Normal key handling; a typed count repeats movements and
+                    // multiplies seeks, and anything else just clears it
+                    let count = player_state.take_count();
+                    let times = count.unwrap_or(1);
+                    match action {
+                        Some(Action::Quit) => {
                             break;
                         }
-                        KeyCode::Char(c)
-                            if matches_keybind(&config.keybinds.play_pause, c, key.modifiers) =>
-                        {
+                        Some(Action::PlayPause) => {
                             player_state.toggle_playback();
                             true
                         }
-                        KeyCode::Char(c)
-                            if matches_keybind(&config.keybinds.next, c, key.modifiers) =>
-                        {
+                        Some(Action::Next) => {
                             player_state.next_track();
                             true
                         }
-                        KeyCode::Char(c)
-                            if matches_keybind(&config.keybinds.previous, c, key.modifiers) =>
-                        {
+                        Some(Action::Previous) => {
                             player_state.previous_track();
                             true
                         }
-                        KeyCode::Char(c)
-                            if matches_keybind(&config.keybinds.shuffle, c, key.modifiers) =>
-                        {
+                        Some(Action::Shuffle) => {
                             player_state.toggle_shuffle();
                             true
                         }
-                        KeyCode::Char(c)
-                            if matches_keybind(&config.keybinds.volume_up, c, key.modifiers) =>
-                        {
-                            player_state.increase_volume();
+                        Some(Action::VolumeUp) => {
+                            player_state.increase_volume(player_state.config.audio.volume_step());
+                            true
+                        }
+                        Some(Action::VolumeDown) => {
+                            player_state.decrease_volume(player_state.config.audio.volume_step());
+                            true
+                        }
+                        Some(Action::FineVolumeUp) => {
+                            player_state
+                                .increase_volume(player_state.config.audio.fine_volume_step());
+                            true
+                        }
+                        Some(Action::FineVolumeDown) => {
+                            player_state
+                                .decrease_volume(player_state.config.audio.fine_volume_step());
                             true
                         }
-                        KeyCode::Char(c)
-                            if matches_keybind(&config.keybinds.volume_down, c, key.modifiers) =>
-                        {
-                            player_state.decrease_volume();
+                        Some(Action::BigSeekForward) => {
+                            player_state
+                                .seek_forward(player_state.config.audio.big_seek_step() * times);
                             true
                         }
-                        KeyCode::Char(c)
-                            if matches_keybind(&config.keybinds.seek_forward, c, key.modifiers) =>
-                        {
-                            player_state.seek_forward();
+                        Some(Action::BigSeekBackward) => {
+                            player_state
+                                .seek_backward(player_state.config.audio.big_seek_step() * times);
                             true
                         }
-                        KeyCode::Char(c)
-                            if matches_keybind(
-                                &config.keybinds.seek_backward,
-                                c,
-                                key.modifiers,
-                            ) =>
-                        {
-                            player_state.seek_backward();
+                        Some(Action::SeekForward) => {
+                            player_state
+                                .seek_forward(player_state.config.audio.seek_step() * times);
                             true
                         }
-                        KeyCode::Char(c)
-                            if matches_keybind(&config.keybinds.help, c, key.modifiers) =>
-                        {
+                        Some(Action::SeekBackward) => {
+                            player_state
+                                .seek_backward(player_state.config.audio.seek_step() * times);
+                            true
+                        }
+                        Some(Action::Help) => {
                             player_state.toggle_help();
                             true
                         }
-                        KeyCode::Char(c)
-                            if matches_keybind(&config.keybinds.search, c, key.modifiers) =>
-                        {
+                        Some(Action::Search) => {
                             player_state.start_search();
                             true
                         }
-                        KeyCode::Char(c)
-                            if matches_keybind(&config.keybinds.LoopC, c, key.modifiers) =>
-                        {
+                        Some(Action::Filter) => {
+                            player_state.start_filter();
+                            true
+                        }
+                        Some(Action::Favorite) => {
+                            player_state.toggle_favorite();
+                            true
+                        }
+                        Some(Action::FavoritesFilter) => {
+                            player_state.toggle_favorites_filter();
+                            true
+                        }
+                        Some(Action::LoopCurrent) => {
                             player_state.loopC = !player_state.loopC;
                             true
                         }
-                        KeyCode::Char(c)
-                            if matches_keybind(&config.keybinds.clear, c, key.modifiers) =>
-                        {
+                        Some(Action::StopAfterCurrent) => {
+                            player_state.stop_after_current = !player_state.stop_after_current;
+                            true
+                        }
+                        Some(Action::Equalizer) => {
+                            player_state.toggle_equalizer();
+                            true
+                        }
+                        Some(Action::SleepTimer) => {
+                            player_state.start_sleep_prompt();
+                            true
+                        }
+                        Some(Action::LoopA) => {
+                            player_state.mark_loop_a();
+                            true
+                        }
+                        Some(Action::LoopB) => {
+                            player_state.mark_loop_b();
+                            true
+                        }
+                        Some(Action::LoopClear) => {
+                            player_state.clear_loop();
+                            true
+                        }
+                        Some(Action::SelectCurrent) => {
+                            player_state.select_current();
+                            true
+                        }
+                        Some(Action::PlayRandom) => {
+                            player_state.play_random();
+                            true
+                        }
+                        Some(Action::Rescan) => {
+                            player_state.rescan_library();
+                            true
+                        }
+                        Some(Action::RefreshTags) => {
+                            player_state.refresh_tags();
+                            true
+                        }
+                        Some(Action::TrackInfo) => {
+                            player_state.toggle_track_info();
+                            true
+                        }
+                        Some(Action::VisualizerMode) => {
+                            player_state.cycle_visualizer_mode();
+                            true
+                        }
+                        Some(Action::ToggleVisualizer) => {
+                            player_state.toggle_visualizer();
+                            true
+                        }
+                        Some(Action::History) => {
+                            player_state.toggle_history();
+                            true
+                        }
+                        Some(Action::Sort) => {
+                            player_state.cycle_sort();
+                            true
+                        }
+                        Some(Action::BrowseMode) => {
+                            player_state.toggle_browse_mode();
+                            true
+                        }
+                        Some(Action::Clear) => {
                             player_state.clear_queue();
                             true
                         }
-                        KeyCode::Char('-') => {
-                            player_state.decrease_volume();
+                        Some(Action::Mute) => {
+                            player_state.toggle_mute();
+                            true
+                        }
+                        Some(Action::TrimUp) => {
+                            player_state.adjust_trim(TRIM_STEP_DB);
+                            true
+                        }
+                        Some(Action::TrimDown) => {
+                            player_state.adjust_trim(-TRIM_STEP_DB);
+                            true
+                        }
+                        Some(Action::SavePlaylist) => {
+                            player_state.save_playlist();
+                            true
+                        }
+                        Some(Action::OutputDevice) => {
+                            player_state.cycle_output_device();
+                            true
+                        }
+                        Some(Action::Queue) => {
+                            player_state.toggle_queue_view();
+                            true
+                        }
+                        Some(Action::QueueFocus) => {
+                            player_state.toggle_queue_focus();
                             true
                         }
-                        KeyCode::Up => {
-                            player_state.scroll_up();
+                        Some(Action::Enqueue) => {
+                            player_state.enqueue_selected();
                             true
                         }
-                        KeyCode::Down => {
-                            player_state.scroll_down();
+                        Some(Action::RemoveFromQueue) => {
+                            player_state.remove_from_queue();
                             true
                         }
-                        KeyCode::Enter if config.keybinds.select == "enter" => {
+                        Some(Action::MoveUp) => {
+                            player_state.move_track_up();
+                            true
+                        }
+                        Some(Action::MoveDown) => {
+                            player_state.move_track_down();
+                            true
+                        }
+                        Some(Action::ScrollUp) => {
+                            for _ in 0..times {
+                                player_state.scroll_up();
+                            }
+                            true
+                        }
+                        Some(Action::ScrollDown) => {
+                            for _ in 0..times {
+                                player_state.scroll_down();
+                            }
+                            true
+                        }
+                        Some(Action::PageUp) => {
+                            player_state.page_up();
+                            true
+                        }
+                        Some(Action::PageDown) => {
+                            player_state.page_down();
+                            true
+                        }
+                        Some(Action::First) => {
+                            player_state.select_first();
+                            true
+                        }
+                        Some(Action::Last) => {
+                            player_state.select_last();
+                            true
+                        }
+                        Some(Action::Select) => {
                             player_state.play_selected();
                             true
                         }
-                        _ => false,
+                        None => match key.code {
+                            // Esc drops a typed count before anything else
+                            KeyCode::Esc if count.is_some() => true,
+                            KeyCode::Esc if player_state.filter_active => {
+                                player_state.clear_filter();
+                                true
+                            }
+                            KeyCode::Left if player_state.tree_focused() => {
+                                player_state.collapse_selected();
+                                true
+                            }
+                            KeyCode::Right if player_state.tree_focused() => {
+                                player_state.expand_selected();
+                                true
+                            }
+                            // Outside the tree nothing moves sideways, so the
+                            // arrows seek like the seek keys
+                            KeyCode::Left => {
+                                player_state
+                                    .seek_backward(player_state.config.audio.seek_step() * times);
+                                true
+                            }
+                            KeyCode::Right => {
+                                player_state
+                                    .seek_forward(player_state.config.audio.seek_step() * times);
+                                true
+                            }
+                            _ => false,
+                        },
                     }
                 };
 
@@ -267,35 +748,223 @@ async fn main() -> Result<()> {
                     player_state.mark_needs_redraw();
                 }
             }
+            Ok(Some(Event::Resize(_, _))) => {
+                // Repaint everything at once; the last frame was laid out
+                // for the old size and would otherwise linger until the next draw
+                terminal.clear()?;
+                player_state.mark_needs_redraw();
+            }
+            // The event listener panicked; without it there's no way to quit
+            Ok(None) => break,
             _ => {}
         }
+    }
 
-        // Auto-advance or loop when current finishes
-        if player_state.should_advance() {
-            if player_state.loopC {
-                // Replay same track
-                if let Some(idx) = player_state.current_track_index {
-                    player_state.play_track(idx);
-                }
-            } else {
-                player_state.next_track();
-            }
+    player_state.save_session();
+
+    // Restore terminal
+    AlbumArt::delete(terminal.backend_mut())?;
+    drop(terminal_guard);
+
+    Ok(())
+}
+
+/// Everything the player does between frames and key presses: config and
+/// library changes, control commands, timers and moving on to the next track
+fn update_playback(player_state: &mut PlayerState, config_watcher: &mut ConfigWatcher) {
+    // Pick up edits to config.toml
+    match config_watcher.poll() {
+        Some(Ok(new_config)) => {
+            player_state.apply_config(new_config);
             player_state.mark_needs_redraw();
         }
+        Some(Err(e)) => {
+            player_state.config_error = Some(e);
+            player_state.mark_needs_redraw();
+        }
+        None => {}
     }
 
-    // Restore terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    // Pick up tracks from the background library scan, then live changes
+    if player_state.poll_scan()
+        || player_state.poll_library_changes()
+        || player_state.poll_system_volume()
+        || player_state.poll_control()
+    {
+        player_state.mark_needs_redraw();
+    }
+    player_state.poll_notification();
+
+    if player_state.check_autoplay() {
+        player_state.mark_needs_redraw();
+    }
 
+    // Fade out and stop when the sleep timer runs out
+    if player_state.check_sleep_timer() {
+        player_state.mark_needs_redraw();
+    }
+
+    // Jump back to A once an A-B loop reaches B
+    if player_state.check_ab_loop() {
+        player_state.mark_needs_redraw();
+    }
+
+    // Count a play once the track is halfway through
+    if player_state.check_play_count() {
+        player_state.mark_needs_redraw();
+    }
+
+    // Auto-advance or loop when current finishes
+    if player_state.should_advance() {
+        player_state.advance();
+        player_state.mark_needs_redraw();
+    }
+}
+
+/// Play without a terminal UI until a shutdown signal, taking commands only
+/// from the control socket
+async fn run_headless(
+    mut player_state: PlayerState,
+    mut config_watcher: ConfigWatcher,
+    mut shutdown_rx: mpsc::Receiver<()>,
+) -> Result<()> {
+    if let Some(error) = &player_state.config_error {
+        eprintln!("catty-player: {}", error);
+    }
+    if let Some(error) = player_state.audio.device_error() {
+        eprintln!("catty-player: no audio output ({})", error);
+    }
+    if player_state.config.control.socket_path().is_none() {
+        eprintln!("catty-player: no [control] socket is set, so only signals reach this player");
+    }
+
+    loop {
+        update_playback(&mut player_state, &mut config_watcher);
+
+        // Nothing is drawn, so waking for the sleep timer and track ends is enough
+        let interval = if player_state.is_idle() {
+            IDLE_POLL_INTERVAL
+        } else {
+            HEADLESS_POLL_INTERVAL
+        };
+        if tokio::time::timeout(interval, shutdown_rx.recv())
+            .await
+            .is_ok()
+        {
+            break;
+        }
+    }
+
+    player_state.save_session();
     Ok(())
 }
 
-fn matches_keybind(keybind: &str, c: char, _modifiers: KeyModifiers) -> bool {
-    keybind.to_lowercase() == c.to_string().to_lowercase() || (keybind == "space" && c == ' ')
+/// Raw mode, the alternate screen and mouse capture for as long as it lives.
+///
+/// Dropping it restores the terminal, so an early `?` return or a panic
+/// leaves the shell usable. A panic hook restores it before the message is
+/// printed, otherwise the message would land on the alternate screen.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn new() -> io::Result<Self> {
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            default_hook(info);
+        }));
+
+        enable_raw_mode()?;
+        TERMINAL_ACTIVE.store(true, Ordering::SeqCst);
+        let guard = TerminalGuard;
+        execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+/// Whether the terminal is in the state `TerminalGuard::new` set up
+static TERMINAL_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Undo `TerminalGuard::new`. Only the first call after setup does anything,
+/// so the panic hook and the guard's `Drop` don't both write escape codes.
+fn restore_terminal() {
+    if !TERMINAL_ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let _ = disable_raw_mode();
+    let _ = execute!(
+        io::stdout(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        cursor::Show
+    );
+}
+
+/// Resolves on the first request to shut down. In raw mode Ctrl-C arrives
+/// as a key press, so SIGINT only comes from `kill` or a parent process.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(signal) => signal,
+            Err(_) => return std::future::pending().await,
+        };
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(signal) => signal,
+            Err(_) => return std::future::pending().await,
+        };
+        tokio::select! {
+            Ok(()) = tokio::signal::ctrl_c() => {}
+            Some(()) = terminate.recv() => {}
+            Some(()) = hangup.recv() => {}
+            else => std::future::pending().await,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Digit of a key typed as part of a count, like the 5 in `5j`
+fn count_digit(key: &KeyEvent) -> Option<u32> {
+    let held = KeyModifiers::CONTROL | KeyModifiers::ALT;
+    if key.modifiers.intersects(held) {
+        return None;
+    }
+    match key.code {
+        KeyCode::Char(c) => c.to_digit(10),
+        _ => None,
+    }
+}
+
+/// Whether a bare flag like `--clear-cache` was given
+fn has_flag(flag: &str) -> bool {
+    std::env::args().skip(1).any(|arg| arg == flag)
+}
+
+/// File given with an option like `--playlist <file>`, `-p <file>` or
+/// `--playlist=<file>`
+fn path_arg(long: &str, short: &str) -> Option<PathBuf> {
+    let joined = format!("{}=", long);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == long || arg == short {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix(&joined) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
 }
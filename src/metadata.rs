@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{
+    MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey, Visual,
+};
+use symphonia::core::probe::{Hint, ProbeResult};
+use symphonia::core::units::TimeBase;
+
+/// Tags and stream length read from a track
+#[derive(Debug, Default)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    /// ReplayGain adjustments in dB
+    pub track_gain: Option<f32>,
+    pub album_gain: Option<f32>,
+    /// Length in whole seconds
+    pub duration: Option<u64>,
+}
+
+/// How a track's audio is encoded, as far as the container says
+#[derive(Debug, Default)]
+pub struct CodecInfo {
+    /// Short codec name like "flac" or "mp3"
+    pub codec: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    /// Not stored by lossy formats
+    pub bits_per_sample: Option<u32>,
+}
+
+/// Probe a file with symphonia, reading any tags found along the way
+fn probe(path: &Path) -> Option<ProbeResult> {
+    let file = File::open(path).ok()?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()
+}
+
+/// Pick the front cover if tagged as such, otherwise the first picture
+fn pick_visual(revision: &MetadataRevision) -> Option<&Visual> {
+    let visuals = revision.visuals();
+    visuals
+        .iter()
+        .find(|v| matches!(v.usage, Some(StandardVisualKey::FrontCover)))
+        .or_else(|| visuals.first())
+}
+
+/// Extract the encoded bytes (JPEG/PNG) of a track's embedded cover art
+pub fn read_cover_art(path: &Path) -> Option<Vec<u8>> {
+    let mut probed = probe(path)?;
+
+    // Tags outside the container (e.g. ID3v2 on mp3) come from the probe,
+    // container-level tags (FLAC, MP4, Vorbis comments) from the reader
+    let visual = probed
+        .metadata
+        .get()
+        .and_then(|m| m.current().and_then(pick_visual).cloned())
+        .or_else(|| {
+            probed
+                .format
+                .metadata()
+                .current()
+                .and_then(pick_visual)
+                .cloned()
+        })?;
+
+    Some(visual.data.into_vec())
+}
+
+/// Read the codec and stream parameters of a track's default stream
+pub fn read_codec(path: &Path) -> Option<CodecInfo> {
+    let probed = probe(path)?;
+    let params = &probed.format.default_track()?.codec_params;
+
+    Some(CodecInfo {
+        codec: symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map(|descriptor| descriptor.short_name.to_string()),
+        sample_rate: params.sample_rate,
+        channels: params.channels.map(|channels| channels.count() as u16),
+        bits_per_sample: params.bits_per_sample,
+    })
+}
+
+/// Length of the default track in whole seconds: from the frame count in the
+/// stream header, or failing that by adding up the packets
+fn stream_duration(probed: &mut ProbeResult) -> Option<u64> {
+    let track = probed.format.default_track()?;
+    let params = &track.codec_params;
+    let time_base = params
+        .time_base
+        .or_else(|| params.sample_rate.map(|rate| TimeBase::new(1, rate)))?;
+    if let Some(frames) = params.n_frames {
+        return Some(time_base.calc_time(frames).seconds);
+    }
+
+    // Some files (e.g. VBR MP3s without a Xing header) don't say how long they are
+    let id = track.id;
+    let mut frames = 0;
+    while let Ok(packet) = probed.format.next_packet() {
+        if packet.track_id() == id {
+            frames += packet.dur;
+        }
+    }
+    (frames > 0).then(|| time_base.calc_time(frames).seconds)
+}
+
+/// Read title/artist/album/track number tags and the track length, leaving
+/// missing ones as `None`
+pub fn read_tags(path: &Path) -> TrackTags {
+    let mut tags = TrackTags::default();
+    let mut album_artist = None;
+
+    let Some(mut probed) = probe(path) else {
+        return tags;
+    };
+
+    // First value found wins, probe-level tags before container-level ones
+    let mut apply = |revision: &MetadataRevision| {
+        for tag in revision.tags() {
+            let value = tag.value.to_string().trim().to_string();
+            if value.is_empty() {
+                continue;
+            }
+
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => fill(&mut tags.title, value),
+                Some(StandardTagKey::Artist) => fill(&mut tags.artist, value),
+                Some(StandardTagKey::AlbumArtist) => fill(&mut album_artist, value),
+                Some(StandardTagKey::Album) => fill(&mut tags.album, value),
+                Some(StandardTagKey::TrackNumber) if tags.track_number.is_none() => {
+                    // Often stored as "3/12"
+                    tags.track_number = value.split('/').next().and_then(|n| n.trim().parse().ok());
+                }
+                Some(StandardTagKey::ReplayGainTrackGain) if tags.track_gain.is_none() => {
+                    tags.track_gain = parse_gain(&value);
+                }
+                Some(StandardTagKey::ReplayGainAlbumGain) if tags.album_gain.is_none() => {
+                    tags.album_gain = parse_gain(&value);
+                }
+                _ => {}
+            }
+        }
+    };
+
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        apply(revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        apply(revision);
+    }
+
+    tags.artist = tags.artist.or(album_artist);
+    tags.duration = stream_duration(&mut probed);
+    tags
+}
+
+/// Parse a ReplayGain value like "-6.54 dB"
+fn parse_gain(value: &str) -> Option<f32> {
+    // Drop the unit and any spacing before it
+    let number = value.trim_end_matches(|c: char| c.is_alphabetic() || c.is_whitespace());
+    number.trim().parse().ok().filter(|g: &f32| g.is_finite())
+}
+
+/// Set an optional tag only if it isn't set yet
+fn fill(slot: &mut Option<String>, value: String) {
+    if slot.is_none() {
+        *slot = Some(value);
+    }
+}
@@ -0,0 +1,44 @@
+use crate::database::{MusicDatabase, Track};
+use crate::metadata;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Show a desktop notification for a track that started playing.
+/// notify-send runs on its own thread, so a slow or missing daemon never stalls the UI.
+pub fn now_playing(track: &Track, cover_art: bool) {
+    let summary = track.title.clone();
+    let body = match (&track.artist, &track.album) {
+        (Some(artist), Some(album)) => format!("{} — {}", artist, album),
+        (Some(artist), None) => artist.clone(),
+        (None, Some(album)) => album.clone(),
+        (None, None) => String::new(),
+    };
+    let path = track.path.clone();
+
+    thread::spawn(move || {
+        let mut command = Command::new("notify-send");
+        command
+            .args(["--app-name", "Catty", "--expire-time", "5000"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        if let Some(icon) = cover_art.then(|| save_cover(&path)).flatten() {
+            command.arg("--icon").arg(icon);
+        }
+        // Tags starting with "-" would otherwise be read as options. Without
+        // notify-send there is nothing to show, so errors are ignored
+        let _ = command.arg("--").arg(summary).arg(body).status();
+    });
+}
+
+/// Write the track's embedded cover into the cache directory, for notify-send to load
+fn save_cover(path: &Path) -> Option<PathBuf> {
+    let data = metadata::read_cover_art(path)?;
+    let format = image::guess_format(&data).ok()?;
+    let extension = format.extensions_str().first()?;
+
+    let icon = MusicDatabase::cache_dir().join(format!("notification-cover.{}", extension));
+    fs::write(&icon, data).ok()?;
+    Some(icon)
+}
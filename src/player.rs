@@ -1,71 +1,278 @@
-use crate::audio::AudioPlayer;
-use crate::config::Config;
-use crate::database::{MusicDatabase, Track};
-use crate::visualizer::Visualizer;
+use crate::album_art::AlbumArt;
+use crate::audio::{AudioBackend, StreamFormat};
+use crate::browse::{self, BrowseMode, TreeRow};
+use crate::config::{Config, ReplayGainMode};
+use crate::control::{ControlCommand, ControlSocket};
+use crate::database::{self, LibraryWatcher, MusicDatabase, ScanUpdate, SortKey, Track};
+use crate::keybind::Keymap;
+use crate::metadata::{self, CodecInfo};
+use crate::notification;
+use crate::playlist;
+use crate::visualizer::{Visualizer, VisualizerMode};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Settings carried over between launches, kept in ~/.cache/catty/state.json
+#[derive(Serialize, Deserialize)]
+struct Session {
+    volume: f32,
+    #[serde(default)]
+    muted: bool,
+    /// Library order picked with the sort key; unset follows config.toml
+    #[serde(default)]
+    sort_key: Option<SortKey>,
+    #[serde(default)]
+    sort_descending: bool,
+    /// Visualizer shown or hidden with its toggle key; unset follows config.toml
+    #[serde(default)]
+    show_visualizer: Option<bool>,
+}
+
+/// What the track info popup shows, read once when it opens
+pub struct TrackInfo {
+    pub track: Track,
+    pub codec: Option<CodecInfo>,
+    pub stream: Option<StreamFormat>, // What the decoder produces, if this is the playing track
+}
 
 /// Player state management
 pub struct PlayerState {
     #[allow(non_snake_case)]
     pub loopC: bool,
+    pub stop_after_current: bool, // Stop instead of advancing when the track ends
+    pub loop_a: Option<Duration>, // Start of the A-B loop in the current track
+    pub loop_b: Option<Duration>, // End of the A-B loop
+    pub sleep_prompt: Option<String>, // Minutes being typed for the sleep timer
+    pub pending_count: Option<u32>, // Vi-style count typed before a movement or seek
+    sleep_at: Option<Instant>,    // When the sleep timer stops playback
     // Search UI state
     pub search_mode: bool,
     pub search_query: String,
-    pub search_results: Vec<usize>,
+    pub search_results: Vec<usize>, // Best fuzzy match first
+    pub search_as_filter: bool,     // Submitting keeps the results instead of playing
+    pub filter_active: bool,        // Library list narrowed to the search results
+    pub filter_selected: usize,     // Selected row among the filtered results
+    pub favorites_filter: bool,     // The filter lists starred tracks, not search matches
+    matcher: SkimMatcherV2,
     pub database: MusicDatabase,
-    pub audio: AudioPlayer,
+    scan: Option<Receiver<ScanUpdate>>, // Library scan still running
+    pub scanned_files: Option<usize>,   // Files the running scan has seen
+    library_watcher: Option<LibraryWatcher>,
+    control: Option<ControlSocket>, // Socket scripts send commands to, when configured
+    pub audio: Box<dyn AudioBackend>,
     pub queue: Vec<Track>,
+    queue_is_library: bool, // Queue holds the whole library, so no track counts as queued
     pub current_track_index: Option<usize>,
     pub list_state: usize, // Selected item in the list
     pub scroll_offset: usize,
+    pub page_size: usize, // Visible rows of the track list, updated on render
+    pub browse_mode: BrowseMode,
+    pub show_queue: bool,    // Queue panel is shown beside the library
+    pub queue_focused: bool, // Keys act on the queue panel rather than the library
+    pub queue_selected: usize,
+    pub tree_selected: usize,        // Selected row in the tree view
+    pub most_played_selected: usize, // Selected row in the most played view
+    collapsed: HashSet<String>,      // Collapsed artist/album headers
     pub is_playing: bool,
+    play_counted: bool, // The current track's play has been counted
     pub shuffle: bool,
     pub volume: f32,
+    pub muted: bool,
+    pub system_volume: Option<f32>, // Last PulseAudio volume, when shown
+    system_volume_checked: Option<Instant>, // When the system volume was last read
+    notify_at: Option<Instant>,     // When the playing track started, until it's announced
+    autoplay_pending: bool,         // Autoplay is waiting for the library to have tracks
     pub visualizer: Visualizer,
+    pub visualizer_mode: VisualizerMode,
+    pub show_visualizer: bool,
+    pub sort_key: SortKey, // Library order, from config.toml until cycled
+    pub sort_descending: bool,
+    pub album_art: AlbumArt,
     pub config: Config,
+    pub config_error: Option<String>,  // Last failed config reload
+    pub config_dialog: Option<String>, // Startup config problem awaiting dismissal
+    pub keymap: Keymap,                // Keybinds resolved to actions
     needs_redraw: bool,
-    played_indices: Vec<usize>, // Track played songs in shuffle mode
+    played_indices: Vec<usize>,  // Track played songs in shuffle mode
+    shuffle_next: Option<usize>, // Shuffle pick shown as up next and played by next_track
     pub show_help: bool,
+    pub history: Vec<(PathBuf, SystemTime)>, // Recently played, oldest first
+    pub show_history: bool,
+    pub history_selected: usize,
+    pub track_info: Option<TrackInfo>,     // Open track info popup
+    notice: Option<(String, Instant)>,     // Short-lived status message
+    last_error: Option<(String, Instant)>, // Why the last track failed to play
+    failed_in_a_row: usize,                // Tracks that failed since one last played
+    unplayable: HashSet<PathBuf>,          // Files that failed to decode, skipped by next_track
 }
 
 impl PlayerState {
     /// Create new player state
-    pub fn new(database: MusicDatabase, audio: AudioPlayer, config: Config) -> Self {
-        let visualizer = Visualizer::new(config.visualizer.bar_count, config.visualizer.smoothing);
+    pub fn new(mut database: MusicDatabase, audio: Box<dyn AudioBackend>, config: Config) -> Self {
+        // Pick up the volume and library order where the last session left them
+        let session = Self::load_session();
+        let (sort_key, sort_descending) = session
+            .as_ref()
+            .and_then(|s| s.sort_key.map(|key| (key, s.sort_descending)))
+            .unwrap_or((config.library.sort_key, config.library.sort_descending));
+        database.sort(sort_key, sort_descending);
+        let show_visualizer = session
+            .as_ref()
+            .and_then(|s| s.show_visualizer)
+            .unwrap_or(config.visualizer.enabled);
 
-        let initial_volume = 0.2; // Start at 20%
+        let mut visualizer = Visualizer::new(
+            config.visualizer.bar_count,
+            config.visualizer.attack(),
+            config.visualizer.release(),
+            audio.get_sample_buffer(),
+        );
+        visualizer.set_auto_gain(config.visualizer.auto_gain);
+        visualizer.set_stereo(config.visualizer.stereo);
+        visualizer.set_eq(&config.visualizer.eq);
+        let album_art = AlbumArt::new(config.album_art.enabled);
+
+        let initial_volume = session
+            .as_ref()
+            .map_or(config.audio.initial_volume, |s| s.volume)
+            .clamp(0.0, 1.0);
+        let muted = session.is_some_and(|s| s.muted);
         audio.set_volume(initial_volume);
+        if muted {
+            audio.toggle_mute();
+        }
+        audio.set_equalizer(
+            config.audio.equalizer.enabled,
+            &config.audio.equalizer.bands,
+        );
+
+        let (control, control_error) = match Self::bind_control(&config) {
+            Ok(control) => (control, None),
+            Err(e) => (None, Some(e)),
+        };
+
+        // Tell the user if the configured output device wasn't found
+        let notice = audio
+            .device_warning()
+            .map(|warning| warning.to_string())
+            .or(control_error)
+            .map(|message| (message, Instant::now()));
+
+        database.set_extra_extensions(&config.library.extra_extensions);
+        database.set_duplicates(
+            config.library.duplicates,
+            config.library.duplicate_preference,
+        );
+        let scan = Some(database.start_scan());
+        let config_dialog = Self::keybind_dialog(&config);
+        let keymap = Keymap::new(&config.keybinds);
 
         Self {
             database,
+            scan,
+            scanned_files: Some(0),
+            library_watcher: LibraryWatcher::new(&database::music_dir()),
+            control,
             audio,
             queue: Vec::new(),
+            queue_is_library: false,
             current_track_index: None,
             list_state: 0,
             scroll_offset: 0,
+            page_size: 0,
+            browse_mode: BrowseMode::default(),
+            show_queue: false,
+            queue_focused: false,
+            queue_selected: 0,
+            tree_selected: 0,
+            most_played_selected: 0,
+            collapsed: HashSet::new(),
             is_playing: false,
+            play_counted: false,
             shuffle: false,
             volume: initial_volume,
+            muted,
             visualizer,
+            visualizer_mode: config.visualizer.mode,
+            show_visualizer,
+            sort_key,
+            sort_descending,
+            album_art,
+            autoplay_pending: config.playback.autoplay,
             config,
+            config_error: None,
+            config_dialog,
+            keymap,
             needs_redraw: true,
             loopC: false,
+            stop_after_current: false,
+            loop_a: None,
+            loop_b: None,
+            sleep_prompt: None,
+            pending_count: None,
+            notify_at: None,
+            sleep_at: None,
             search_mode: false,
             search_query: String::new(),
             search_results: Vec::new(),
+            search_as_filter: false,
+            filter_active: false,
+            filter_selected: 0,
+            favorites_filter: false,
+            matcher: SkimMatcherV2::default().ignore_case(),
             played_indices: Vec::new(),
+            shuffle_next: None,
             show_help: false,
+            history: Self::load_history(),
+            show_history: false,
+            history_selected: 0,
+            track_info: None,
+            notice,
+            last_error: None,
+            failed_in_a_row: 0,
+            unplayable: HashSet::new(),
+            system_volume: None,
+            system_volume_checked: None,
         }
     }
 
     /// Start search mode (user pressed search keybind)
     pub fn start_search(&mut self) {
         self.search_mode = true;
+        self.search_as_filter = false;
+        self.filter_active = false;
+        self.favorites_filter = false;
+        self.search_query.clear();
+        self.search_results.clear();
+    }
+
+    /// Start a search whose results stay listed for browsing once submitted
+    pub fn start_filter(&mut self) {
+        self.start_search();
+        self.search_as_filter = true;
+    }
+
+    /// Go back to the whole library
+    pub fn clear_filter(&mut self) {
+        self.filter_active = false;
+        self.favorites_filter = false;
         self.search_query.clear();
         self.search_results.clear();
     }
 
+    /// Whether the library list shows search results instead of every track
+    pub fn showing_search_results(&self) -> bool {
+        self.filter_active || (self.search_mode && !self.search_query.is_empty())
+    }
+
     pub fn cancel_search(&mut self) {
         self.search_mode = false;
         self.search_query.clear();
@@ -83,6 +290,17 @@ impl PlayerState {
     }
 
     pub fn search_submit(&mut self) {
+        if self.search_as_filter {
+            // Leave the text input but keep the results on screen
+            self.search_mode = false;
+            self.filter_active = !self.search_results.is_empty();
+            self.filter_selected = 0;
+            if !self.filter_active {
+                self.search_query.clear();
+            }
+            return;
+        }
+
         if let Some(&first) = self.search_results.first() {
             self.list_state = first;
             // Play the selected search result
@@ -91,26 +309,459 @@ impl PlayerState {
         self.cancel_search();
     }
 
+    /// Replace the queue with every search result, in the order listed, and
+    /// play the first
+    pub fn search_submit_all(&mut self) {
+        let results = std::mem::take(&mut self.search_results);
+        if let Some(&first) = results.first() {
+            self.list_state = first;
+            self.play_tracks(&results, 0);
+        }
+        self.search_mode = false;
+        self.filter_active = false;
+        self.search_query.clear();
+    }
+
     fn update_search_results(&mut self) {
-        let q = self.search_query.to_lowercase();
-        if q.is_empty() {
+        if self.favorites_filter {
+            self.search_results = self.database.favorite_indices();
+            let last = self.search_results.len().saturating_sub(1);
+            self.filter_selected = self.filter_selected.min(last);
+            return;
+        }
+        if self.search_query.is_empty() {
             self.search_results.clear();
             return;
         }
 
-        self.search_results = self
+        // Titles match fuzzily; failing that a path fragment like a folder name
+        // matches as a plain substring, ranked after every title match
+        let query = self.search_query.to_lowercase();
+        let mut scored: Vec<(i64, usize)> = self
             .database
             .get_tracks()
             .iter()
             .enumerate()
             .filter_map(|(i, t)| {
-                if t.title.to_lowercase().contains(&q) {
-                    Some(i)
-                } else {
-                    None
-                }
+                self.matcher
+                    .fuzzy_match(&t.title, &self.search_query)
+                    .or_else(|| {
+                        let path = t.path.to_string_lossy().to_lowercase();
+                        path.contains(&query).then_some(i64::MIN)
+                    })
+                    .map(|score| (score, i))
             })
             .collect();
+        // Highest score first, library order among equals
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        self.search_results = scored.into_iter().map(|(_, i)| i).collect();
+    }
+
+    /// List only starred tracks, or go back to the whole library
+    pub fn toggle_favorites_filter(&mut self) {
+        if self.favorites_filter {
+            self.clear_filter();
+            return;
+        }
+
+        self.clear_filter();
+        self.favorites_filter = true;
+        self.filter_selected = 0;
+        self.update_search_results();
+        self.filter_active = !self.search_results.is_empty();
+        if !self.filter_active {
+            self.favorites_filter = false;
+            self.set_notice("No favorites yet".to_string());
+        }
+    }
+
+    /// Star or unstar the selected track
+    pub fn toggle_favorite(&mut self) {
+        let Some(path) = self.selected_track().map(|t| t.path.clone()) else {
+            return;
+        };
+
+        match self.database.toggle_favorite(&path) {
+            Ok(_) if self.favorites_filter => {
+                // Unstarring drops the track from the list; leave it once empty
+                self.update_search_results();
+                if self.search_results.is_empty() {
+                    self.clear_filter();
+                }
+            }
+            Ok(_) => {}
+            Err(e) => self.set_notice(format!("Couldn't save favorites: {}", e)),
+        }
+    }
+
+    /// The single track under the cursor in whichever list keys act on
+    fn selected_track(&self) -> Option<&Track> {
+        if self.queue_view_active() {
+            return self.queue.get(self.queue_selected);
+        }
+
+        let index = if self.filter_active {
+            self.search_results.get(self.filter_selected).copied()
+        } else if self.browse_mode == BrowseMode::Tree && !self.search_mode {
+            match self.tree_rows().get(self.tree_selected) {
+                Some(&TreeRow::Track(i)) => Some(i),
+                _ => None,
+            }
+        } else if self.browse_mode == BrowseMode::MostPlayed && !self.search_mode {
+            self.database
+                .most_played()
+                .get(self.most_played_selected)
+                .copied()
+        } else {
+            Some(self.list_state)
+        };
+        index.and_then(|i| self.database.get_tracks().get(i))
+    }
+
+    /// Character positions in `title` matched by the search query
+    pub fn search_matches(&self, title: &str) -> Vec<usize> {
+        self.matcher
+            .fuzzy_indices(title, &self.search_query)
+            .map(|(_, indices)| indices)
+            .unwrap_or_default()
+    }
+
+    /// Apply updates from the background library scan, returning whether
+    /// anything changed
+    pub fn poll_scan(&mut self) -> bool {
+        let mut changed = false;
+        while let Some(scan) = &self.scan {
+            let update = match scan.try_recv() {
+                Ok(update) => update,
+                Err(TryRecvError::Empty) => break,
+                // The scan thread died without finishing
+                Err(TryRecvError::Disconnected) => {
+                    self.scan = None;
+                    self.scanned_files = None;
+                    return true;
+                }
+            };
+            changed = true;
+
+            match update {
+                ScanUpdate::Progress { files, tracks } => {
+                    self.scanned_files = Some(files);
+                    self.update_library(|database| database.add_tracks(tracks));
+                }
+                ScanUpdate::Finished(tracks) => {
+                    let mut saved = Ok(());
+                    self.update_library(|database| saved = database.finish_scan(tracks));
+                    if let Err(e) = saved {
+                        self.set_notice(format!("Couldn't save the library cache: {}", e));
+                    }
+                    self.scan = None;
+                    self.scanned_files = None;
+                }
+                ScanUpdate::MissingDirectory(dir) => {
+                    self.set_notice(format!("Music directory not found: {}", dir.display()));
+                    self.scan = None;
+                    self.scanned_files = None;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Scan the music directory again in the background. The library keeps
+    /// working meanwhile and the selection follows its track when it's done.
+    pub fn rescan_library(&mut self) {
+        if self.scan.is_some() {
+            self.set_notice("Already scanning the library".to_string());
+            return;
+        }
+        self.start_scan();
+        self.set_notice("Rescanning…".to_string());
+    }
+
+    /// Re-read every track's tags in the background, without rescanning
+    /// the music directory
+    pub fn refresh_tags(&mut self) {
+        if self.scan.is_some() {
+            self.set_notice("Already scanning the library".to_string());
+            return;
+        }
+        self.scan = Some(self.database.start_tag_refresh());
+        self.scanned_files = Some(0);
+        self.set_notice("Re-reading tags…".to_string());
+    }
+
+    fn start_scan(&mut self) {
+        self.scan = Some(self.database.start_scan());
+        self.scanned_files = Some(0);
+    }
+
+    /// Apply files added to or removed from the music directory, returning
+    /// whether the library changed
+    pub fn poll_library_changes(&mut self) -> bool {
+        // Changes wait until the startup scan is done so it can't undo them
+        if self.scan.is_some() {
+            return false;
+        }
+        let Some(watcher) = &mut self.library_watcher else {
+            return false;
+        };
+        let paths = watcher.poll();
+        if paths.is_empty() {
+            return false;
+        }
+
+        let mut result = Ok(false);
+        self.update_library(|database| result = database.apply_changes(&paths));
+        match result {
+            Ok(changed) => changed,
+            Err(e) => {
+                self.set_notice(format!("Couldn't save the library cache: {}", e));
+                true
+            }
+        }
+    }
+
+    /// Open the control socket named in the config, if there is one
+    fn bind_control(config: &Config) -> Result<Option<ControlSocket>, String> {
+        let Some(path) = config.control.socket_path() else {
+            return Ok(None);
+        };
+        ControlSocket::bind(&path)
+            .map(Some)
+            .map_err(|e| format!("Couldn't open {}: {}", path.display(), e))
+    }
+
+    /// Carry out commands sent to the control socket, answering each with
+    /// the playback status
+    pub fn poll_control(&mut self) -> bool {
+        let Some(control) = &mut self.control else {
+            return false;
+        };
+        let requests = control.poll();
+        if requests.is_empty() {
+            return false;
+        }
+
+        for request in requests {
+            let Some(command) = request.command else {
+                request.reply(r#"{"error":"unknown command"}"#);
+                continue;
+            };
+            match command {
+                ControlCommand::Status => {}
+                ControlCommand::Play if !self.is_playing => self.toggle_playback(),
+                ControlCommand::Pause if self.is_playing => self.toggle_playback(),
+                ControlCommand::Play | ControlCommand::Pause => {}
+                ControlCommand::Toggle => self.toggle_playback(),
+                ControlCommand::Next => {
+                    self.next_track();
+                }
+                ControlCommand::Previous => self.previous_track(),
+            }
+            request.reply(&self.status_json());
+        }
+        true
+    }
+
+    /// What's playing, where and how loud, for the control socket
+    fn status_json(&self) -> String {
+        // Three decimals, so f32 noise doesn't show up in the JSON
+        let round = |value: f32| (f64::from(value) * 1000.0).round() / 1000.0;
+        let track = self.get_current_track();
+        let state = match (track, self.is_playing) {
+            (None, _) => "stopped",
+            (Some(_), true) => "playing",
+            (Some(_), false) => "paused",
+        };
+        serde_json::json!({
+            "state": state,
+            "title": track.map(|t| &t.title),
+            "artist": track.and_then(|t| t.artist.as_ref()),
+            "album": track.and_then(|t| t.album.as_ref()),
+            "path": track.map(|t| &t.path),
+            "position": track.map(|_| round(self.get_elapsed_seconds())),
+            "duration": track.map(|_| round(self.get_duration_seconds())),
+            "volume": round(self.volume),
+            "muted": self.muted,
+        })
+        .to_string()
+    }
+
+    /// Cycle the library sort order: ascending, descending, then the next key
+    /// (remembered in the session, not config.toml)
+    pub fn cycle_sort(&mut self) {
+        if self.sort_descending {
+            self.sort_key = self.sort_key.next();
+            self.sort_descending = false;
+        } else {
+            self.sort_descending = true;
+        }
+
+        self.apply_sort();
+    }
+
+    /// Re-sort the library by the configured order
+    fn apply_sort(&mut self) {
+        self.update_library(|_| {});
+    }
+
+    /// Change the library, then re-sort it and refresh everything indexing into it
+    fn update_library(&mut self, change: impl FnOnce(&mut MusicDatabase)) {
+        // Keep each selection on the same track, not the same row
+        let tracks = self.database.get_tracks();
+        let path_at = |i: usize| tracks.get(i).map(|t| t.path.clone());
+        let selected = path_at(self.list_state);
+        let most_played = self
+            .database
+            .most_played()
+            .get(self.most_played_selected)
+            .and_then(|&i| path_at(i));
+        let filtered = self
+            .search_results
+            .get(self.filter_selected)
+            .and_then(|&i| path_at(i));
+        let tree_row = self
+            .tree_rows()
+            .get(self.tree_selected)
+            .and_then(|row| row.id(tracks));
+        change(&mut self.database);
+        self.database.sort(self.sort_key, self.sort_descending);
+        match selected.and_then(|p| self.database.index_of(&p)) {
+            Some(i) => self.list_state = i,
+            // The selected track is gone; stay in range of what's left
+            None => {
+                let last = self.database.track_count().saturating_sub(1);
+                self.list_state = self.list_state.min(last);
+            }
+        }
+
+        // Rows that are gone leave the selection where it was, in range
+        let tracks = self.database.get_tracks();
+        let rows = self.tree_rows();
+        self.tree_selected = tree_row
+            .and_then(|id| {
+                rows.iter()
+                    .position(|row| row.id(tracks).as_ref() == Some(&id))
+            })
+            .unwrap_or(self.tree_selected.min(rows.len().saturating_sub(1)));
+        let most_played_rows = self.database.most_played();
+        self.most_played_selected = most_played
+            .and_then(|p| most_played_rows.iter().position(|&i| tracks[i].path == p))
+            .unwrap_or(
+                self.most_played_selected
+                    .min(most_played_rows.len().saturating_sub(1)),
+            );
+
+        self.update_search_results();
+        let tracks = self.database.get_tracks();
+        self.filter_selected = filtered
+            .and_then(|p| {
+                self.search_results
+                    .iter()
+                    .position(|&i| tracks[i].path == p)
+            })
+            .unwrap_or(
+                self.filter_selected
+                    .min(self.search_results.len().saturating_sub(1)),
+            );
+    }
+
+    /// Swap in a reloaded config, applying what changed
+    pub fn apply_config(&mut self, config: Config) {
+        let old = std::mem::replace(&mut self.config, config);
+
+        self.visualizer
+            .set_bar_count(self.config.visualizer.bar_count);
+        self.visualizer.set_smoothing(
+            self.config.visualizer.attack(),
+            self.config.visualizer.release(),
+        );
+        if self.config.visualizer.auto_gain != old.visualizer.auto_gain {
+            self.visualizer
+                .set_auto_gain(self.config.visualizer.auto_gain);
+        }
+        if self.config.visualizer.stereo != old.visualizer.stereo {
+            self.visualizer.set_stereo(self.config.visualizer.stereo);
+        }
+        self.visualizer.set_eq(&self.config.visualizer.eq);
+        if self.config.visualizer.mode != old.visualizer.mode {
+            self.visualizer_mode = self.config.visualizer.mode;
+        }
+        if self.config.visualizer.enabled != old.visualizer.enabled {
+            self.show_visualizer = self.config.visualizer.enabled;
+        }
+
+        self.album_art.set_enabled(self.config.album_art.enabled);
+        if let Some(track) = self.get_current_track().map(|t| t.path.clone()) {
+            self.album_art.load(&track);
+        }
+
+        if self.config.audio.equalizer != old.audio.equalizer {
+            let equalizer = &self.config.audio.equalizer;
+            self.audio
+                .set_equalizer(equalizer.enabled, &equalizer.bands);
+        }
+
+        if self.config.audio.output_device != old.audio.output_device {
+            let device = self.config.audio.output_device.clone();
+            self.switch_output_device(device.as_deref());
+        }
+
+        // Editing the order in config.toml takes over from a cycled one
+        if self.config.library.sort_key != old.library.sort_key
+            || self.config.library.sort_descending != old.library.sort_descending
+        {
+            self.sort_key = self.config.library.sort_key;
+            self.sort_descending = self.config.library.sort_descending;
+            self.apply_sort();
+        }
+
+        if self.config.library.duplicates != old.library.duplicates
+            || self.config.library.duplicate_preference != old.library.duplicate_preference
+        {
+            let (mode, preference) = (
+                self.config.library.duplicates,
+                self.config.library.duplicate_preference,
+            );
+            self.update_library(|database| database.set_duplicates(mode, preference));
+        }
+
+        // Rescan so files with added or removed extensions come and go
+        if self
+            .database
+            .set_extra_extensions(&self.config.library.extra_extensions)
+        {
+            self.start_scan();
+        }
+
+        if self.config.control.socket_path() != old.control.socket_path() {
+            // Close the old socket first, in case the new one reuses its path
+            self.control = None;
+            match Self::bind_control(&self.config) {
+                Ok(control) => self.control = control,
+                Err(e) => self.set_notice(e),
+            }
+        }
+
+        self.keymap = Keymap::new(&self.config.keybinds);
+        self.config_error = None;
+        self.config_dialog = Self::keybind_dialog(&self.config);
+    }
+
+    /// Dialog listing keybinds that won't work, if there are any
+    fn keybind_dialog(config: &Config) -> Option<String> {
+        let warnings = config.keybind_warnings();
+        if warnings.is_empty() {
+            return None;
+        }
+        let mut message = String::from("Some keybinds in config.toml won't work:\n");
+        for warning in warnings {
+            message.push_str(&format!("\n  {}", warning));
+        }
+        message.push_str(
+            "\n\nUnrecognized keys do nothing; a key bound twice runs the action listed first.",
+        );
+        Some(message)
     }
 
     /// Toggle playback
@@ -128,21 +779,33 @@ impl PlayerState {
         }
     }
 
-    /// Play next track
-    pub fn next_track(&mut self) {
-        if self.queue.is_empty() {
-            return;
-        }
+    /// Play the next track that can be played, skipping known-bad files.
+    /// Returns whether playback started; gives up after one pass of the queue.
+    pub fn next_track(&mut self) -> bool {
+        for _ in 0..self.queue.len() {
+            let next_index = if self.shuffle {
+                self.get_next_shuffle_index()
+            } else {
+                self.current_track_index
+                    .map(|i| (i + 1) % self.queue.len())
+                    .unwrap_or(0)
+            };
 
-        let next_index = if self.shuffle {
-            self.get_next_shuffle_index()
-        } else {
-            self.current_track_index
-                .map(|i| (i + 1) % self.queue.len())
-                .unwrap_or(0)
-        };
+            if self.unplayable.contains(&self.queue[next_index].path) {
+                // Step over it without trying to decode it again
+                self.current_track_index = Some(next_index);
+                if self.shuffle && !self.played_indices.contains(&next_index) {
+                    self.played_indices.push(next_index);
+                }
+                continue;
+            }
+            if self.play_track(next_index) {
+                return true;
+            }
+        }
 
-        self.play_track(next_index);
+        self.is_playing = false;
+        false
     }
 
     /// Play previous track
@@ -151,6 +814,24 @@ impl PlayerState {
             return;
         }
 
+        // Like most players, a track that's been playing a while restarts first
+        let threshold = self
+            .config
+            .playback
+            .previous_restart_seconds
+            .saturating_mul(1000);
+        if threshold > 0
+            && self.current_track_index.is_some()
+            && self.audio.get_elapsed_millis() > threshold
+        {
+            // Tracks of unknown length can't seek; those go back a track instead
+            let restarted =
+                self.audio.seek_to(0).is_ok() && self.audio.get_elapsed_millis() <= threshold;
+            if restarted {
+                return;
+            }
+        }
+
         let prev_index = self
             .current_track_index
             .map(|i| if i > 0 { i - 1 } else { self.queue.len() - 1 })
@@ -159,20 +840,126 @@ impl PlayerState {
         self.play_track(prev_index);
     }
 
-    /// Play track at index
-    pub fn play_track(&mut self, index: usize) {
-        if let Some(track) = self.queue.get(index) {
-            if self.audio.play(&track.path).is_ok() {
-                self.current_track_index = Some(index);
-                self.is_playing = true;
+    /// Play track at index, returning whether playback started
+    pub fn play_track(&mut self, index: usize) -> bool {
+        let Some(track) = self.queue.get(index) else {
+            return false;
+        };
+        let path = track.path.clone();
 
-                // Track played index for shuffle
-                if self.shuffle && !self.played_indices.contains(&index) {
-                    self.played_indices.push(index);
-                }
+        self.audio.set_gain(self.track_gain(track));
+        if let Err(e) = self.audio.play(&path) {
+            self.play_failed(index, &path, e);
+            return false;
+        }
+        self.unplayable.remove(&path);
+
+        // Replaying the same track (e.g. on loop) isn't announced again
+        if self.get_current_track().map(|t| &t.path) != Some(&path) {
+            self.notify_at = Some(Instant::now());
+        }
+
+        // Loop points belong to the track they were marked in
+        if self.current_track_index != Some(index) {
+            self.clear_loop();
+        }
+        self.current_track_index = Some(index);
+        self.is_playing = true;
+        self.play_counted = false;
+        self.failed_in_a_row = 0;
+        self.last_error = None;
+        self.album_art.load(&path);
+
+        // Track played index for shuffle
+        if self.shuffle && !self.played_indices.contains(&index) {
+            self.played_indices.push(index);
+        }
+        self.pick_shuffle_next();
+
+        self.push_history(path);
+        true
+    }
+
+    /// Record a track that couldn't be played. While playing, auto-advance
+    /// then moves past it; once the whole queue has failed playback stops.
+    fn play_failed(&mut self, index: usize, path: &Path, error: anyhow::Error) {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        // Without an output device every track fails, so none of them are bad
+        if self.audio.device_error().is_none() {
+            self.unplayable.insert(path.to_path_buf());
+        }
+        self.last_error = Some((
+            format!("Cannot play {}: {}", name.to_string_lossy(), error),
+            Instant::now(),
+        ));
+
+        self.audio.stop();
+        self.album_art.clear();
+        self.notify_at = None;
+        self.clear_loop();
+        self.current_track_index = Some(index);
+        if self.shuffle && !self.played_indices.contains(&index) {
+            self.played_indices.push(index);
+        }
+        self.pick_shuffle_next();
+
+        self.failed_in_a_row += 1;
+        if self.failed_in_a_row >= self.queue.len() {
+            self.is_playing = false;
+        }
+    }
+
+    /// Whether the current track is one that failed to play
+    pub fn current_track_failed(&self) -> bool {
+        self.failed_in_a_row > 0
+    }
+
+    /// Whether a file failed to play, e.g. because its format is unsupported
+    pub fn is_unplayable(&self, path: &Path) -> bool {
+        self.unplayable.contains(path)
+    }
+
+    /// Why the last track failed to play, if that was recent
+    pub fn last_error(&self) -> Option<&str> {
+        const ERROR_DURATION: Duration = Duration::from_secs(8);
+
+        self.last_error
+            .as_ref()
+            .filter(|(_, shown)| shown.elapsed() < ERROR_DURATION)
+            .map(|(message, _)| message.as_str())
+    }
+
+    /// Volume multiplier for a track: ReplayGain with its hand-set trim on top
+    fn track_gain(&self, track: &Track) -> f32 {
+        let trim = self.database.trim(&track.path);
+        self.replay_gain(track) * 10f32.powf(trim / 20.0)
+    }
+
+    /// Nudge the playing track's volume trim by `delta` dB; it's remembered
+    /// for the file and applied whenever it plays
+    pub fn adjust_trim(&mut self, delta: f32) {
+        let Some(track) = self.get_current_track().cloned() else {
+            return;
+        };
+        match self.database.adjust_trim(&track.path, delta) {
+            Ok(trim) => {
+                self.audio.set_gain(self.track_gain(&track));
+                self.set_notice(format!("Track trim {:+.0} dB", trim));
             }
+            Err(e) => self.set_notice(format!("Couldn't save the trim: {}", e)),
         }
     }
+
+    /// Volume multiplier from the track's ReplayGain tags (1.0 if untagged or off)
+    fn replay_gain(&self, track: &Track) -> f32 {
+        let db = match self.config.audio.replaygain {
+            ReplayGainMode::Track => track.track_gain.or(track.album_gain),
+            ReplayGainMode::Album => track.album_gain.or(track.track_gain),
+            ReplayGainMode::Off => None,
+        };
+        db.map_or(1.0, |db| 10f32.powf(db / 20.0))
+    }
+
     #[allow(dead_code)]
     pub fn play_again(&mut self) {
         // Replay the current track if there is one
@@ -188,145 +975,1121 @@ impl PlayerState {
         if let Some(current) = self.current_track_index {
             self.played_indices.push(current);
         }
+        self.pick_shuffle_next();
     }
 
-    /// Get next shuffle index (without repetition until all played)
+    /// Choose the next shuffle track now so it can be shown as up next
+    fn pick_shuffle_next(&mut self) {
+        self.shuffle_next = None;
+        if self.shuffle && !self.queue.is_empty() {
+            self.shuffle_next = Some(self.get_next_shuffle_index());
+        }
+    }
+
+    /// Get next shuffle index: no track repeats until all have played, and
+    /// the current one is never picked unless it's the only one
     fn get_next_shuffle_index(&mut self) -> usize {
         let queue_len = self.queue.len();
+        let current = self.current_track_index;
 
-        // Reset if all tracks have been played
-        if self.played_indices.len() >= queue_len {
-            self.played_indices.clear();
-            if let Some(current) = self.current_track_index {
-                self.played_indices.push(current);
-            }
+        // Stick to the pick already shown as up next
+        if let Some(next) = self
+            .shuffle_next
+            .take()
+            .filter(|&i| i < queue_len && Some(i) != current && !self.played_indices.contains(&i))
+        {
+            return next;
         }
 
         // Find unplayed tracks
         let mut unplayed: Vec<usize> = (0..queue_len)
-            .filter(|i| !self.played_indices.contains(i))
+            .filter(|&i| Some(i) != current && !self.played_indices.contains(&i))
             .collect();
 
+        // Start a new cycle once all have played
         if unplayed.is_empty() {
-            return 0;
+            self.played_indices = current.into_iter().collect();
+            unplayed = (0..queue_len).filter(|&i| Some(i) != current).collect();
         }
 
         // Pick random unplayed track
-        let mut rng = thread_rng();
-        unplayed.shuffle(&mut rng);
-        unplayed[0]
+        unplayed
+            .choose(&mut thread_rng())
+            .copied()
+            .unwrap_or(current.unwrap_or(0))
     }
 
-    /// Increase volume
-    pub fn increase_volume(&mut self) {
-        self.volume = (self.volume + 0.05).min(1.0);
+    /// Increase volume by `step`
+    pub fn increase_volume(&mut self, step: f32) {
+        self.unmute();
+        self.volume = (self.volume + step).min(1.0);
         self.audio.set_volume(self.volume);
     }
 
-    /// Decrease volume
-    pub fn decrease_volume(&mut self) {
-        self.volume = (self.volume - 0.05).max(0.0);
+    /// Decrease volume by `step`
+    pub fn decrease_volume(&mut self, step: f32) {
+        self.unmute();
+        self.volume = (self.volume - step).max(0.0);
         self.audio.set_volume(self.volume);
     }
 
-    /// Scroll up in list
-    pub fn scroll_up(&mut self) {
-        if self.list_state > 0 {
-            self.list_state -= 1;
+    /// Re-read the system volume when it's shown and the last read is stale,
+    /// returning whether it changed
+    pub fn poll_system_volume(&mut self) -> bool {
+        // pactl is a process spawn, so it runs a couple of times a second at most
+        const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+        if !self.config.audio.show_system_volume {
+            return self.system_volume.take().is_some();
+        }
+        if self
+            .system_volume_checked
+            .is_some_and(|at| at.elapsed() < CHECK_INTERVAL)
+        {
+            return false;
         }
+        self.system_volume_checked = Some(Instant::now());
+
+        let volume = crate::audio::get_system_volume();
+        let changed = volume != self.system_volume;
+        self.system_volume = volume;
+        changed
     }
 
-    /// Scroll down in list
-    pub fn scroll_down(&mut self) {
-        let max = self.database.track_count().saturating_sub(1);
-        if self.list_state < max {
-            self.list_state += 1;
+    /// With autoplay on, start playing as soon as there's something to play:
+    /// the queue, else the last played track, else the first in the library
+    pub fn check_autoplay(&mut self) -> bool {
+        if !self.autoplay_pending {
+            return false;
+        }
+        if self.is_playing {
+            self.autoplay_pending = false;
+            return false;
         }
+
+        if !self.queue.is_empty() {
+            self.play_track(0);
+        } else if self.database.track_count() > 0 {
+            let last_played = self
+                .history
+                .last()
+                .and_then(|(path, _)| self.database.index_of(path));
+            self.play_from_library(last_played.unwrap_or(0));
+            self.select_current();
+        } else if self.scan.is_some() {
+            // An empty cache fills in as the first scan goes
+            return false;
+        }
+        self.autoplay_pending = false;
+        true
     }
 
-    /// Play selected track
-    pub fn play_selected(&mut self) {
-        let tracks = self.database.get_tracks();
-        if let Some(_track) = tracks.get(self.list_state) {
-            self.queue.clear();
-            self.queue.extend_from_slice(tracks);
-            self.played_indices.clear();
-            self.play_track(self.list_state);
+    /// Announce the playing track on the desktop once it has played for a
+    /// moment, so skipping through tracks doesn't send one for each
+    pub fn poll_notification(&mut self) {
+        const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+        match self.notify_at {
+            Some(at) if at.elapsed() >= SETTLE_TIME => {}
+            _ => return,
+        }
+        self.notify_at = None;
+
+        if !self.config.notifications.enabled || !self.is_playing {
+            return;
+        }
+        if let Some(track) = self.get_current_track() {
+            notification::now_playing(track, self.config.notifications.cover_art);
         }
     }
 
-    /// Clear queue
-    pub fn clear_queue(&mut self) {
-        self.queue.clear();
-        self.current_track_index = None;
-        self.audio.stop();
-        self.is_playing = false;
-        self.played_indices.clear();
+    /// Silence output, or restore the volume it had before muting
+    pub fn toggle_mute(&mut self) {
+        self.muted = self.audio.toggle_mute();
     }
 
-    /// Check if should advance to next track
-    pub fn should_advance(&self) -> bool {
-        self.is_playing && self.audio.is_empty() && !self.queue.is_empty()
+    fn unmute(&mut self) {
+        if self.muted {
+            self.toggle_mute();
+        }
     }
 
-    /// Update visualizer data
-    pub fn update_visualizer(&mut self) {
-        if self.is_playing {
-            // Get audio samples and pass to visualizer
-            let sample_buffer = self.audio.get_sample_buffer();
-            let samples = sample_buffer.lock().unwrap();
+    /// Scroll up in list, wrapping to the bottom if enabled
+    pub fn scroll_up(&mut self) {
+        if self.config.library.wrap_around && *self.active_selection() == 0 {
+            self.select_last();
+        } else {
+            self.scroll_up_by(1);
+        }
+    }
 
-            // Copy samples to visualizer's buffer
-            let viz_buffer = self.visualizer.get_audio_buffer();
-            let mut viz_buf = viz_buffer.lock().unwrap();
-            viz_buf.extend_from_slice(&samples);
-            drop(viz_buf);
+    /// Scroll down in list, wrapping to the top if enabled
+    pub fn scroll_down(&mut self) {
+        let at_end = *self.active_selection() + 1 >= self.active_list_len();
+        if self.config.library.wrap_around && at_end {
+            self.select_first();
+        } else {
+            self.scroll_down_by(1);
+        }
+    }
 
-            // Clear audio player's buffer after copying
-            drop(samples);
-            sample_buffer.lock().unwrap().clear();
+    /// Add a typed digit to the count for the next movement or seek
+    pub fn push_count_digit(&mut self, digit: u32) {
+        const MAX_COUNT: u32 = 9999;
 
-            // Update visualizer with FFT
-            self.visualizer.update();
+        // A leading zero means nothing
+        if self.pending_count.is_none() && digit == 0 {
+            return;
         }
+        let count = self.pending_count.unwrap_or(0) * 10 + digit;
+        self.pending_count = Some(count.min(MAX_COUNT));
     }
 
-    /// Check if needs redraw
-    pub fn needs_redraw(&self) -> bool {
-        self.needs_redraw
+    /// Use up the pending count, if one was typed
+    pub fn take_count(&mut self) -> Option<u32> {
+        self.pending_count.take()
     }
 
-    /// Mark that redraw is needed
-    pub fn mark_needs_redraw(&mut self) {
-        self.needs_redraw = true;
+    /// Jump to the top of the list
+    pub fn select_first(&mut self) {
+        *self.active_selection() = 0;
     }
 
-    /// Clear redraw flag
-    pub fn clear_redraw_flag(&mut self) {
-        self.needs_redraw = false;
+    /// Jump to the bottom of the list
+    pub fn select_last(&mut self) {
+        let last = self.active_list_len().saturating_sub(1);
+        *self.active_selection() = last;
     }
 
-    /// Get current track
-    pub fn get_current_track(&self) -> Option<&Track> {
-        self.current_track_index.and_then(|i| self.queue.get(i))
+    /// Scroll up by one screen of the list
+    pub fn page_up(&mut self) {
+        self.scroll_up_by(self.page_size.max(1));
     }
 
-    /// Seek forward by 10 seconds
-    pub fn seek_forward(&mut self) {
-        let current = self.audio.get_elapsed_millis();
-        let new_pos = current + 10_000;
-        self.audio.seek_to(new_pos).unwrap(); // <- updated
+    /// Scroll down by one screen of the list
+    pub fn page_down(&mut self) {
+        self.scroll_down_by(self.page_size.max(1));
     }
 
-    pub fn seek_backward(&mut self) {
-        let current = self.audio.get_elapsed_millis();
-        let new_pos = current.saturating_sub(10_000);
-        self.audio.seek_to(new_pos).unwrap(); // <- updated
+    fn scroll_up_by(&mut self, rows: usize) {
+        let selected = self.active_selection();
+        *selected = selected.saturating_sub(rows);
     }
 
-    /// Toggle help menu visibility
-    pub fn toggle_help(&mut self) {
-        self.show_help = !self.show_help;
+    fn scroll_down_by(&mut self, rows: usize) {
+        let max = self.active_list_len().saturating_sub(1);
+        let selected = self.active_selection();
+        *selected = (*selected + rows).min(max);
+    }
+
+    /// Selected row of the list keys act on: queue, library tree or flat library
+    fn active_selection(&mut self) -> &mut usize {
+        if self.queue_view_active() {
+            &mut self.queue_selected
+        } else if self.filter_active {
+            &mut self.filter_selected
+        } else if self.browse_mode == BrowseMode::Tree {
+            &mut self.tree_selected
+        } else if self.browse_mode == BrowseMode::MostPlayed {
+            &mut self.most_played_selected
+        } else {
+            &mut self.list_state
+        }
+    }
+
+    /// Number of rows in the list keys act on
+    fn active_list_len(&self) -> usize {
+        if self.queue_view_active() {
+            self.queue.len()
+        } else if self.filter_active {
+            self.search_results.len()
+        } else if self.browse_mode == BrowseMode::Tree {
+            self.tree_rows().len()
+        } else if self.browse_mode == BrowseMode::MostPlayed {
+            self.database.most_played().len()
+        } else {
+            self.database.track_count()
+        }
+    }
+
+    /// Move the selection to the playing track in the list keys act on,
+    /// expanding its artist and album in the tree
+    pub fn select_current(&mut self) {
+        let Some(path) = self.get_current_track().map(|t| t.path.clone()) else {
+            return;
+        };
+        if self.queue_view_active() {
+            self.queue_selected = self.current_track_index.unwrap_or(0);
+            return;
+        }
+
+        // Find the track's row in whichever view is showing
+        let row = self.database.index_of(&path).and_then(|index| {
+            if self.filter_active {
+                self.search_results.iter().position(|&i| i == index)
+            } else if self.browse_mode == BrowseMode::Tree {
+                for key in browse::group_keys(&self.database.get_tracks()[index]) {
+                    self.collapsed.remove(&key);
+                }
+                self.tree_rows()
+                    .iter()
+                    .position(|row| *row == TreeRow::Track(index))
+            } else if self.browse_mode == BrowseMode::MostPlayed {
+                self.database.most_played().iter().position(|&i| i == index)
+            } else {
+                Some(index)
+            }
+        });
+
+        match row {
+            Some(row) => *self.active_selection() = row,
+            None => self.set_notice("The playing track isn't in this list".to_string()),
+        }
+    }
+
+    /// Show or hide the queue panel, focusing it when shown
+    pub fn toggle_queue_view(&mut self) {
+        self.show_queue = !self.show_queue;
+        self.queue_focused = self.show_queue;
+        if self.show_queue {
+            self.queue_selected = self.current_track_index.unwrap_or(0);
+        }
+    }
+
+    /// Move focus between the library and the queue panel
+    pub fn toggle_queue_focus(&mut self) {
+        if self.show_queue {
+            self.queue_focused = !self.queue_focused;
+        }
+    }
+
+    /// Paths of the tracks picked into the queue; empty while it's just the
+    /// whole library being played through
+    pub fn queued_paths(&self) -> HashSet<&Path> {
+        if self.queue_is_library {
+            return HashSet::new();
+        }
+        self.queue.iter().map(|t| t.path.as_path()).collect()
+    }
+
+    /// Whether keys act on the queue panel (search always acts on the library)
+    pub fn queue_view_active(&self) -> bool {
+        self.show_queue && self.queue_focused && !self.search_mode
+    }
+
+    /// Whether keys act on the library tree
+    pub fn tree_focused(&self) -> bool {
+        self.browse_mode == BrowseMode::Tree && !self.queue_view_active() && !self.filter_active
+    }
+
+    /// Append the selected library track, album or artist to the queue
+    pub fn enqueue_selected(&mut self) {
+        if self.queue_view_active() {
+            return;
+        }
+
+        let tracks = self.database.get_tracks();
+        let indices = if self.filter_active {
+            self.search_results
+                .get(self.filter_selected)
+                .map(|&i| vec![i])
+                .unwrap_or_default()
+        } else if self.browse_mode == BrowseMode::Tree && !self.search_mode {
+            match self.tree_rows().get(self.tree_selected) {
+                Some(TreeRow::Artist { name, .. }) => browse::artist_tracks(tracks, name),
+                Some(TreeRow::Album { artist, name, .. }) => {
+                    browse::album_tracks(tracks, artist, name)
+                }
+                Some(&TreeRow::Track(i)) => vec![i],
+                None => Vec::new(),
+            }
+        } else if self.browse_mode == BrowseMode::MostPlayed && !self.search_mode {
+            self.database
+                .most_played()
+                .get(self.most_played_selected)
+                .map(|&i| vec![i])
+                .unwrap_or_default()
+        } else if self.list_state < tracks.len() {
+            vec![self.list_state]
+        } else {
+            Vec::new()
+        };
+
+        if indices.is_empty() {
+            return;
+        }
+        let added: Vec<Track> = indices.iter().map(|&i| tracks[i].clone()).collect();
+        let message = match added.as_slice() {
+            [track] => format!("Queued {}", track.title),
+            _ => format!("Queued {} tracks", added.len()),
+        };
+        self.queue.extend(added);
+        self.set_notice(message);
+    }
+
+    /// Remove the selected entry from the queue, stopping it if it's playing
+    pub fn remove_from_queue(&mut self) {
+        let i = self.queue_selected;
+        if !self.queue_view_active() || i >= self.queue.len() {
+            return;
+        }
+
+        self.queue.remove(i);
+        // Entries after the removed one shift down by one
+        let remap = |j: usize| if j > i { j - 1 } else { j };
+        if self.current_track_index == Some(i) {
+            self.audio.stop();
+            self.current_track_index = None;
+            self.is_playing = false;
+            self.album_art.clear();
+        } else {
+            self.current_track_index = self.current_track_index.map(remap);
+        }
+        self.played_indices.retain(|&j| j != i);
+        for played in &mut self.played_indices {
+            *played = remap(*played);
+        }
+        match self.shuffle_next {
+            Some(next) if next == i => self.pick_shuffle_next(),
+            next => self.shuffle_next = next.map(remap),
+        }
+
+        self.queue_selected = i.min(self.queue.len().saturating_sub(1));
+    }
+
+    /// Queue index of the track that plays when the current one ends, if
+    /// anything does: the current track again while looping, otherwise the
+    /// next playable track in order or the shuffle pick
+    pub fn up_next(&self) -> Option<usize> {
+        if self.stop_after_current || self.queue.is_empty() {
+            return None;
+        }
+        if self.loopC {
+            return self.current_track_index;
+        }
+        if self.shuffle {
+            return self.shuffle_next;
+        }
+
+        let start = self.current_track_index.map_or(0, |i| i + 1);
+        (0..self.queue.len())
+            .map(|step| (start + step) % self.queue.len())
+            .find(|&i| !self.unplayable.contains(&self.queue[i].path))
+    }
+
+    /// Total length of the queue (tracks of unknown length count as zero)
+    pub fn queue_duration(&self) -> Duration {
+        Duration::from_secs(self.queue.iter().filter_map(|t| t.duration).sum())
+    }
+
+    /// Time until the queue has played through: the rest of the current
+    /// track plus every track still to come
+    pub fn queue_remaining(&self) -> Duration {
+        let Some(current) = self.current_track_index else {
+            return self.queue_duration();
+        };
+
+        let upcoming: u64 = self
+            .queue
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| {
+                if self.shuffle {
+                    !self.played_indices.contains(&i)
+                } else {
+                    i > current
+                }
+            })
+            .filter_map(|(_, t)| t.duration)
+            .sum();
+        let left_in_current = (self.get_duration_seconds() - self.get_elapsed_seconds()).max(0.0);
+        Duration::from_secs(upcoming) + Duration::from_secs_f32(left_in_current)
+    }
+
+    /// Move the selected queue entry one place earlier
+    pub fn move_track_up(&mut self) {
+        let i = self.queue_selected;
+        if self.queue_view_active() && i > 0 && i < self.queue.len() {
+            self.swap_queue_entries(i - 1, i);
+            self.queue_selected -= 1;
+        }
+    }
+
+    /// Move the selected queue entry one place later
+    pub fn move_track_down(&mut self) {
+        let i = self.queue_selected;
+        if self.queue_view_active() && i + 1 < self.queue.len() {
+            self.swap_queue_entries(i, i + 1);
+            self.queue_selected += 1;
+        }
+    }
+
+    /// Swap two queue entries, keeping indices pointing at the same tracks
+    fn swap_queue_entries(&mut self, a: usize, b: usize) {
+        self.queue.swap(a, b);
+
+        let remap = |i: usize| {
+            if i == a {
+                b
+            } else if i == b {
+                a
+            } else {
+                i
+            }
+        };
+        self.current_track_index = self.current_track_index.map(remap);
+        for played in &mut self.played_indices {
+            *played = remap(*played);
+        }
+        self.shuffle_next = self.shuffle_next.map(remap);
+    }
+
+    /// Cycle between the flat list, the artist/album tree and most played
+    pub fn toggle_browse_mode(&mut self) {
+        self.browse_mode = self.browse_mode.next();
+        self.most_played_selected = 0;
+    }
+
+    /// Visible rows of the library tree
+    pub fn tree_rows(&self) -> Vec<TreeRow> {
+        browse::build_rows(self.database.get_tracks(), &self.collapsed)
+    }
+
+    /// Collapse the selected header, or the album of the selected track
+    pub fn collapse_selected(&mut self) {
+        let rows = self.tree_rows();
+        let Some(row) = rows.get(self.tree_selected) else {
+            return;
+        };
+
+        let header = match row {
+            TreeRow::Track(i) => {
+                let track = &self.database.get_tracks()[*i];
+                let (artist, album) = (browse::artist_of(track), browse::album_of(track));
+                // Move the cursor up to the album header being collapsed
+                rows[..self.tree_selected]
+                    .iter()
+                    .rposition(|r| match r {
+                        TreeRow::Album {
+                            artist: a, name, ..
+                        } => a == artist && name == album,
+                        _ => false,
+                    })
+                    .map(|pos| (pos, rows[pos].clone()))
+            }
+            _ => Some((self.tree_selected, row.clone())),
+        };
+
+        if let Some((pos, header)) = header {
+            if let Some(key) = header.group_key() {
+                self.collapsed.insert(key);
+            }
+            self.tree_selected = pos;
+        }
+    }
+
+    /// Expand the selected header
+    pub fn expand_selected(&mut self) {
+        if let Some(key) = self
+            .tree_rows()
+            .get(self.tree_selected)
+            .and_then(|r| r.group_key())
+        {
+            self.collapsed.remove(&key);
+        }
+    }
+
+    /// Queue the given library tracks in order and start at `start`
+    fn play_tracks(&mut self, indices: &[usize], start: usize) {
+        let tracks = self.database.get_tracks();
+        let queue = indices.iter().map(|&i| tracks[i].clone()).collect();
+        self.replace_queue(queue);
+        self.play_track(start);
+    }
+
+    /// Swap in a new queue. The shuffle cycle starts over unless it holds the
+    /// same tracks as before, so picking another track to play from the same
+    /// list doesn't bring back the ones already heard
+    fn replace_queue(&mut self, queue: Vec<Track>) {
+        let same = queue.len() == self.queue.len()
+            && queue.iter().zip(&self.queue).all(|(a, b)| a.path == b.path);
+        if !same {
+            self.played_indices.clear();
+            self.shuffle_next = None;
+        }
+        self.queue = queue;
+        self.queue_is_library = false;
+        self.failed_in_a_row = 0;
+    }
+
+    /// Activate the selected tree row: toggle artists, play albums from the top
+    /// or from the selected track
+    fn play_selected_tree_row(&mut self) {
+        let Some(row) = self.tree_rows().get(self.tree_selected).cloned() else {
+            return;
+        };
+
+        match &row {
+            TreeRow::Artist { collapsed, .. } => {
+                if let Some(key) = row.group_key() {
+                    if *collapsed {
+                        self.collapsed.remove(&key);
+                    } else {
+                        self.collapsed.insert(key);
+                    }
+                }
+            }
+            TreeRow::Album { artist, name, .. } => {
+                let album = browse::album_tracks(self.database.get_tracks(), artist, name);
+                self.play_tracks(&album, 0);
+            }
+            &TreeRow::Track(i) => {
+                let tracks = self.database.get_tracks();
+                let track = &tracks[i];
+                let album =
+                    browse::album_tracks(tracks, browse::artist_of(track), browse::album_of(track));
+                let start = album.iter().position(|&j| j == i).unwrap_or(0);
+                self.play_tracks(&album, start);
+            }
+        }
+    }
+
+    /// Play selected track
+    pub fn play_selected(&mut self) {
+        if self.queue_view_active() {
+            self.play_track(self.queue_selected);
+            return;
+        }
+        if self.filter_active {
+            // Queue just the filtered tracks
+            if self.filter_selected < self.search_results.len() {
+                self.play_tracks(&self.search_results.clone(), self.filter_selected);
+            }
+            return;
+        }
+        if self.browse_mode == BrowseMode::Tree && !self.search_mode {
+            self.play_selected_tree_row();
+            return;
+        }
+        if self.browse_mode == BrowseMode::MostPlayed && !self.search_mode {
+            // Queue the most played list in its order
+            let rows = self.database.most_played();
+            if self.most_played_selected < rows.len() {
+                self.play_tracks(&rows, self.most_played_selected);
+            }
+            return;
+        }
+
+        self.play_from_library(self.list_state);
+    }
+
+    /// Queue the whole library and play a track picked at random from it,
+    /// without touching the shuffle setting
+    pub fn play_random(&mut self) {
+        let count = self.database.track_count();
+        if count == 0 {
+            self.set_notice("The library is empty".to_string());
+            return;
+        }
+        self.play_from_library(thread_rng().gen_range(0..count));
+        self.select_current();
+    }
+
+    /// Queue the whole library and play the track at `index`
+    fn play_from_library(&mut self, index: usize) {
+        let tracks = self.database.get_tracks();
+        if index < tracks.len() {
+            self.replace_queue(tracks.to_vec());
+            self.queue_is_library = true;
+            self.play_track(index);
+        }
+    }
+
+    /// File the play history is persisted to
+    fn history_path() -> PathBuf {
+        MusicDatabase::cache_dir().join("history.json")
+    }
+
+    /// Load the persisted play history (empty if missing or unreadable)
+    fn load_history() -> Vec<(PathBuf, SystemTime)> {
+        fs::read_to_string(Self::history_path())
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+
+    /// Persist the play history, ignoring write errors
+    fn save_history(&self) {
+        if let Ok(data) = serde_json::to_string(&self.history) {
+            let _ = fs::write(Self::history_path(), data);
+        }
+    }
+
+    fn session_path() -> PathBuf {
+        MusicDatabase::cache_dir().join("state.json")
+    }
+
+    /// Load what the last session saved, if anything
+    fn load_session() -> Option<Session> {
+        fs::read_to_string(Self::session_path())
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+    }
+
+    /// Remember the volume, mute state, a cycled library order and a toggled
+    /// visualizer for the next launch, ignoring write errors
+    pub fn save_session(&self) {
+        let cycled = self.sort_key != self.config.library.sort_key
+            || self.sort_descending != self.config.library.sort_descending;
+        let session = Session {
+            volume: self.volume,
+            muted: self.muted,
+            sort_key: cycled.then_some(self.sort_key),
+            sort_descending: self.sort_descending,
+            show_visualizer: (self.show_visualizer != self.config.visualizer.enabled)
+                .then_some(self.show_visualizer),
+        };
+        if let Ok(data) = serde_json::to_string(&session) {
+            let _ = fs::write(Self::session_path(), data);
+        }
+    }
+
+    /// Record a played track, capped at the configured length
+    fn push_history(&mut self, path: PathBuf) {
+        // Looping the same track doesn't add entries
+        if self.history.last().map(|(p, _)| p) == Some(&path) {
+            return;
+        }
+
+        self.history.push((path, SystemTime::now()));
+        let max = self.config.history.max_entries;
+        if self.history.len() > max {
+            let excess = self.history.len() - max;
+            self.history.drain(..excess);
+        }
+        self.save_history();
+    }
+
+    /// Open or close the history panel
+    pub fn toggle_history(&mut self) {
+        self.show_history = !self.show_history;
+        self.history_selected = 0;
+    }
+
+    /// Move up in the history panel (towards the most recent)
+    pub fn history_up(&mut self) {
+        self.history_selected = self.history_selected.saturating_sub(1);
+    }
+
+    /// Move down in the history panel (towards older entries)
+    pub fn history_down(&mut self) {
+        if self.history_selected + 1 < self.history.len() {
+            self.history_selected += 1;
+        }
+    }
+
+    /// Replay the selected history entry if it's still in the library
+    pub fn play_history_selected(&mut self) {
+        // The panel lists the most recent entry first
+        let Some((path, _)) = self.history.iter().rev().nth(self.history_selected) else {
+            return;
+        };
+
+        if let Some(index) = self.database.index_of(path) {
+            self.list_state = index;
+            self.play_from_library(index);
+        }
+        self.show_history = false;
+    }
+
+    /// Replace the queue with a playlist file and start playing it
+    pub fn load_playlist(&mut self, path: &Path) -> anyhow::Result<()> {
+        let loaded = playlist::load(path, self.database.get_tracks())?;
+
+        let mut message = format!("Loaded {} tracks", loaded.tracks.len());
+        if loaded.missing > 0 {
+            message.push_str(&format!(", skipped {} missing", loaded.missing));
+        }
+        self.set_notice(message);
+
+        self.replace_queue(loaded.tracks);
+        self.current_track_index = None;
+        self.play_track(0);
+        Ok(())
+    }
+
+    /// Write the queue to the configured playlist file
+    pub fn save_playlist(&mut self) {
+        let path = PathBuf::from(&*shellexpand::tilde(&self.config.playlist.save_path));
+        let message = match playlist::save(&path, &self.queue) {
+            Ok(()) => format!("Saved {} tracks to {}", self.queue.len(), path.display()),
+            Err(e) => format!("Couldn't save playlist: {:#}", e),
+        };
+        self.set_notice(message);
+    }
+
+    /// Move output to the next available device
+    pub fn cycle_output_device(&mut self) {
+        let devices = self.audio.output_devices();
+        if devices.is_empty() {
+            self.set_notice("No output devices found".to_string());
+            return;
+        }
+
+        let next = self
+            .audio
+            .device_name()
+            .and_then(|current| devices.iter().position(|d| d == current))
+            .map_or(0, |i| (i + 1) % devices.len());
+        self.switch_output_device(Some(&devices[next]));
+    }
+
+    /// Reopen audio on another device, resuming the current track where it was
+    fn switch_output_device(&mut self, device: Option<&str>) {
+        let position = self.audio.get_elapsed_millis();
+
+        if let Err(e) = self.audio.set_output_device(device) {
+            self.set_notice(format!("Couldn't switch output: {}", e));
+            return;
+        }
+        self.set_notice(format!("Output: {}", device.unwrap_or("default device")));
+
+        let Some(path) = self.get_current_track().map(|t| t.path.clone()) else {
+            return;
+        };
+        if self.audio.play(&path).is_ok() {
+            let _ = self.audio.seek_to(position);
+            if !self.is_playing {
+                self.audio.pause();
+            }
+        }
+    }
+
+    /// Show a status message in the title bar for a few seconds
+    pub fn set_notice(&mut self, message: String) {
+        self.notice = Some((message, Instant::now()));
+    }
+
+    /// Current status message, if it hasn't expired
+    pub fn notice(&self) -> Option<&str> {
+        const NOTICE_DURATION: Duration = Duration::from_secs(5);
+
+        self.notice
+            .as_ref()
+            .filter(|(_, shown)| shown.elapsed() < NOTICE_DURATION)
+            .map(|(message, _)| message.as_str())
+    }
+
+    /// Clear queue
+    pub fn clear_queue(&mut self) {
+        self.queue.clear();
+        self.queue_is_library = false;
+        self.current_track_index = None;
+        self.shuffle_next = None;
+        self.audio.stop();
+        self.is_playing = false;
+        self.played_indices.clear();
+        self.failed_in_a_row = 0;
+        self.album_art.clear();
+    }
+
+    /// Check if should advance to next track
+    pub fn should_advance(&self) -> bool {
+        self.is_playing
+            && !self.queue.is_empty()
+            && (self.audio.is_finished() || self.current_track_failed())
+    }
+
+    /// Move on from the finished track: stop once if asked to, replay it
+    /// when looping, otherwise play the next one
+    pub fn advance(&mut self) {
+        if self.stop_after_current {
+            // Stop here once; play picks up with the next track
+            self.stop_after_current = false;
+            self.is_playing = false;
+        } else if self.loopC && !self.current_track_failed() {
+            // Replay same track
+            if let Some(idx) = self.current_track_index {
+                self.play_track(idx);
+            }
+        } else {
+            self.next_track();
+        }
+    }
+
+    /// Update visualizer data
+    pub fn update_visualizer(&mut self) {
+        // Hold the last frame while paused; once stopped the bars decay away
+        if !self.is_playing && self.audio.is_paused() {
+            return;
+        }
+        // No FFT for a hidden panel
+        if !self.show_visualizer {
+            return;
+        }
+
+        // Update visualizer with FFT of the newest samples
+        self.visualizer.update();
+    }
+
+    /// Nothing on screen moves by itself: paused, or stopped with the
+    /// visualizer fully decayed or hidden
+    pub fn is_idle(&self) -> bool {
+        !self.is_playing
+            && (self.audio.is_paused() || !self.show_visualizer || self.visualizer.is_settled())
+    }
+
+    /// Check if needs redraw
+    pub fn needs_redraw(&self) -> bool {
+        self.needs_redraw
+    }
+
+    /// Mark that redraw is needed
+    pub fn mark_needs_redraw(&mut self) {
+        self.needs_redraw = true;
+    }
+
+    /// Clear redraw flag
+    pub fn clear_redraw_flag(&mut self) {
+        self.needs_redraw = false;
+    }
+
+    /// Get current track
+    pub fn get_current_track(&self) -> Option<&Track> {
+        self.current_track_index.and_then(|i| self.queue.get(i))
+    }
+
+    /// Whether a library track is the one currently loaded
+    pub fn is_current_track(&self, track: &Track) -> bool {
+        self.get_current_track()
+            .is_some_and(|current| current.path == track.path)
+    }
+
+    /// Seek forward by `step`
+    pub fn seek_forward(&mut self, step: Duration) {
+        if let Err(e) = self.audio.seek_forward(step) {
+            self.set_notice(format!("Couldn't seek: {}", e));
+        }
+    }
+
+    /// Seek backward by `step`
+    pub fn seek_backward(&mut self, step: Duration) {
+        if let Err(e) = self.audio.seek_backward(step) {
+            self.set_notice(format!("Couldn't seek: {}", e));
+        }
+    }
+
+    /// Mark the current position as the start of the A-B loop
+    pub fn mark_loop_a(&mut self) {
+        if self.current_track_index.is_none() {
+            return;
+        }
+        let position = Duration::from_millis(self.audio.get_elapsed_millis());
+        // A new A after the old B starts a fresh loop
+        if self.loop_b.is_some_and(|b| b <= position) {
+            self.loop_b = None;
+        }
+        self.loop_a = Some(position);
+    }
+
+    /// Mark the current position as the end of the A-B loop
+    pub fn mark_loop_b(&mut self) {
+        if self.current_track_index.is_none() {
+            return;
+        }
+        let position = Duration::from_millis(self.audio.get_elapsed_millis());
+        if self.loop_a.is_some_and(|a| position <= a) {
+            self.set_notice("Loop end must come after its start".to_string());
+            return;
+        }
+        self.loop_b = Some(position);
+    }
+
+    /// Forget both loop points
+    pub fn clear_loop(&mut self) {
+        self.loop_a = None;
+        self.loop_b = None;
+    }
+
+    /// Section being repeated; without an A point it starts at the beginning
+    pub fn loop_region(&self) -> Option<(Duration, Duration)> {
+        self.loop_b.map(|b| (self.loop_a.unwrap_or_default(), b))
+    }
+
+    /// Seek back to A once playback reaches B, returning whether it did
+    pub fn check_ab_loop(&mut self) -> bool {
+        let Some((a, b)) = self.loop_region() else {
+            return false;
+        };
+        if !self.is_playing || self.audio.get_elapsed_millis() < b.as_millis() as u64 {
+            return false;
+        }
+        let _ = self.audio.seek_to(a.as_millis() as u64);
+        true
+    }
+
+    /// Count the current track as played once it's past the halfway mark,
+    /// returning whether it was just counted
+    pub fn check_play_count(&mut self) -> bool {
+        const PLAYED_FRACTION: f32 = 0.5;
+
+        let duration = self.get_duration_seconds();
+        if self.play_counted
+            || !self.is_playing
+            || duration <= 0.0
+            || self.get_elapsed_seconds() < duration * PLAYED_FRACTION
+        {
+            return false;
+        }
+        let Some(path) = self.get_current_track().map(|t| t.path.clone()) else {
+            return false;
+        };
+
+        self.play_counted = true;
+        if let Err(e) = self.database.record_play(&path) {
+            self.set_notice(format!("Couldn't save the play count: {}", e));
+        }
+        true
+    }
+
+    /// Switch the equalizer on or off
+    pub fn toggle_equalizer(&mut self) {
+        let enabled = self.audio.toggle_equalizer();
+        self.set_notice(format!("Equalizer {}", if enabled { "on" } else { "off" }));
+    }
+
+    /// Open the sleep timer prompt
+    pub fn start_sleep_prompt(&mut self) {
+        self.sleep_prompt = Some(String::new());
+    }
+
+    pub fn cancel_sleep_prompt(&mut self) {
+        self.sleep_prompt = None;
+    }
+
+    /// Type into the sleep timer prompt (minutes, or `t` for end of track)
+    pub fn sleep_prompt_add_char(&mut self, c: char) {
+        // Five digits is over two months, plenty for a sleep timer
+        const MAX_LEN: usize = 5;
+
+        if let Some(input) = &mut self.sleep_prompt {
+            if (c.is_ascii_digit() || c == 't') && input.len() < MAX_LEN {
+                input.push(c);
+            }
+        }
+    }
+
+    pub fn sleep_prompt_backspace(&mut self) {
+        if let Some(input) = &mut self.sleep_prompt {
+            input.pop();
+        }
+    }
+
+    /// Start, or with empty input cancel, the sleep timer
+    pub fn submit_sleep_prompt(&mut self) {
+        let Some(input) = self.sleep_prompt.take() else {
+            return;
+        };
+
+        if input.contains('t') {
+            self.sleep_at = None;
+            self.stop_after_current = true;
+            self.set_notice("Stopping after this track".to_string());
+            return;
+        }
+        match input.parse::<u64>() {
+            Ok(minutes) if minutes > 0 => {
+                let at = minutes
+                    .checked_mul(60)
+                    .and_then(|secs| Instant::now().checked_add(Duration::from_secs(secs)));
+                match at {
+                    Some(at) => {
+                        self.sleep_at = Some(at);
+                        self.set_notice(format!("Sleeping in {} min", minutes));
+                    }
+                    None => self.set_notice("Sleep timer too long".to_string()),
+                }
+            }
+            Err(_) if !input.is_empty() => self.set_notice("Sleep timer too long".to_string()),
+            _ => self.cancel_sleep_timer(),
+        }
+    }
+
+    /// Turn the sleep timer off, restoring any partial fade
+    pub fn cancel_sleep_timer(&mut self) {
+        if self.sleep_at.take().is_some() {
+            self.audio.set_fade(1.0);
+            self.set_notice("Sleep timer off".to_string());
+        }
+    }
+
+    /// Time left on the sleep timer, if one is running
+    pub fn sleep_remaining(&self) -> Option<Duration> {
+        self.sleep_at
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Fade out near the sleep time and pause when it's reached, returning
+    /// whether playback was stopped
+    pub fn check_sleep_timer(&mut self) -> bool {
+        const FADE_DURATION: Duration = Duration::from_secs(10);
+
+        let Some(remaining) = self.sleep_remaining() else {
+            return false;
+        };
+        if !remaining.is_zero() {
+            if remaining < FADE_DURATION {
+                self.audio
+                    .set_fade(remaining.as_secs_f32() / FADE_DURATION.as_secs_f32());
+            }
+            return false;
+        }
+
+        self.sleep_at = None;
+        if self.is_playing {
+            self.audio.pause();
+            self.is_playing = false;
+        }
+        self.audio.set_fade(1.0);
+        true
+    }
+
+    /// Switch to the next visualizer mode
+    pub fn cycle_visualizer_mode(&mut self) {
+        self.visualizer_mode = self.visualizer_mode.next();
+    }
+
+    /// Show or hide the visualizer panel; the session remembers the choice
+    pub fn toggle_visualizer(&mut self) {
+        self.show_visualizer = !self.show_visualizer;
+    }
+
+    /// Toggle help menu visibility
+    pub fn toggle_help(&mut self) {
+        self.show_help = !self.show_help;
+    }
+
+    /// Open the track info popup for the playing track (or the selected one
+    /// when nothing is playing), or close it
+    pub fn toggle_track_info(&mut self) {
+        if self.track_info.take().is_some() {
+            return;
+        }
+
+        let Some(track) = self.get_current_track().or(self.selected_track()).cloned() else {
+            self.set_notice("No track to show".to_string());
+            return;
+        };
+        let stream = if self.is_current_track(&track) {
+            self.audio.stream_format()
+        } else {
+            None
+        };
+        self.track_info = Some(TrackInfo {
+            codec: metadata::read_codec(&track.path),
+            track,
+            stream,
+        });
     }
 
     /// Get elapsed time in seconds
@@ -334,11 +2097,546 @@ impl PlayerState {
         (self.audio.get_elapsed_millis() as f32) / 1000.0
     }
 
-    /// Get total duration in seconds
+    /// Get total duration in seconds, from the decoder once the track is
+    /// loaded and from the library scan before that
     pub fn get_duration_seconds(&self) -> f32 {
         self.audio
             .get_duration()
             .map(|d| d.as_secs() as f32 + d.subsec_millis() as f32 / 1000.0)
+            .or_else(|| {
+                self.get_current_track()
+                    .and_then(|t| t.duration)
+                    .map(|secs| secs as f32)
+            })
             .unwrap_or(0.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::TrackTags;
+    use crate::visualizer::SampleRing;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+
+    /// Audio backend stand-in that remembers what it was asked to play; clones
+    /// share their state, so a test can keep one to look inside the player's
+    #[derive(Clone, Default)]
+    struct MockAudio {
+        playing: Rc<RefCell<Option<PathBuf>>>,
+        finished: Rc<Cell<bool>>,
+        elapsed: Rc<Cell<u64>>,
+        unseekable: Rc<Cell<bool>>, // Seeks succeed without moving, like a track of unknown length
+        samples: Arc<Mutex<SampleRing>>,
+    }
+
+    impl AudioBackend for MockAudio {
+        fn output_devices(&self) -> Vec<String> {
+            Vec::new()
+        }
+
+        fn set_output_device(&mut self, _name: Option<&str>) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn device_name(&self) -> Option<&str> {
+            None
+        }
+
+        fn device_warning(&self) -> Option<&str> {
+            None
+        }
+
+        fn device_error(&self) -> Option<&str> {
+            None
+        }
+
+        fn play(&self, path: &Path) -> anyhow::Result<()> {
+            *self.playing.borrow_mut() = Some(path.to_path_buf());
+            self.finished.set(false);
+            self.elapsed.set(0);
+            Ok(())
+        }
+
+        fn seek_to(&self, millis: u64) -> anyhow::Result<()> {
+            if !self.unseekable.get() {
+                self.elapsed.set(millis);
+            }
+            Ok(())
+        }
+
+        fn pause(&self) {}
+
+        fn resume(&self) {}
+
+        fn stop(&self) {
+            *self.playing.borrow_mut() = None;
+        }
+
+        fn get_elapsed_millis(&self) -> u64 {
+            self.elapsed.get()
+        }
+
+        fn get_duration(&self) -> Option<Duration> {
+            None
+        }
+
+        fn stream_format(&self) -> Option<StreamFormat> {
+            self.playing.borrow().as_ref().map(|_| StreamFormat {
+                channels: 2,
+                sample_rate: 44100,
+            })
+        }
+
+        fn is_paused(&self) -> bool {
+            false
+        }
+
+        fn is_finished(&self) -> bool {
+            self.finished.get()
+        }
+
+        fn get_sample_buffer(&self) -> Arc<Mutex<SampleRing>> {
+            Arc::clone(&self.samples)
+        }
+
+        fn set_volume(&self, _volume: f32) {}
+
+        fn set_gain(&self, _gain: f32) {}
+
+        fn set_fade(&self, _fade: f32) {}
+
+        fn set_equalizer(&self, _enabled: bool, _gains: &[f32]) {}
+
+        fn toggle_equalizer(&self) -> bool {
+            false
+        }
+
+        fn toggle_mute(&self) -> bool {
+            false
+        }
+    }
+
+    /// Point the cache and music directories at an empty temp dir named after
+    /// the running test, so history writes never touch the real ones or
+    /// another test's. The Music folder is never created, which keeps the
+    /// scan and watcher from doing anything.
+    fn isolate_home() {
+        let thread = std::thread::current();
+        let home = std::env::temp_dir()
+            .join("catty-player-tests")
+            .join(thread.name().unwrap_or("main").replace("::", "-"));
+        let _ = fs::remove_dir_all(&home);
+        database::set_test_home(home);
+    }
+
+    fn player_with_queue(len: usize) -> (PlayerState, MockAudio) {
+        isolate_home();
+        let audio = MockAudio::default();
+        let database = MusicDatabase::new().unwrap();
+        let mut player = PlayerState::new(database, Box::new(audio.clone()), Config::default());
+        player.queue = (0..len)
+            .map(|i| {
+                let path = PathBuf::from(format!("/music/{}.mp3", i));
+                Track::from_tags(&path, TrackTags::default(), None)
+            })
+            .collect();
+        (player, audio)
+    }
+
+    #[test]
+    fn shuffle_plays_every_track_before_repeating() {
+        let (mut player, _) = player_with_queue(6);
+        player.toggle_shuffle();
+        player.play_track(0);
+
+        let mut seen = HashSet::from([0]);
+        for _ in 1..6 {
+            assert!(player.next_track());
+            assert!(seen.insert(player.current_track_index.unwrap()));
+        }
+
+        // Once all have played a new round starts, without repeating the last one
+        let last = player.current_track_index;
+        assert!(player.next_track());
+        assert_ne!(player.current_track_index, last);
+    }
+
+    #[test]
+    fn picking_a_track_from_the_same_list_keeps_the_shuffle_cycle() {
+        let (mut player, _) = player_with_queue(4);
+        player.database.tracks = player.queue.clone();
+        player.toggle_shuffle();
+        player.play_from_library(0);
+        player.next_track();
+        let mut heard = HashSet::from([0, player.current_track_index.unwrap()]);
+
+        // Choose one of the two not heard yet by hand
+        let unplayed = (0..4).find(|i| !heard.contains(i)).unwrap();
+        player.play_from_library(unplayed);
+        heard.insert(unplayed);
+
+        player.next_track();
+        assert!(heard.insert(player.current_track_index.unwrap()));
+    }
+
+    #[test]
+    fn shuffle_turned_on_mid_track_never_picks_the_playing_one() {
+        let (mut player, _) = player_with_queue(2);
+        player.play_track(1);
+        player.toggle_shuffle();
+        assert_eq!(player.up_next(), Some(0));
+
+        player.next_track();
+        assert_eq!(player.current_track_index, Some(0));
+        // A new cycle starts without the track that just played
+        player.next_track();
+        assert_eq!(player.current_track_index, Some(1));
+    }
+
+    #[test]
+    fn next_and_previous_wrap_around_the_queue() {
+        let (mut player, audio) = player_with_queue(3);
+        player.play_track(2);
+
+        player.next_track();
+        assert_eq!(player.current_track_index, Some(0));
+        assert_eq!(
+            audio.playing.borrow().as_deref(),
+            Some(Path::new("/music/0.mp3"))
+        );
+
+        player.previous_track();
+        assert_eq!(player.current_track_index, Some(2));
+    }
+
+    #[test]
+    fn track_info_describes_the_playing_track() {
+        let (mut player, _) = player_with_queue(3);
+        player.play_track(1);
+
+        player.toggle_track_info();
+        let info = player.track_info.as_ref().unwrap();
+        assert_eq!(info.track.path, Path::new("/music/1.mp3"));
+        assert_eq!(info.stream.map(|s| s.sample_rate), Some(44100));
+        // The file doesn't exist, so there's no container to read
+        assert!(info.codec.is_none());
+
+        player.toggle_track_info();
+        assert!(player.track_info.is_none());
+    }
+
+    #[test]
+    fn previous_restarts_a_track_past_the_threshold() {
+        let (mut player, audio) = player_with_queue(3);
+        player.play_track(1);
+        audio.elapsed.set(10_000);
+
+        player.previous_track();
+        assert_eq!(player.current_track_index, Some(1));
+        assert_eq!(audio.elapsed.get(), 0);
+
+        // Pressed again right away it goes back a track
+        player.previous_track();
+        assert_eq!(player.current_track_index, Some(0));
+    }
+
+    #[test]
+    fn only_tracks_picked_into_the_queue_count_as_queued() {
+        let (mut player, _) = player_with_queue(3);
+        player.database.tracks = player.queue.clone();
+        assert_eq!(player.queued_paths().len(), 3);
+
+        // Playing from the library queues all of it, which marks nothing
+        player.play_from_library(1);
+        assert!(player.queued_paths().is_empty());
+
+        player.play_tracks(&[2], 0);
+        let queued = player.queued_paths();
+        assert_eq!(queued.len(), 1);
+        assert!(queued.contains(Path::new("/music/2.mp3")));
+    }
+
+    #[test]
+    fn huge_sleep_timer_is_refused_without_panicking() {
+        let (mut player, _) = player_with_queue(1);
+        for input in [u64::MAX.to_string(), "99999999999999999999999".to_string()] {
+            player.sleep_prompt = Some(input);
+            player.submit_sleep_prompt();
+            assert!(player.sleep_remaining().is_none());
+            assert_eq!(player.notice(), Some("Sleep timer too long"));
+        }
+
+        // Typing stops at five digits
+        player.start_sleep_prompt();
+        for _ in 0..30 {
+            player.sleep_prompt_add_char('9');
+        }
+        assert_eq!(player.sleep_prompt.as_deref(), Some("99999"));
+        player.submit_sleep_prompt();
+        assert!(player.sleep_remaining().is_some());
+    }
+
+    #[test]
+    fn cycling_the_sort_leaves_the_config_alone() {
+        let (mut player, _) = player_with_queue(0);
+        player.cycle_sort();
+        assert!(player.sort_descending);
+        player.cycle_sort();
+        assert_eq!(player.sort_key, SortKey::Artist);
+        assert!(!player.sort_descending);
+        assert_eq!(player.config.library.sort_key, SortKey::Title);
+        assert!(!player.config.library.sort_descending);
+    }
+
+    #[test]
+    fn previous_goes_back_when_the_track_cannot_restart() {
+        let (mut player, audio) = player_with_queue(3);
+        player.play_track(1);
+        audio.elapsed.set(10_000);
+        audio.unseekable.set(true);
+
+        player.previous_track();
+        assert_eq!(player.current_track_index, Some(0));
+    }
+
+    #[test]
+    fn huge_restart_threshold_never_restarts() {
+        let (mut player, audio) = player_with_queue(3);
+        player.config.playback.previous_restart_seconds = u64::MAX;
+        player.play_track(1);
+        audio.elapsed.set(10_000);
+
+        player.previous_track();
+        assert_eq!(player.current_track_index, Some(0));
+    }
+
+    #[test]
+    fn clear_queue_resets_playback() {
+        let (mut player, audio) = player_with_queue(3);
+        player.toggle_shuffle();
+        player.play_track(1);
+
+        player.clear_queue();
+        assert!(player.queue.is_empty());
+        assert_eq!(player.current_track_index, None);
+        assert!(!player.is_playing);
+        assert!(player.played_indices.is_empty());
+        assert!(audio.playing.borrow().is_none());
+        assert!(!player.should_advance());
+    }
+
+    #[test]
+    fn finished_track_advances_to_the_next() {
+        let (mut player, audio) = player_with_queue(3);
+        player.play_track(0);
+        assert!(!player.should_advance());
+
+        audio.finished.set(true);
+        assert!(player.should_advance());
+        player.advance();
+        assert_eq!(player.current_track_index, Some(1));
+        assert!(player.is_playing);
+    }
+
+    #[test]
+    fn finished_track_repeats_while_looping() {
+        let (mut player, audio) = player_with_queue(3);
+        player.loopC = true;
+        player.play_track(1);
+
+        audio.finished.set(true);
+        player.advance();
+        assert_eq!(player.current_track_index, Some(1));
+        assert!(!audio.finished.get());
+    }
+
+    #[test]
+    fn stop_after_current_stops_once() {
+        let (mut player, audio) = player_with_queue(3);
+        player.stop_after_current = true;
+        player.play_track(0);
+
+        audio.finished.set(true);
+        player.advance();
+        assert!(!player.is_playing);
+        assert!(!player.stop_after_current);
+        assert!(!player.should_advance());
+
+        // The next play goes on as normal
+        player.toggle_playback();
+        player.advance();
+        assert_eq!(player.current_track_index, Some(1));
+    }
+
+    #[test]
+    fn up_next_is_what_plays_next() {
+        let (mut player, _) = player_with_queue(4);
+        player.play_track(3);
+        assert_eq!(player.up_next(), Some(0));
+
+        player.toggle_shuffle();
+        for _ in 0..8 {
+            let expected = player.up_next();
+            assert!(expected.is_some());
+            player.next_track();
+            assert_eq!(player.current_track_index, expected);
+        }
+
+        player.loopC = true;
+        assert_eq!(player.up_next(), player.current_track_index);
+        player.stop_after_current = true;
+        assert_eq!(player.up_next(), None);
+    }
+
+    #[test]
+    fn search_matches_path_fragments_after_titles() {
+        let (mut player, _) = player_with_queue(0);
+        player.database.tracks = ["/music/Live/1999/a.mp3", "/music/Studio/live.mp3"]
+            .iter()
+            .map(|path| Track::from_tags(Path::new(path), TrackTags::default(), None))
+            .collect();
+
+        player.start_search();
+        for c in "LIVE".chars() {
+            player.search_add_char(c);
+        }
+        // The title "live" ranks ahead of the folder match
+        assert_eq!(player.search_results, vec![1, 0]);
+
+        player.search_backspace();
+        player.search_backspace();
+        player.search_backspace();
+        player.search_backspace();
+        for c in "/1999/".chars() {
+            player.search_add_char(c);
+        }
+        assert_eq!(player.search_results, vec![0]);
+    }
+
+    #[test]
+    fn submitting_all_search_results_queues_them_in_order() {
+        let (mut player, _) = player_with_queue(0);
+        player.database.tracks = ["/music/a.mp3", "/music/live.mp3", "/music/Live/b.mp3"]
+            .iter()
+            .map(|path| Track::from_tags(Path::new(path), TrackTags::default(), None))
+            .collect();
+
+        player.start_search();
+        for c in "live".chars() {
+            player.search_add_char(c);
+        }
+        player.search_submit_all();
+
+        let queued: Vec<&Path> = player.queue.iter().map(|t| t.path.as_path()).collect();
+        assert_eq!(
+            queued,
+            [Path::new("/music/live.mp3"), Path::new("/music/Live/b.mp3")]
+        );
+        assert_eq!(player.current_track_index, Some(0));
+        assert!(!player.search_mode);
+    }
+
+    #[test]
+    fn typed_counts_build_up_and_are_used_once() {
+        let (mut player, _) = player_with_queue(0);
+        player.push_count_digit(0);
+        assert_eq!(player.pending_count, None);
+
+        player.push_count_digit(1);
+        player.push_count_digit(2);
+        assert_eq!(player.take_count(), Some(12));
+        assert_eq!(player.take_count(), None);
+    }
+
+    #[test]
+    fn select_current_finds_the_playing_track() {
+        let (mut player, _) = player_with_queue(5);
+        player.database.tracks = player.queue.clone();
+        player.play_track(3);
+        player.select_first();
+
+        player.select_current();
+        assert_eq!(player.list_state, 3);
+
+        // In the tree the collapsed artist opens up to show it
+        player.toggle_browse_mode();
+        player.select_first();
+        player.collapse_selected();
+        assert_eq!(player.tree_rows().len(), 1);
+        player.select_current();
+        assert_eq!(player.tree_rows()[player.tree_selected], TreeRow::Track(3));
+    }
+
+    #[test]
+    fn selections_follow_their_tracks_through_a_rescan() {
+        let (mut player, _) = player_with_queue(0);
+        let by = |path: &str, artist: &str| {
+            let tags = TrackTags {
+                artist: Some(artist.to_string()),
+                ..TrackTags::default()
+            };
+            Track::from_tags(Path::new(path), tags, None)
+        };
+        player.database.tracks = vec![by("/music/b.mp3", "Beta"), by("/music/c.mp3", "Gamma")];
+        player.list_state = 1;
+        player.toggle_browse_mode();
+        player.tree_selected = 5;
+        assert_eq!(player.tree_rows()[5], TreeRow::Track(1));
+
+        // A new artist sorts in front, pushing both tracks down
+        player.update_library(|database| database.add_tracks(vec![by("/music/a.mp3", "Alpha")]));
+        let c = player.database.index_of(Path::new("/music/c.mp3"));
+        assert_eq!(Some(player.list_state), c);
+        assert_eq!(
+            player.tree_rows()[player.tree_selected],
+            TreeRow::Track(c.unwrap())
+        );
+    }
+
+    #[test]
+    fn autoplay_resumes_with_the_last_played_track() {
+        let (mut player, _) = player_with_queue(0);
+        player.database.tracks = (0..3)
+            .map(|i| {
+                let path = PathBuf::from(format!("/music/{}.mp3", i));
+                Track::from_tags(&path, TrackTags::default(), None)
+            })
+            .collect();
+        player.scan = None;
+        player.history = vec![(PathBuf::from("/music/1.mp3"), SystemTime::now())];
+
+        assert!(!player.check_autoplay());
+        player.autoplay_pending = true;
+        assert!(player.check_autoplay());
+        assert_eq!(
+            player.get_current_track().map(|t| t.path.as_path()),
+            Some(Path::new("/music/1.mp3"))
+        );
+        assert_eq!(player.queue.len(), 3);
+        assert!(!player.check_autoplay());
+    }
+
+    #[test]
+    fn empty_library_keys_do_nothing() {
+        let (mut player, audio) = player_with_queue(0);
+        assert_eq!(player.database.track_count(), 0);
+
+        for _ in 0..3 {
+            player.toggle_browse_mode();
+            player.scroll_down();
+            player.page_down();
+            player.select_last();
+            player.scroll_up();
+            player.play_selected();
+            player.next_track();
+            player.previous_track();
+        }
+        assert_eq!(player.list_state, 0);
+        assert!(player.selected_track().is_none());
+        assert!(audio.playing.borrow().is_none());
+        assert!(!player.is_playing);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::database::Track;
+use crate::metadata;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// Tracks read from an .m3u/.m3u8 file
+pub struct Playlist {
+    pub tracks: Vec<Track>,
+    /// Entries whose file doesn't exist (or isn't a local file)
+    pub missing: usize,
+}
+
+/// Duration and title from an `#EXTINF:<seconds>,<title>` line
+#[derive(Default)]
+struct ExtInf {
+    duration: Option<u64>,
+    artist: Option<String>,
+    title: Option<String>,
+}
+
+impl ExtInf {
+    fn parse(info: &str) -> Self {
+        let (duration, display) = info.split_once(',').unwrap_or((info, ""));
+        // Durations of -1 (unknown) or with attributes after them are ignored
+        let duration = duration.trim().parse::<f64>().ok().filter(|d| *d >= 0.0);
+        let display = display.trim();
+
+        // Commonly written as "Artist - Title"
+        let (artist, title) = match display.split_once(" - ") {
+            Some((artist, title)) => (Some(artist.trim()), title.trim()),
+            None => (None, display),
+        };
+
+        Self {
+            duration: duration.map(|d| d.round() as u64),
+            artist: artist.filter(|a| !a.is_empty()).map(String::from),
+            title: Some(title).filter(|t| !t.is_empty()).map(String::from),
+        }
+    }
+}
+
+/// Read a playlist, resolving relative entries against its directory.
+/// Tracks already in the library are reused so their tags stay consistent.
+pub fn load(path: &Path, library: &[Track]) -> Result<Playlist> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    // .m3u8 is UTF-8; old .m3u files may be Latin-1, which lossy decoding survives
+    let content = String::from_utf8_lossy(&bytes);
+    let base = path.parent().unwrap_or(Path::new("."));
+
+    let mut playlist = Playlist {
+        tracks: Vec::new(),
+        missing: 0,
+    };
+    let mut info = ExtInf::default();
+
+    for line in content.lines() {
+        let line = line.trim().trim_start_matches('\u{feff}');
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("#EXTINF:") {
+            info = ExtInf::parse(rest);
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let hints = std::mem::take(&mut info);
+        let entry = base.join(line);
+        if line.contains("://") || !entry.is_file() {
+            playlist.missing += 1;
+            continue;
+        }
+
+        let mut track = match library.iter().find(|t| t.path == entry) {
+            Some(track) => track.clone(),
+            None => track_from_file(&entry, &hints),
+        };
+        track.duration = track.duration.or(hints.duration);
+        playlist.tracks.push(track);
+    }
+
+    Ok(playlist)
+}
+
+/// Build a track for a file outside the library, preferring its own tags
+fn track_from_file(path: &Path, hints: &ExtInf) -> Track {
+    let mut tags = metadata::read_tags(path);
+    tags.title = tags.title.or_else(|| hints.title.clone());
+    tags.artist = tags.artist.or_else(|| hints.artist.clone());
+
+    Track::from_tags(path, tags, None)
+}
+
+/// Write tracks as an extended m3u playlist with absolute paths
+pub fn save(path: &Path, tracks: &[Track]) -> Result<()> {
+    let mut out = String::from("#EXTM3U\n");
+    for track in tracks {
+        let duration = track.duration.map_or(-1, |d| d as i64);
+        let display = match &track.artist {
+            Some(artist) => format!("{} - {}", artist, track.title),
+            None => track.title.clone(),
+        };
+        out.push_str(&format!("#EXTINF:{},{}\n", duration, display));
+        out.push_str(&format!("{}\n", track.path.display()));
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, out).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::TrackTags;
+    use std::path::PathBuf;
+
+    /// An empty temp dir for one test, holding the files named in `files`
+    fn scratch_dir(test: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join("catty-player-tests")
+            .join(format!("playlist-{}", test));
+        let _ = fs::remove_dir_all(&dir);
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn extinf_splits_artist_and_title() {
+        let info = ExtInf::parse("215,Daft Punk - One More Time");
+        assert_eq!(info.duration, Some(215));
+        assert_eq!(info.artist.as_deref(), Some("Daft Punk"));
+        assert_eq!(info.title.as_deref(), Some("One More Time"));
+
+        let info = ExtInf::parse("12.6,Just a Title");
+        assert_eq!(info.duration, Some(13));
+        assert_eq!(info.artist, None);
+        assert_eq!(info.title.as_deref(), Some("Just a Title"));
+    }
+
+    #[test]
+    fn extinf_ignores_unknown_durations() {
+        let info = ExtInf::parse("-1,Stream");
+        assert_eq!(info.duration, None);
+        assert_eq!(info.title.as_deref(), Some("Stream"));
+
+        let info = ExtInf::parse("180 tvg-id=\"x\",Song");
+        assert_eq!(info.duration, None);
+        assert_eq!(info.title.as_deref(), Some("Song"));
+    }
+
+    #[test]
+    fn extinf_without_a_comma_has_no_title() {
+        let info = ExtInf::parse("240");
+        assert_eq!(info.duration, Some(240));
+        assert_eq!(info.artist, None);
+        assert_eq!(info.title, None);
+    }
+
+    #[test]
+    fn load_resolves_relative_entries_and_strips_the_bom() {
+        let dir = scratch_dir("relative", &["songs/a.mp3", "b.mp3"]);
+        let playlist_path = dir.join("mix.m3u8");
+        let absolute = dir.join("b.mp3");
+        let content = format!(
+            "\u{feff}#EXTM3U\n#EXTINF:61,Someone - Track A\nsongs/a.mp3\n\n{}\n",
+            absolute.display()
+        );
+        fs::write(&playlist_path, content).unwrap();
+
+        let playlist = load(&playlist_path, &[]).unwrap();
+        assert_eq!(playlist.missing, 0);
+        assert_eq!(playlist.tracks.len(), 2);
+
+        let first = &playlist.tracks[0];
+        assert_eq!(first.path, dir.join("songs/a.mp3"));
+        // The empty file has no tags, so the #EXTINF line fills them in
+        assert_eq!(first.title, "Track A");
+        assert_eq!(first.artist.as_deref(), Some("Someone"));
+        assert_eq!(first.duration, Some(61));
+
+        assert_eq!(playlist.tracks[1].path, absolute);
+        assert_eq!(playlist.tracks[1].title, "b");
+    }
+
+    #[test]
+    fn urls_and_absent_files_count_as_missing() {
+        let dir = scratch_dir("missing", &["here.mp3"]);
+        let playlist_path = dir.join("mix.m3u");
+        let content = "#EXTM3U\nhttp://radio.example/stream\ngone.mp3\nhere.mp3\n";
+        fs::write(&playlist_path, content).unwrap();
+
+        let playlist = load(&playlist_path, &[]).unwrap();
+        assert_eq!(playlist.missing, 2);
+        assert_eq!(playlist.tracks.len(), 1);
+        assert_eq!(playlist.tracks[0].path, dir.join("here.mp3"));
+    }
+
+    #[test]
+    fn saved_playlist_loads_back() {
+        let dir = scratch_dir("round-trip", &["one.mp3", "two.flac"]);
+        let one = TrackTags {
+            title: Some("First".into()),
+            artist: Some("Band".into()),
+            duration: Some(200),
+            ..TrackTags::default()
+        };
+        let two = TrackTags {
+            title: Some("Second".into()),
+            ..TrackTags::default()
+        };
+        let tracks = vec![
+            Track::from_tags(&dir.join("one.mp3"), one, None),
+            Track::from_tags(&dir.join("two.flac"), two, None),
+        ];
+        let playlist_path = dir.join("lists").join("saved.m3u8");
+        save(&playlist_path, &tracks).unwrap();
+
+        // Loaded without the library, so everything comes from the file itself
+        let playlist = load(&playlist_path, &[]).unwrap();
+        assert_eq!(playlist.missing, 0);
+        let loaded: Vec<_> = playlist
+            .tracks
+            .iter()
+            .map(|t| {
+                (
+                    t.path.clone(),
+                    t.title.as_str(),
+                    t.artist.as_deref(),
+                    t.duration,
+                )
+            })
+            .collect();
+        assert_eq!(
+            loaded,
+            [
+                (dir.join("one.mp3"), "First", Some("Band"), Some(200)),
+                (dir.join("two.flac"), "Second", None, None),
+            ]
+        );
+    }
+}
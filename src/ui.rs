@@ -1,14 +1,35 @@
+use crate::browse::{BrowseMode, TreeRow};
 use crate::config::Config;
+use crate::database::{self, Track};
+use crate::keybind::Action;
 //use crate::config::WatermarkConfig;
-use crate::player::PlayerState;
+use crate::player::{PlayerState, TrackInfo};
+use crate::visualizer::{VisualizerMode, VisualizerOrientation};
 //use ratatui::style::Stylize;
 use ratatui::{
-    layout::{Constraint, Direction, Layout, Rect},
+    layout::{Alignment, Constraint, Direction, Layout, Margin, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Gauge, List, ListItem, Paragraph},
+    widgets::{Block, Borders, Clear, Gauge, List, ListItem, Paragraph, Wrap},
     Frame,
 };
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Columns taken by a track's "MM:SS" duration
+const DURATION_WIDTH: usize = 5;
+/// Columns taken by a track's play count, e.g. "12×"
+const PLAYS_WIDTH: usize = 4;
+/// Terminal rows below which the visualizer is dropped in favor of the list
+const VISUALIZER_MIN_HEIGHT: u16 = 24;
+/// Terminal rows below which the controls shrink to a single status line
+const CONTROLS_MIN_HEIGHT: u16 = 16;
+/// Cells filled from the bottom in eighths, empty to full
+const EIGHTH_BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// Cells filled from the left in eighths, empty to full
+const LEFT_EIGHTH_BLOCKS: [char; 9] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉', '█'];
 
 /// UI rendering
 pub struct UI;
@@ -16,75 +37,357 @@ pub struct UI;
 impl UI {
     /// Render the entire UI
     pub fn render(f: &mut Frame, state: &mut PlayerState) {
-        // If help menu is shown, render it instead
-        if state.show_help {
-            Self::render_help(f, state);
+        // Cover art is only placed again if its panel is drawn this frame
+        state.album_art.set_area(None);
+
+        if state.show_history {
+            Self::render_history(f, state);
             return;
         }
 
         let size = f.area();
 
+        // Short terminals (e.g. split panes) first lose the visualizer, then
+        // the full controls. Hiding the visualizer also gives its room to the list
+        let show_visualizer = state.show_visualizer && size.height >= VISUALIZER_MIN_HEIGHT;
+        let compact_controls = size.height < CONTROLS_MIN_HEIGHT;
+
         // Main layout: vertical split
+        let mut constraints = vec![Constraint::Length(3)]; // Title bar
+        if show_visualizer {
+            constraints.push(Constraint::Percentage(25)); // Track list (25%)
+            constraints.push(Constraint::Percentage(55)); // Visualizer (55%)
+        } else {
+            constraints.push(Constraint::Min(0)); // Track list takes the rest
+        }
+        constraints.push(Constraint::Length(3)); // Progress bar
+        constraints.push(Constraint::Length(if compact_controls { 1 } else { 8 })); // Controls
         let chunks = Layout::default()
             .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Length(3),      // Title bar
-                Constraint::Percentage(25), // Track list (25%)
-                Constraint::Percentage(55), // Visualizer (55%)
-                Constraint::Length(3),      // Progress bar
-                Constraint::Length(6),      // Controls
-            ])
+            .constraints(constraints)
             .split(size);
+        let (progress_area, controls_area) = (chunks[chunks.len() - 2], chunks[chunks.len() - 1]);
 
         // Render title (shows search input when active)
         Self::render_title(f, chunks[0], state);
 
-        // Render track list
-        Self::render_track_list(f, chunks[1], state);
+        // Render track list, with the queue panel beside it when shown
+        state.page_size = chunks[1].height.saturating_sub(2) as usize;
+        if state.show_queue && !state.search_mode {
+            let panels = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+                .split(chunks[1]);
+            Self::render_track_list(f, panels[0], state);
+            Self::render_queue(f, panels[1], state);
+        } else {
+            Self::render_track_list(f, chunks[1], state);
+        }
 
-        // Render visualizer
-        Self::render_visualizer(f, chunks[2], state);
+        // Render visualizer, flanked by the cover art when enabled
+        if !show_visualizer {
+            // No room for either
+        } else if state.config.album_art.enabled {
+            // Terminal cells are roughly twice as tall as wide
+            let cover_width = (chunks[2].height * 2).min(chunks[2].width * 2 / 5);
+            let viz_chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Length(cover_width), Constraint::Min(0)])
+                .split(chunks[2]);
+
+            Self::render_album_art(f, viz_chunks[0], state);
+            Self::render_visualizer(f, viz_chunks[1], state);
+        } else {
+            Self::render_visualizer(f, chunks[2], state);
+        }
 
         // Render progress bar
-        Self::render_progress(f, chunks[3], state);
+        Self::render_progress(f, progress_area, state);
 
         // Render controls
-        Self::render_controls(f, chunks[4], state);
+        if compact_controls {
+            Self::render_status_line(f, controls_area, state);
+        } else {
+            Self::render_controls(f, controls_area, state);
+        }
+
+        // Help floats over the dimmed player; the cover image would sit on top
+        // of it, so it's hidden meanwhile
+        if state.show_help {
+            state.album_art.set_area(None);
+            f.buffer_mut()
+                .set_style(size, Style::default().add_modifier(Modifier::DIM));
+            Self::render_help(f, state);
+        }
+
+        if let Some(info) = &state.track_info {
+            state.album_art.set_area(None);
+            f.buffer_mut()
+                .set_style(size, Style::default().add_modifier(Modifier::DIM));
+            Self::render_track_info(f, state, info);
+        }
+
+        // Startup config error on top of everything
+        if let Some(message) = &state.config_dialog {
+            Self::render_config_dialog(f, state, message);
+        }
+    }
+
+    /// Render the dialog explaining why config.toml couldn't be loaded
+    fn render_config_dialog(f: &mut Frame, state: &PlayerState, message: &str) {
+        let size = f.area();
+        let area =
+            Self::centered_rect(size, (size.width * 4 / 5).max(20), (size.height / 2).max(8));
+
+        let mut lines: Vec<Line> = message.lines().map(Line::from).collect();
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            "Press Enter or Esc to continue",
+            Style::default().fg(Color::DarkGray),
+        )));
+
+        let dialog = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!(" {} Config Error ", Self::glyph(state, "⚠", "!")))
+                .style(Style::default().fg(Color::Red)),
+        );
+
+        f.render_widget(Clear, area);
+        f.render_widget(dialog, area);
+    }
+
+    /// Area of the given size centered in `area`, shrunk to fit inside it
+    fn centered_rect(area: Rect, width: u16, height: u16) -> Rect {
+        let width = width.min(area.width);
+        let height = height.min(area.height);
+        Rect::new(
+            area.x + (area.width - width) / 2,
+            area.y + (area.height - height) / 2,
+            width,
+            height,
+        )
     }
 
     /// Render title bar
     fn render_title(f: &mut Frame, area: Rect, state: &PlayerState) {
-        let accent_color = Config::parse_color(&state.config.colors.accent);
+        let accent_color = state.config.colors.accent();
 
         // If in search mode, render input box with query
         if state.search_mode {
             let input = format!("/{}", state.search_query);
+            let title = if state.search_as_filter {
+                " Filter (Enter: Keep Matches, Alt+Enter: Queue All) "
+            } else {
+                " Search (Enter: Play, Alt+Enter: Queue All) "
+            };
             let p = Paragraph::new(input)
                 .style(
                     Style::default()
                         .fg(accent_color)
                         .add_modifier(Modifier::BOLD),
                 )
-                .block(Block::default().borders(Borders::ALL).title(" Search "));
+                .block(Block::default().borders(Borders::ALL).title(title));
             f.render_widget(p, area);
             return;
         }
 
-        let title = Paragraph::new("🎵 Catty Music Player")
-            .style(
-                Style::default()
-                    .fg(accent_color)
-                    .add_modifier(Modifier::BOLD),
-            )
-            .block(Block::default().borders(Borders::ALL));
+        // Sleep timer input, shaped like the search box
+        if let Some(input) = &state.sleep_prompt {
+            let p = Paragraph::new(format!("{} min", input))
+                .style(
+                    Style::default()
+                        .fg(accent_color)
+                        .add_modifier(Modifier::BOLD),
+                )
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(" Sleep Timer (minutes, t: after this track, empty: cancel) "),
+                );
+            f.render_widget(p, area);
+            return;
+        }
+
+        let warning_sign = Self::glyph(state, "⚠", "!");
+        let mut spans = vec![Span::styled(
+            format!("{}Catty Music Player", Self::glyph(state, "🎵 ", "")),
+            Style::default()
+                .fg(accent_color)
+                .add_modifier(Modifier::BOLD),
+        )];
+
+        // Album of the playing track as a subtitle
+        if let Some(album) = state.get_current_track().and_then(|t| t.album.as_deref()) {
+            spans.push(Span::styled(
+                format!("  {} {}", Self::glyph(state, "💿", "-"), album),
+                Style::default().fg(state.config.colors.foreground()),
+            ));
+        }
+
+        // Banner when running without an audio device
+        if let Some(err) = state.audio.device_error() {
+            spans.push(Span::styled(
+                format!(
+                    "  {} No audio output ({}) - browsing only",
+                    warning_sign, err
+                ),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ));
+        }
+
+        // Banner when an edited config couldn't be applied
+        if let Some(err) = &state.config_error {
+            spans.push(Span::styled(
+                format!(
+                    "  {} {} (keeping previous settings)",
+                    warning_sign,
+                    err.replace('\n', " ")
+                ),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ));
+        }
+
+        if let Some(files) = state.scanned_files {
+            spans.push(Span::styled(
+                format!(
+                    "  Scanning{} {} files",
+                    Self::glyph(state, "…", "..."),
+                    files
+                ),
+                Style::default().fg(Color::Yellow),
+            ));
+        }
+
+        if let Some(err) = state.last_error() {
+            spans.push(Span::styled(
+                format!("  {} {}", warning_sign, err),
+                Style::default().fg(Color::Red),
+            ));
+        }
+
+        if let Some(remaining) = state.sleep_remaining() {
+            spans.push(Span::styled(
+                format!(
+                    "  {} {}",
+                    Self::glyph(state, "⏾", "Sleep"),
+                    Self::format_time(remaining.as_secs())
+                ),
+                Style::default().fg(Color::Magenta),
+            ));
+        }
+
+        if let Some(notice) = state.notice() {
+            spans.push(Span::styled(
+                format!("  {}", notice),
+                Style::default().fg(Color::Green),
+            ));
+        }
+
+        // Count typed so far for the next movement or seek
+        if let Some(count) = state.pending_count {
+            spans.push(Span::styled(
+                format!("  {}{}", count, Self::glyph(state, "…", "...")),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+
+        // Hint about theme colors that fell back to defaults
+        if let Some(warning) = state.config.color_warning() {
+            spans.push(Span::styled(
+                format!("  {} {}", warning_sign, warning),
+                Style::default().fg(Color::Yellow),
+            ));
+        }
+
+        let title = Paragraph::new(Line::from(spans)).block(Block::default().borders(Borders::ALL));
         f.render_widget(title, area);
     }
 
+    /// Border of a list panel, dimmed when the other panel has focus
+    fn panel_border(state: &PlayerState, focused: bool) -> Style {
+        if focused {
+            Style::default().fg(state.config.colors.accent())
+        } else {
+            Style::default().fg(Color::DarkGray)
+        }
+    }
+
+    /// Highlighted row of a list
+    fn selected_style(state: &PlayerState) -> Style {
+        let colors = &state.config.colors;
+        Style::default()
+            .fg(colors.selected_foreground())
+            .bg(colors.selected_background())
+            .add_modifier(Modifier::BOLD)
+    }
+
     /// Render track list
     fn render_track_list(f: &mut Frame, area: Rect, state: &PlayerState) {
+        // Nothing to list once the scan has finished empty-handed
+        if state.database.track_count() == 0 && state.scanned_files.is_none() {
+            Self::render_empty_library(f, area, state);
+            return;
+        }
+
+        // Search results are always shown flat
+        if state.browse_mode == BrowseMode::Tree && !state.search_mode && !state.filter_active {
+            Self::render_tree(f, area, state);
+            return;
+        }
+
         let tracks = state.database.get_tracks();
-        let foreground = Config::parse_color(&state.config.colors.foreground);
-        let accent = Config::parse_color(&state.config.colors.accent);
+        let foreground = state.config.colors.foreground();
+        let accent = state.config.colors.accent();
+        let queued = state.queued_paths();
+        let most_played = state.database.most_played();
+        let show_most_played =
+            state.browse_mode == BrowseMode::MostPlayed && !state.showing_search_results();
+
+        // Columns: prefix, title, favorite star (if any are starred), artist (if
+        // the library has any), plays (once anything has been played), duration
+        let inner_width = area.width.saturating_sub(2) as usize;
+        let show_stars = tracks.iter().any(|t| state.database.is_favorite(&t.path));
+        let show_artist = inner_width >= 40 && tracks.iter().any(|t| t.artist.is_some());
+        let artist_width = if show_artist {
+            (inner_width / 3).min(30)
+        } else {
+            0
+        };
+        let show_plays = !most_played.is_empty();
+        let favorite = state.config.symbols.favorite();
+        let title_width = inner_width
+            .saturating_sub(Self::prefix_width(state) + DURATION_WIDTH + 1)
+            .saturating_sub(if show_stars { favorite.width() + 1 } else { 0 })
+            .saturating_sub(if show_artist { artist_width + 1 } else { 0 })
+            .saturating_sub(if show_plays { PLAYS_WIDTH + 1 } else { 0 });
+        // Title column, then the star, artist, plays and duration columns after it
+        let columns = |track: &Track| {
+            let duration = track.duration.map(Self::format_time).unwrap_or_default();
+            let mut rest = String::new();
+            if show_stars {
+                rest.push(' ');
+                if state.database.is_favorite(&track.path) {
+                    rest.push_str(favorite);
+                } else {
+                    rest.push_str(&" ".repeat(favorite.width()));
+                }
+            }
+            if show_artist {
+                let artist = track.artist.as_deref().unwrap_or("");
+                rest.push(' ');
+                rest.push_str(&Self::fit_width(artist, artist_width));
+            }
+            if show_plays {
+                let plays = match state.database.play_count(&track.path) {
+                    0 => String::new(),
+                    n => format!("{}{}", n, Self::glyph(state, "×", "x")),
+                };
+                rest.push_str(&format!(" {:>width$}", plays, width = PLAYS_WIDTH));
+            }
+            rest.push_str(&format!(" {:>width$}", duration, width = DURATION_WIDTH));
+            (Self::fit_width(&track.title, title_width), rest)
+        };
 
         // Calculate visible range
         let visible_height = area.height.saturating_sub(2) as usize;
@@ -94,83 +397,313 @@ impl UI {
         let scroll_offset = if selected < state.scroll_offset {
             selected
         } else if selected >= state.scroll_offset + visible_height {
-            selected.saturating_sub(visible_height - 1)
+            (selected + 1).saturating_sub(visible_height)
         } else {
             state.scroll_offset
         };
 
-        let items: Vec<ListItem> = if state.search_mode && !state.search_query.is_empty() {
+        let items: Vec<ListItem> = if state.showing_search_results() {
+            // A kept filter scrolls with its own selection
+            let result_offset = if state.filter_active {
+                (state.filter_selected + 1).saturating_sub(visible_height)
+            } else {
+                0
+            };
+
             state
                 .search_results
                 .iter()
+                .enumerate()
+                .skip(result_offset)
                 .take(visible_height)
-                .map(|&i| {
+                .map(|(row, &i)| {
                     let track = &tracks[i];
-                    let is_current = state.current_track_index == Some(i);
-                    let is_selected = i == selected;
-
-                    let prefix = if is_current {
-                        if state.is_playing {
-                            "▶ "
-                        } else {
-                            "⏸ "
-                        }
+                    let is_current = state.is_current_track(track);
+                    let is_selected = if state.filter_active {
+                        row == state.filter_selected
                     } else {
-                        "  "
+                        i == selected
                     };
 
+                    let prefix =
+                        Self::track_prefix(state, &track.path, is_current, is_selected, true);
+
                     let style = if is_selected {
-                        Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+                        Self::selected_style(state)
                     } else if is_current {
-                        Style::default().fg(Color::Green)
+                        Style::default().fg(state.config.colors.playing_foreground())
+                    } else if queued.contains(track.path.as_path()) {
+                        Style::default().fg(state.config.colors.queued_foreground())
                     } else {
                         Style::default().fg(foreground)
                     };
 
-                    let content = format!("{}{}", prefix, track.title);
-                    ListItem::new(content).style(style)
+                    // Underline the characters the query matched
+                    let (title, rest) = columns(track);
+                    let matched = Style::default()
+                        .fg(accent)
+                        .add_modifier(Modifier::UNDERLINED);
+                    let mut spans = vec![Span::raw(prefix)];
+                    spans.extend(Self::highlight_matches(
+                        &title,
+                        &track.title,
+                        &state.search_matches(&track.title),
+                        matched,
+                    ));
+                    spans.push(Span::raw(rest));
+                    ListItem::new(Line::from(spans)).style(style)
                 })
                 .collect()
         } else {
-            tracks
-                .iter()
+            // The most played view scrolls with its own selection
+            let (rows, selected_row, offset) = if show_most_played {
+                let selected = state.most_played_selected;
+                let offset = (selected + 1).saturating_sub(visible_height);
+                (most_played.clone(), selected, offset)
+            } else {
+                ((0..tracks.len()).collect(), selected, scroll_offset)
+            };
+
+            rows.iter()
                 .enumerate()
-                .skip(scroll_offset)
+                .skip(offset)
                 .take(visible_height)
-                .map(|(i, track)| {
-                    let is_current = state.current_track_index == Some(i);
-                    let is_selected = i == selected;
+                .map(|(row, &i)| {
+                    let track = &tracks[i];
+                    let is_current = state.is_current_track(track);
+                    let is_selected = row == selected_row;
 
-                    let prefix = if is_current {
-                        if state.is_playing {
-                            "▶ "
-                        } else {
-                            "⏸ "
-                        }
-                    } else {
-                        "  "
-                    };
+                    let prefix =
+                        Self::track_prefix(state, &track.path, is_current, is_selected, true);
 
                     let style = if is_selected {
-                        Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+                        Self::selected_style(state)
                     } else if is_current {
-                        Style::default().fg(Color::Green)
+                        Style::default().fg(state.config.colors.playing_foreground())
+                    } else if queued.contains(track.path.as_path()) {
+                        Style::default().fg(state.config.colors.queued_foreground())
                     } else {
                         Style::default().fg(foreground)
                     };
 
-                    let content = format!("{}{}", prefix, track.title);
-                    ListItem::new(content).style(style)
+                    let (title, rest) = columns(track);
+                    ListItem::new(format!("{}{}{}", prefix, title, rest)).style(style)
                 })
                 .collect()
         };
 
-        let title = format!(" Tracks ({}/{}) ", selected + 1, tracks.len());
+        let direction = if state.sort_descending {
+            Self::glyph(state, "↓", "v")
+        } else {
+            Self::glyph(state, "↑", "^")
+        };
+        let title = if state.favorites_filter {
+            format!(
+                " Favorites ({}/{}) · Esc: Clear ",
+                (state.filter_selected + 1).min(state.search_results.len()),
+                state.search_results.len()
+            )
+        } else if state.filter_active {
+            format!(
+                " Filter \"{}\" ({}/{}) · Esc: Clear ",
+                state.search_query,
+                (state.filter_selected + 1).min(state.search_results.len()),
+                state.search_results.len()
+            )
+        } else if show_most_played {
+            format!(
+                " Most Played ({}/{}) ",
+                (state.most_played_selected + 1).min(most_played.len()),
+                most_played.len()
+            )
+        } else {
+            let hidden = match state.database.hidden_count() {
+                0 => String::new(),
+                n => format!(" · {} duplicates hidden", n),
+            };
+            format!(
+                " Tracks ({}/{}) · {} {}{} ",
+                (selected + 1).min(tracks.len()),
+                tracks.len(),
+                state.sort_key.name(),
+                direction,
+                hidden
+            )
+        };
         let list = List::new(items).block(
             Block::default()
                 .borders(Borders::ALL)
                 .title(title)
-                .border_style(Style::default().fg(accent)),
+                .border_style(Self::panel_border(state, !state.queue_view_active())),
+        );
+
+        f.render_widget(list, area);
+    }
+
+    /// Placeholder for the library panel when no music was found
+    fn render_empty_library(f: &mut Frame, area: Rect, state: &PlayerState) {
+        let foreground = state.config.colors.foreground();
+        let lines = vec![
+            Line::from(Span::styled(
+                "No tracks found — check your library path",
+                Style::default().fg(state.config.colors.accent()),
+            )),
+            Line::from(""),
+            Line::from(format!(
+                "Catty looks for music in {}",
+                database::music_dir().display()
+            )),
+            Line::from("New files there show up as soon as they're added."),
+        ];
+
+        let placeholder = Paragraph::new(lines)
+            .style(Style::default().fg(foreground))
+            .wrap(Wrap { trim: false })
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(" Tracks (0) ")
+                    .border_style(Self::panel_border(state, !state.queue_view_active())),
+            );
+        f.render_widget(placeholder, area);
+    }
+
+    /// Render the play queue in playing order
+    fn render_queue(f: &mut Frame, area: Rect, state: &PlayerState) {
+        let foreground = state.config.colors.foreground();
+
+        let visible_height = area.height.saturating_sub(2) as usize;
+        let selected = state
+            .queue_selected
+            .min(state.queue.len().saturating_sub(1));
+
+        // Keep the selection on screen
+        let scroll_offset = (selected + 1).saturating_sub(visible_height);
+
+        let items: Vec<ListItem> = state
+            .queue
+            .iter()
+            .enumerate()
+            .skip(scroll_offset)
+            .take(visible_height)
+            .map(|(i, track)| {
+                let is_current = state.current_track_index == Some(i);
+
+                let prefix =
+                    Self::track_prefix(state, &track.path, is_current, i == selected, false);
+
+                let style = if i == selected {
+                    Self::selected_style(state)
+                } else if is_current {
+                    Style::default().fg(state.config.colors.playing_foreground())
+                } else {
+                    Style::default().fg(foreground)
+                };
+
+                let content = match &track.artist {
+                    Some(artist) => format!("{}{:>3}. {} - {}", prefix, i + 1, track.title, artist),
+                    None => format!("{}{:>3}. {}", prefix, i + 1, track.title),
+                };
+                ListItem::new(content).style(style)
+            })
+            .collect();
+
+        let list = List::new(items).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!(
+                    " Queue ({} tracks, {} · {} left) · {}/{}: Move ",
+                    state.queue.len(),
+                    Self::format_long_time(state.queue_duration().as_secs()),
+                    Self::format_long_time(state.queue_remaining().as_secs()),
+                    state.config.keybinds.move_up,
+                    state.config.keybinds.move_down
+                ))
+                .border_style(Self::panel_border(state, state.queue_view_active())),
+        );
+
+        f.render_widget(list, area);
+    }
+
+    /// Render the library grouped by artist and album
+    fn render_tree(f: &mut Frame, area: Rect, state: &PlayerState) {
+        let tracks = state.database.get_tracks();
+        let rows = state.tree_rows();
+        let foreground = state.config.colors.foreground();
+        let accent = state.config.colors.accent();
+        let queued = state.queued_paths();
+
+        let visible_height = area.height.saturating_sub(2) as usize;
+        let selected = state.tree_selected.min(rows.len().saturating_sub(1));
+
+        // Keep the selection on screen
+        let scroll_offset = (selected + 1).saturating_sub(visible_height);
+
+        let items: Vec<ListItem> = rows
+            .iter()
+            .enumerate()
+            .skip(scroll_offset)
+            .take(visible_height)
+            .map(|(i, row)| {
+                let marker = |collapsed: bool| {
+                    if collapsed {
+                        Self::glyph(state, "▸", "+")
+                    } else {
+                        Self::glyph(state, "▾", "-")
+                    }
+                };
+
+                let (content, style) = match row {
+                    TreeRow::Artist { name, collapsed } => (
+                        format!("{} {}", marker(*collapsed), name),
+                        Style::default().fg(accent).add_modifier(Modifier::BOLD),
+                    ),
+                    TreeRow::Album {
+                        name, collapsed, ..
+                    } => (
+                        format!("  {} {}", marker(*collapsed), name),
+                        Style::default().fg(foreground).add_modifier(Modifier::BOLD),
+                    ),
+                    TreeRow::Track(idx) => {
+                        let track = &tracks[*idx];
+                        let is_current = state.is_current_track(track);
+
+                        let prefix =
+                            Self::track_prefix(state, &track.path, is_current, i == selected, true);
+
+                        let style = if is_current {
+                            Style::default().fg(state.config.colors.playing_foreground())
+                        } else if queued.contains(track.path.as_path()) {
+                            Style::default().fg(state.config.colors.queued_foreground())
+                        } else {
+                            Style::default().fg(foreground)
+                        };
+                        let star = if state.database.is_favorite(&track.path) {
+                            format!(" {}", state.config.symbols.favorite())
+                        } else {
+                            String::new()
+                        };
+                        (format!("    {}{}{}", prefix, track.title, star), style)
+                    }
+                };
+
+                let style = if i == selected {
+                    Self::selected_style(state)
+                } else {
+                    style
+                };
+                ListItem::new(content).style(style)
+            })
+            .collect();
+
+        let list = List::new(items).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!(
+                    " Library by Artist/Album ({} tracks) ",
+                    tracks.len()
+                ))
+                .border_style(Self::panel_border(state, !state.queue_view_active())),
         );
 
         f.render_widget(list, area);
@@ -178,14 +711,324 @@ impl UI {
 
     /// Render CAVA-style visualizer
     fn render_visualizer(f: &mut Frame, area: Rect, state: &PlayerState) {
-        let bars = state.visualizer.get_bars();
-        let viz_fg = Config::parse_color(&state.config.colors.visualizer_foreground);
-        let viz_bg = Config::parse_color(&state.config.colors.visualizer_background);
-        let accent = Config::parse_color(&state.config.colors.accent);
+        let viz_fg = state.config.colors.visualizer_foreground();
+        let viz_bg = state.config.colors.visualizer_background();
 
         let width = area.width.saturating_sub(2) as usize;
         let height = area.height.saturating_sub(2) as usize;
 
+        // A panel squeezed down to its border has no cells to fill
+        if width == 0 || height == 0 {
+            return Self::render_visualizer_block(f, area, state, Vec::new(), false);
+        }
+
+        let gradient: Vec<Color> = state
+            .config
+            .visualizer
+            .gradient
+            .iter()
+            .filter_map(|c| Config::try_parse_color(c).ok())
+            .collect();
+
+        // The waveform and spectrogram have a time axis, so they always run left to right
+        let horizontal = state.config.visualizer.orientation == VisualizerOrientation::Horizontal
+            && !matches!(
+                state.visualizer_mode,
+                VisualizerMode::Waveform | VisualizerMode::Spectrogram
+            );
+
+        // In stereo the spectrum splits into a half for each channel, like the mirror
+        let bars = state.visualizer.get_bars();
+        let right_bars = state.visualizer.get_right_bars();
+        let mode = match (state.visualizer_mode, right_bars) {
+            (VisualizerMode::Spectrum, Some(_)) => VisualizerMode::Mirror,
+            (mode, _) => mode,
+        };
+        let second_bars = right_bars.unwrap_or(bars);
+
+        // Each bar takes `bar_width` cells plus a gap before the next, so fewer fit
+        let bar_width = state.config.visualizer.bar_width.max(1);
+        let bar_gap = state.config.visualizer.bar_gap;
+        let slots = |cells: usize| (cells + bar_gap) / (bar_width + bar_gap);
+
+        let mut lines = match mode {
+            VisualizerMode::Spectrogram => {
+                // Quiet to loud runs from the background up to the bar color without a gradient
+                let stops = if gradient.is_empty() {
+                    vec![viz_bg, viz_fg]
+                } else {
+                    gradient
+                };
+                let grid = Self::spectrogram_lines(
+                    state.visualizer.get_spectrogram(),
+                    &stops,
+                    viz_bg,
+                    state.config.symbols.bar(),
+                    width,
+                    height,
+                );
+                return Self::render_visualizer_block(f, area, state, grid, false);
+            }
+            VisualizerMode::Spectrum if horizontal => Self::widen_rows(
+                Self::horizontal_spectrum_lines(&Self::fit_bars(bars, slots(height)), width),
+                bar_width,
+                bar_gap,
+            ),
+            VisualizerMode::Mirror if horizontal => Self::widen_rows(
+                Self::horizontal_mirror_lines(
+                    &Self::fit_bars(bars, slots(height)),
+                    &Self::fit_bars(second_bars, slots(height)),
+                    width,
+                ),
+                bar_width,
+                bar_gap,
+            ),
+            VisualizerMode::Spectrum => Self::widen_columns(
+                Self::spectrum_lines(bars, slots(width), height),
+                bar_width,
+                bar_gap,
+            ),
+            VisualizerMode::Mirror => Self::widen_columns(
+                Self::mirror_lines(bars, second_bars, slots(width), height),
+                bar_width,
+                bar_gap,
+            ),
+            VisualizerMode::Waveform => {
+                Self::waveform_lines(state.visualizer.get_waveform(), width, height)
+            }
+        };
+
+        let bar = state.config.symbols.bar();
+        if bar != '█' {
+            lines = Self::replace_blocks(lines, bar);
+        }
+
+        // Pad every row to the full panel so the background fills it without gaps
+        if lines.len() < height {
+            lines.resize(height, String::new());
+        }
+
+        // Create spans with colors, shading each row by its level when a gradient is set
+        let styled_lines: Vec<Line> = lines
+            .into_iter()
+            .enumerate()
+            .map(|(row, line)| {
+                let line = format!("{line:<width$}");
+                if horizontal && !gradient.is_empty() {
+                    // Bars run across, so the level changes per column
+                    let spans: Vec<Span> = line
+                        .chars()
+                        .enumerate()
+                        .map(|(col, c)| {
+                            let level = Self::column_level(mode, col, width);
+                            let fg = Config::gradient_color(&gradient, level);
+                            Span::styled(c.to_string(), Style::default().fg(fg).bg(viz_bg))
+                        })
+                        .collect();
+                    return Line::from(spans);
+                }
+                let fg = if gradient.is_empty() {
+                    viz_fg
+                } else {
+                    let level = Self::row_level(mode, row, height);
+                    Config::gradient_color(&gradient, level)
+                };
+                Line::from(Span::styled(line, Style::default().fg(fg).bg(viz_bg)))
+            })
+            .collect();
+
+        let stereo = right_bars.is_some() && mode != VisualizerMode::Waveform;
+        Self::render_visualizer_block(f, area, state, styled_lines, stereo);
+    }
+
+    /// Draw the visualizer's lines inside its titled border
+    fn render_visualizer_block(
+        f: &mut Frame,
+        area: Rect,
+        state: &PlayerState,
+        lines: Vec<Line>,
+        stereo: bool,
+    ) {
+        let accent = state.config.colors.accent();
+
+        let visualizer = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(if stereo {
+                    format!(" Visualizer ({} · Stereo) ", state.visualizer_mode.name())
+                } else {
+                    format!(" Visualizer ({}) ", state.visualizer_mode.name())
+                })
+                .border_style(Style::default().fg(accent)),
+        );
+
+        f.render_widget(visualizer, area);
+    }
+
+    /// Past spectra as colored columns, newest on the right and low frequencies at the bottom
+    fn spectrogram_lines(
+        columns: &VecDeque<Vec<f32>>,
+        stops: &[Color],
+        background: Color,
+        cell: char,
+        width: usize,
+        height: usize,
+    ) -> Vec<Line<'static>> {
+        // Right-align the history so new columns appear at the right edge
+        let shown = columns.len().min(width);
+        let blank = width - shown;
+
+        (0..height)
+            .map(|row| {
+                let inverted_row = height - 1 - row;
+                let mut spans = vec![Span::styled(
+                    " ".repeat(blank),
+                    Style::default().bg(background),
+                )];
+                spans.extend(columns.iter().skip(columns.len() - shown).map(|column| {
+                    let level = column
+                        .get(inverted_row * column.len() / height)
+                        .copied()
+                        .unwrap_or(0.0);
+                    // A terminal-default background can't be blended, so its cells stay empty
+                    let color = Config::gradient_color(stops, level);
+                    let cell = if color == background { ' ' } else { cell };
+                    Span::styled(cell.to_string(), Style::default().fg(color).bg(background))
+                }));
+                Line::from(spans)
+            })
+            .collect()
+    }
+
+    /// Amplitude a row represents (0.0 quiet to 1.0 loud) in the given mode
+    fn row_level(mode: VisualizerMode, row: usize, height: usize) -> f32 {
+        match mode {
+            VisualizerMode::Spectrum | VisualizerMode::Spectrogram if height > 1 => {
+                (height - 1 - row) as f32 / (height - 1) as f32
+            }
+            VisualizerMode::Spectrum | VisualizerMode::Spectrogram => 1.0,
+            VisualizerMode::Mirror | VisualizerMode::Waveform => {
+                let center = height as f32 / 2.0;
+                ((row as f32 + 0.5 - center).abs() / center).min(1.0)
+            }
+        }
+    }
+
+    /// Amplitude a column represents in a horizontal bar mode
+    fn column_level(mode: VisualizerMode, col: usize, width: usize) -> f32 {
+        match mode {
+            VisualizerMode::Spectrum | VisualizerMode::Spectrogram if width > 1 => {
+                col as f32 / (width - 1) as f32
+            }
+            VisualizerMode::Spectrum | VisualizerMode::Spectrogram => 1.0,
+            VisualizerMode::Mirror | VisualizerMode::Waveform => {
+                let center = width as f32 / 2.0;
+                ((col as f32 + 0.5 - center).abs() / center).min(1.0)
+            }
+        }
+    }
+
+    /// Draw full cells with `bar` instead of "█"; partial blocks have no
+    /// equivalent in other glyphs, so they round to full or empty
+    fn replace_blocks(lines: Vec<String>, bar: char) -> Vec<String> {
+        lines
+            .into_iter()
+            .map(|line| {
+                line.chars()
+                    .map(|c| {
+                        let eighths = EIGHTH_BLOCKS
+                            .iter()
+                            .position(|&block| block == c)
+                            .or_else(|| LEFT_EIGHTH_BLOCKS.iter().position(|&block| block == c));
+                        match eighths {
+                            Some(eighths) if eighths >= 4 => bar,
+                            Some(_) => ' ',
+                            None => c,
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Draw each one-column bar `width` columns wide, with `gap` blank columns between
+    fn widen_columns(lines: Vec<String>, width: usize, gap: usize) -> Vec<String> {
+        if width == 1 && gap == 0 {
+            return lines;
+        }
+        lines
+            .into_iter()
+            .map(|line| {
+                let cells: Vec<String> =
+                    line.chars().map(|c| c.to_string().repeat(width)).collect();
+                cells.join(&" ".repeat(gap))
+            })
+            .collect()
+    }
+
+    /// Repeat each one-row bar over `width` rows, with `gap` blank rows between
+    fn widen_rows(lines: Vec<String>, width: usize, gap: usize) -> Vec<String> {
+        let mut widened = Vec::new();
+        for (i, line) in lines.into_iter().enumerate() {
+            if i > 0 {
+                widened.extend(std::iter::repeat_n(String::new(), gap));
+            }
+            widened.extend(std::iter::repeat_n(line, width));
+        }
+        widened
+    }
+
+    /// Average neighbouring bars so the whole spectrum fits in `count` rows
+    fn fit_bars(bars: &[f32], count: usize) -> Vec<f32> {
+        if count == 0 || bars.is_empty() {
+            return Vec::new();
+        }
+        bars.chunks(bars.len().div_ceil(count))
+            .map(|chunk| chunk.iter().sum::<f32>() / chunk.len() as f32)
+            .collect()
+    }
+
+    /// One bar per row growing from the left edge, lowest frequency at the top
+    fn horizontal_spectrum_lines(bars: &[f32], width: usize) -> Vec<String> {
+        bars.iter()
+            .map(|&bar_width| {
+                // Bar width in eighths of a column, so the end cell can be partly filled
+                let eighths = (bar_width.clamp(0.0, 1.0) * width as f32 * 8.0) as usize;
+                (0..width)
+                    .map(|col| LEFT_EIGHTH_BLOCKS[eighths.saturating_sub(col * 8).min(8)])
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// One bar per row reaching left from the middle column with `left`, and right with `right`
+    fn horizontal_mirror_lines(left: &[f32], right: &[f32], width: usize) -> Vec<String> {
+        let center = width as f32 / 2.0;
+
+        left.iter()
+            .zip(right)
+            .map(|(&left_width, &right_width)| {
+                (0..width)
+                    .map(|col| {
+                        let offset = col as f32 + 0.5 - center;
+                        let bar_width = if offset < 0.0 {
+                            left_width
+                        } else {
+                            right_width
+                        };
+                        if offset.abs() < bar_width.clamp(0.0, 1.0) * center {
+                            '█'
+                        } else {
+                            ' '
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Vertical bars growing from the bottom
+    fn spectrum_lines(bars: &[f32], width: usize, height: usize) -> Vec<String> {
         // Adjust bar count to fit width
         let bar_count = width.min(bars.len());
         let bars_to_show = &bars[..bar_count];
@@ -194,54 +1037,134 @@ impl UI {
         let mut lines: Vec<String> = vec![String::new(); height];
 
         for &bar_height in bars_to_show.iter() {
-            let filled_rows = (bar_height * height as f32) as usize;
+            // Bar height in eighths of a row, so the top cell can be partly filled
+            let eighths = (bar_height.clamp(0.0, 1.0) * height as f32 * 8.0) as usize;
 
             for row in 0..height {
                 let inverted_row = height - 1 - row; // Draw from bottom to top
+                let filled = eighths.saturating_sub(inverted_row * 8).min(8);
+                lines[row].push(EIGHTH_BLOCKS[filled]);
+            }
+        }
 
-                if inverted_row < filled_rows {
-                    // Filled part - use foreground color blocks
-                    lines[row].push('█');
+        lines
+    }
+
+    /// Bars reaching up from the middle row with `top`, and down with `bottom`
+    fn mirror_lines(top: &[f32], bottom: &[f32], width: usize, height: usize) -> Vec<String> {
+        let bar_count = width.min(top.len()).min(bottom.len());
+        let center = height as f32 / 2.0;
+
+        let mut lines: Vec<String> = vec![String::new(); height];
+
+        for (&top_height, &bottom_height) in top[..bar_count].iter().zip(bottom) {
+            for (row, line) in lines.iter_mut().enumerate() {
+                // Distance of the row's middle from the center line
+                let offset = row as f32 + 0.5 - center;
+                let bar_height = if offset < 0.0 {
+                    top_height
+                } else {
+                    bottom_height
+                };
+                line.push(if offset.abs() < bar_height.clamp(0.0, 1.0) * center {
+                    '█'
                 } else {
-                    // Empty part - use background
-                    lines[row].push(' ');
+                    ' '
+                });
+            }
+        }
+
+        lines
+    }
+
+    /// Oscilloscope line of the raw samples across the width
+    fn waveform_lines(samples: &[f32], width: usize, height: usize) -> Vec<String> {
+        let mut grid = vec![vec![' '; width]; height];
+
+        if !samples.is_empty() && height > 0 {
+            let to_row = |sample: f32| {
+                let y = (1.0 - sample.clamp(-1.0, 1.0)) / 2.0 * (height - 1) as f32;
+                y.round() as usize
+            };
+
+            let mut prev_row = None;
+            for x in 0..width {
+                let row = to_row(samples[x * samples.len() / width]);
+
+                // Join with the previous column so steep slopes stay continuous
+                let (top, bottom) = match prev_row {
+                    Some(prev) if prev < row => (prev + 1, row),
+                    Some(prev) if prev > row => (row, prev - 1),
+                    _ => (row, row),
+                };
+                for grid_row in grid.iter_mut().take(bottom + 1).skip(top) {
+                    grid_row[x] = '█';
                 }
+                prev_row = Some(row);
             }
         }
 
-        // Create spans with colors
-        let styled_lines: Vec<Line> = lines
-            .into_iter()
-            .map(|line| Line::from(Span::styled(line, Style::default().fg(viz_fg).bg(viz_bg))))
-            .collect();
+        grid.into_iter()
+            .map(|row| row.into_iter().collect())
+            .collect()
+    }
 
-        let visualizer = Paragraph::new(styled_lines).block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title(" Visualizer ")
-                .border_style(Style::default().fg(accent)),
-        );
+    /// Render cover art panel (image is drawn after the frame by the album art module)
+    fn render_album_art(f: &mut Frame, area: Rect, state: &mut PlayerState) {
+        let accent = state.config.colors.accent();
+        let foreground = state.config.colors.foreground();
 
-        f.render_widget(visualizer, area);
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(" Cover ")
+            .border_style(Style::default().fg(accent));
+        let inner = block.inner(area);
+
+        if state.album_art.can_display() {
+            f.render_widget(block, area);
+            state.album_art.set_area(Some(inner));
+        } else {
+            let placeholder = Paragraph::new(state.album_art.placeholder())
+                .style(Style::default().fg(foreground))
+                .alignment(Alignment::Center)
+                .wrap(Wrap { trim: true })
+                .block(block);
+            f.render_widget(placeholder, area);
+        }
     }
 
     /// Render controls and status
     fn render_controls(f: &mut Frame, area: Rect, state: &PlayerState) {
-        let accent = Config::parse_color(&state.config.colors.accent);
-        let foreground = Config::parse_color(&state.config.colors.foreground);
+        let accent = state.config.colors.accent();
+        let foreground = state.config.colors.foreground();
 
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
                 Constraint::Length(2), // Volume
-                Constraint::Length(4), // Status and controls
+                Constraint::Length(6), // Status, up next and controls
             ])
             .split(area);
 
-        // Volume gauge with value display - read actual system volume from PulseAudio
-        let system_volume = crate::audio::get_system_volume();
-        let volume_percent = (system_volume * 100.0) as u16;
-        let volume_label = format!(" Volume: {}% ", volume_percent);
+        // Volume gauge follows the in-app volume, so +/- show up at once
+        let volume_percent = (state.volume * 100.0).round() as u16;
+        let system = state
+            .system_volume
+            .map(|volume| format!("· System {}% ", (volume * 100.0).round() as u16))
+            .unwrap_or_default();
+        let (volume_label, gauge_color) = if state.muted {
+            (
+                format!(
+                    " Volume: {}% {}Muted {}",
+                    volume_percent,
+                    Self::glyph(state, "🔇 ", ""),
+                    system
+                ),
+                Color::DarkGray,
+            )
+        } else {
+            (format!(" Volume: {}% {}", volume_percent, system), accent)
+        };
         let volume_gauge = Gauge::default()
             .block(
                 Block::default()
@@ -249,14 +1172,18 @@ impl UI {
                     .title(volume_label)
                     .border_style(Style::default().fg(accent)),
             )
-            .gauge_style(Style::default().fg(accent).add_modifier(Modifier::BOLD))
+            .gauge_style(
+                Style::default()
+                    .fg(gauge_color)
+                    .add_modifier(Modifier::BOLD),
+            )
             .percent(volume_percent);
         f.render_widget(volume_gauge, chunks[0]);
 
         // Status and controls
         let current_track = state
             .get_current_track()
-            .map(|t| t.title.clone())
+            .map(Self::track_label)
             .unwrap_or_else(|| "No track playing".to_string());
 
         let status = if state.is_playing {
@@ -267,11 +1194,67 @@ impl UI {
         let shuffle_status = if state.shuffle { "ON" } else { "OFF" };
         let loop_status = if state.loopC { "ON" } else { "OFF" };
 
+        let mut now_playing = vec![
+            Span::styled("Now: ", Style::default().fg(Color::Gray)),
+            Span::styled(current_track, Style::default().fg(foreground)),
+        ];
+        if let Some(track) = state.get_current_track() {
+            let trim = state.database.trim(&track.path);
+            if trim != 0.0 {
+                now_playing.push(Span::styled(
+                    format!(" {} {:+.0} dB", Self::glyph(state, "⇅", "Trim"), trim),
+                    Style::default().fg(Color::Yellow),
+                ));
+            }
+        }
+        if state.stop_after_current {
+            now_playing.push(Span::styled(
+                format!(" {} Stop after this track", Self::glyph(state, "⏹", "[]")),
+                Style::default().fg(Color::Yellow),
+            ));
+        }
+
+        // What plays when the current track ends
+        let mut up_next = Vec::new();
+        if state.current_track_index.is_some() {
+            up_next.push(Span::styled("Up next: ", Style::default().fg(Color::Gray)));
+        }
+        match (state.current_track_index, state.up_next()) {
+            (None, _) => {}
+            (Some(_), None) => up_next.push(Span::styled(
+                "Nothing, playback stops",
+                Style::default().fg(foreground),
+            )),
+            (Some(current), Some(next)) => {
+                up_next.push(Span::styled(
+                    Self::track_label(&state.queue[next]),
+                    Style::default().fg(foreground),
+                ));
+                if next == current && state.loopC {
+                    up_next.push(Span::styled(
+                        " (repeating this track)",
+                        Style::default().fg(Color::Gray),
+                    ));
+                } else if state.shuffle {
+                    up_next.push(Span::styled(" (shuffle)", Style::default().fg(Color::Gray)));
+                }
+            }
+        }
+
+        // Where the current track sits in the queue
+        let title = match state.current_track_index {
+            Some(i) => format!(
+                " {} · Track {} of {} in queue ",
+                status,
+                i + 1,
+                state.queue.len()
+            ),
+            None => format!(" {} ", status),
+        };
+
         let controls = vec![
-            Line::from(vec![
-                Span::styled("Now: ", Style::default().fg(Color::Gray)),
-                Span::styled(current_track, Style::default().fg(foreground)),
-            ]),
+            Line::from(now_playing),
+            Line::from(up_next),
             Line::from(vec![
                 Span::raw(format!(
                     "{}: Play/Pause | ",
@@ -300,23 +1283,59 @@ impl UI {
         let controls_widget = Paragraph::new(controls).block(
             Block::default()
                 .borders(Borders::ALL)
-                .title(format!(" {} ", status))
+                .title(title)
                 .border_style(Style::default().fg(accent)),
         );
 
         f.render_widget(controls_widget, chunks[1]);
     }
 
+    /// One-line stand-in for the controls on short terminals
+    fn render_status_line(f: &mut Frame, area: Rect, state: &PlayerState) {
+        let accent = state.config.colors.accent();
+        let foreground = state.config.colors.foreground();
+
+        let symbols = &state.config.symbols;
+        let (icon, status) = if state.is_playing {
+            (symbols.playing(), "Playing")
+        } else {
+            (symbols.paused(), "Paused")
+        };
+        let current_track = state
+            .get_current_track()
+            .map(Self::track_label)
+            .unwrap_or_else(|| "No track playing".to_string());
+        let volume = if state.muted {
+            "Muted".to_string()
+        } else {
+            format!("Vol {}%", (state.volume * 100.0).round() as u16)
+        };
+
+        let line = Line::from(vec![
+            Span::styled(
+                format!(" {} {} ", icon, status),
+                Style::default().fg(accent),
+            ),
+            Span::styled(current_track, Style::default().fg(foreground)),
+            Span::styled(
+                format!(" · {} · {}: Help", volume, state.config.keybinds.help),
+                Style::default().fg(Color::Gray),
+            ),
+        ]);
+        f.render_widget(Paragraph::new(line), area);
+    }
+
     /// Render progress bar
     fn render_progress(f: &mut Frame, area: Rect, state: &PlayerState) {
-        let accent = Config::parse_color(&state.config.colors.accent);
-        let foreground = Config::parse_color(&state.config.colors.foreground);
+        let accent = state.config.colors.accent();
+        let foreground = state.config.colors.foreground();
 
         let elapsed = state.get_elapsed_seconds();
         let duration = state.get_duration_seconds();
 
+        // Elapsed time can run slightly past the reported duration
         let percent = if duration > 0.0 {
-            ((elapsed / duration) * 100.0) as u16
+            (((elapsed / duration) * 100.0) as u16).min(100)
         } else {
             0
         };
@@ -324,7 +1343,15 @@ impl UI {
         // Format time display
         let elapsed_str = Self::format_time(elapsed as u64);
         let duration_str = Self::format_time(duration as u64);
-        let title = format!(" {}/{} ", elapsed_str, duration_str);
+        let mut title = format!(" {}/{} ", elapsed_str, duration_str);
+        let region = state.loop_region();
+        if let Some((a, b)) = region {
+            title.push_str(&format!(
+                "· Loop {}–{} ",
+                Self::format_time(a.as_secs()),
+                Self::format_time(b.as_secs())
+            ));
+        }
 
         let progress_gauge = Gauge::default()
             .block(
@@ -337,116 +1364,78 @@ impl UI {
             .percent(percent);
 
         f.render_widget(progress_gauge, area);
+
+        // Tint the A-B section of the bar
+        if let Some((a, b)) = region.filter(|_| duration > 0.0) {
+            let inner = area.inner(Margin::new(1, 1));
+            let column = |t: Duration| {
+                let fraction = (t.as_secs_f32() / duration).min(1.0);
+                inner.x + (fraction * inner.width as f32) as u16
+            };
+            let (start, end) = (column(a), column(b).max(column(a) + 1));
+            let section = Rect::new(start, inner.y, end.min(inner.right()) - start, inner.height);
+            f.buffer_mut().set_style(
+                section.intersection(inner),
+                Style::default().fg(Color::Magenta).bg(Color::DarkGray),
+            );
+        }
     }
 
-    /// Render help menu
+    /// Render help menu, listing every action with the key it's bound to
     fn render_help(f: &mut Frame, state: &PlayerState) {
         let size = f.area();
-        let accent = Config::parse_color(&state.config.colors.accent);
-        let foreground = Config::parse_color(&state.config.colors.foreground);
+        let accent = state.config.colors.accent();
+        let foreground = state.config.colors.foreground();
+        let audio = &state.config.audio;
+
+        // One entry per bound action, so the list follows config.toml
+        let mut entries: Vec<Line> = state
+            .config
+            .keybinds
+            .bindings()
+            .into_iter()
+            .filter(|(_, key)| !key.trim().is_empty())
+            .map(|(action, key)| {
+                let description = match action {
+                    Action::SeekForward | Action::SeekBackward => {
+                        format!("{} {}s", action.description(), audio.seek_seconds)
+                    }
+                    Action::BigSeekForward | Action::BigSeekBackward => {
+                        format!("{} {}s", action.description(), audio.big_seek_seconds)
+                    }
+                    _ => action.description().to_string(),
+                };
+                Self::help_entry(key, &description)
+            })
+            .collect();
+        entries.push(Self::help_entry(
+            Self::glyph(state, "←/→", "Left/Right"),
+            "Seek, or Collapse/Expand in the Artist Tree",
+        ));
+        entries.push(Self::help_entry("0-9", "Count for the Next Move or Seek"));
 
-        let help_text = vec![
+        let header = vec![
             Line::from(vec![Span::styled(
                 "=== CATTY MUSIC PLAYER HELP ===",
                 Style::default().fg(accent).add_modifier(Modifier::BOLD),
             )]),
             Line::from(""),
-            Line::from(vec![
-                Span::styled(
-                    format!("{}:", state.config.keybinds.play_pause),
-                    Style::default().fg(Color::Cyan),
-                ),
-                Span::raw(" Toggle Play/Pause"),
-            ]),
-            Line::from(vec![
-                Span::styled(
-                    format!("{}:", state.config.keybinds.next),
-                    Style::default().fg(Color::Cyan),
-                ),
-                Span::raw(" Next Track"),
-            ]),
-            Line::from(vec![
-                Span::styled(
-                    format!("{}:", state.config.keybinds.previous),
-                    Style::default().fg(Color::Cyan),
-                ),
-                Span::raw(" Previous Track"),
-            ]),
-            Line::from(vec![
-                Span::styled(
-                    format!("{}:", state.config.keybinds.shuffle),
-                    Style::default().fg(Color::Cyan),
-                ),
-                Span::raw(" Toggle Shuffle"),
-            ]),
-            Line::from(vec![
-                Span::styled(
-                    format!("{}/-:", state.config.keybinds.volume_up),
-                    Style::default().fg(Color::Cyan),
-                ),
-                Span::raw(" Increase/Decrease Volume"),
-            ]),
-            Line::from(vec![
-                Span::styled(
-                    format!(
-                        "{}/{}:",
-                        state.config.keybinds.seek_backward, state.config.keybinds.seek_forward
-                    ),
-                    Style::default().fg(Color::Cyan),
-                ),
-                Span::raw(" Seek Backward/Forward 10s"),
-            ]),
-            Line::from(vec![
-                Span::styled(
-                    format!("{}:", state.config.keybinds.select),
-                    Style::default().fg(Color::Cyan),
-                ),
-                Span::raw(" Play Selected Track"),
-            ]),
-            Line::from(vec![
-                Span::styled(
-                    format!("{}:", state.config.keybinds.clear),
-                    Style::default().fg(Color::Cyan),
-                ),
-                Span::raw(" Clear Queue"),
-            ]),
-            Line::from(vec![
-                Span::styled(format!("↑/↓:",), Style::default().fg(Color::Cyan)),
-                Span::raw(" Navigate Track List"),
-            ]),
-            Line::from(vec![
-                Span::styled(
-                    format!("{}:", state.config.keybinds.help),
-                    Style::default().fg(Color::Cyan),
-                ),
-                Span::raw(" Toggle Help"),
-            ]),
-            Line::from(vec![
-                Span::styled(
-                    format!("{}:", state.config.keybinds.LoopC),
-                    Style::default().fg(Color::Cyan),
-                ),
-                Span::raw(" Toggle Loop"),
-            ]),
-            Line::from(vec![
-                Span::styled(
-                    format!("{}:", state.config.keybinds.quit),
-                    Style::default().fg(Color::Cyan),
-                ),
-                Span::raw(" Quit Application"),
-            ]),
+        ];
+        let footer = vec![
             Line::from(""),
             Line::from(vec![Span::styled(
-                "Press ? to close help",
+                format!("Press {} to close help", state.config.keybinds.help),
                 Style::default()
                     .fg(Color::Gray)
                     .add_modifier(Modifier::ITALIC),
             )]),
-            Line::from(""),
             match state.config.watermark.water_mark {
                 true => Line::from(vec![Span::styled(
-                    "󰎆 Catty-player - 
+                    format!(
+                        "{} Catty-player - 
 samsit-phew",
+                        state.config.symbols.watermark()
+                    ),
                     Style::default()
                         .fg(Color::Blue)
                         .add_modifier(Modifier::BOLD | Modifier::ITALIC),
@@ -455,16 +1444,320 @@ samsit-phew",
             },
         ];
 
-        let help_widget = Paragraph::new(help_text)
-            .block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .title(" Help ")
-                    .border_style(Style::default().fg(accent)),
-            )
+        // Spread the entries over as many columns as it takes to fit the screen
+        let chrome = (header.len() + footer.len() + 2) as u16;
+        let max_rows = size.height.saturating_sub(chrome).max(1) as usize;
+        let columns = entries.len().div_ceil(max_rows).max(1);
+        let rows = entries.len().div_ceil(columns);
+        let column_width = entries.iter().map(Line::width).max().unwrap_or(0) as u16 + 2;
+
+        let area = Self::centered_rect(
+            size,
+            (size.width * 3 / 5).max(column_width * columns as u16 + 2),
+            (size.height * 3 / 5).max(rows as u16 + chrome),
+        );
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(" Help ")
+            .border_style(Style::default().fg(accent))
             .style(Style::default().fg(foreground));
+        let inner = block.inner(area);
+        f.render_widget(Clear, area);
+        f.render_widget(block, area);
+
+        let sections = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(header.len() as u16),
+                Constraint::Length(rows as u16),
+                Constraint::Length(footer.len() as u16),
+            ])
+            .split(inner);
+        f.render_widget(Paragraph::new(header), sections[0]);
+        f.render_widget(Paragraph::new(footer), sections[2]);
+
+        let column_areas = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(vec![Constraint::Length(column_width); columns])
+            .split(sections[1]);
+        let mut entries = entries.into_iter();
+        for column_area in column_areas.iter() {
+            let column: Vec<Line> = entries.by_ref().take(rows).collect();
+            f.render_widget(Paragraph::new(column), *column_area);
+        }
+    }
 
-        f.render_widget(help_widget, size);
+    /// A help line: the key in cyan, then what it does
+    fn help_entry(key: &str, description: &str) -> Line<'static> {
+        Line::from(vec![
+            Span::styled(format!("{}:", key), Style::default().fg(Color::Cyan)),
+            Span::raw(format!(" {}", description)),
+        ])
+    }
+
+    /// Render the popup with a track's format, file and tags
+    fn render_track_info(f: &mut Frame, state: &PlayerState, info: &TrackInfo) {
+        let size = f.area();
+        let accent = state.config.colors.accent();
+        let foreground = state.config.colors.foreground();
+        let track = &info.track;
+        let codec = info.codec.as_ref();
+
+        // The decoder knows best what's playing; the container fills in the rest
+        let sample_rate = info
+            .stream
+            .map(|s| s.sample_rate)
+            .or(codec.and_then(|c| c.sample_rate));
+        let channels = info
+            .stream
+            .map(|s| s.channels)
+            .or(codec.and_then(|c| c.channels));
+        let gain = |gain: Option<f32>| gain.map(|g| format!("{:+.2} dB", g));
+
+        let fields = [
+            ("Title", Some(track.title.clone())),
+            ("Artist", track.artist.clone()),
+            ("Album", track.album.clone()),
+            ("Track", track.track_number.map(|n| n.to_string())),
+            ("Duration", track.duration.map(Self::format_long_time)),
+            ("Codec", codec.and_then(|c| c.codec.clone())),
+            (
+                "Sample Rate",
+                sample_rate.map(|rate| format!("{} Hz", rate)),
+            ),
+            (
+                "Bit Depth",
+                codec
+                    .and_then(|c| c.bits_per_sample)
+                    .map(|bits| format!("{}-bit", bits)),
+            ),
+            (
+                "Channels",
+                channels.map(|count| match count {
+                    1 => "1 (mono)".to_string(),
+                    2 => "2 (stereo)".to_string(),
+                    _ => count.to_string(),
+                }),
+            ),
+            ("Track Gain", gain(track.track_gain)),
+            ("Album Gain", gain(track.album_gain)),
+            (
+                "Trim",
+                Some(format!("{:+.0} dB", state.database.trim(&track.path))),
+            ),
+            (
+                "Plays",
+                Some(state.database.play_count(&track.path).to_string()),
+            ),
+            ("Path", Some(track.path.display().to_string())),
+        ];
+
+        let lines: Vec<Line> = fields
+            .into_iter()
+            .map(|(label, value)| {
+                Line::from(vec![
+                    Span::styled(format!("{:<12}", label), Style::default().fg(Color::Cyan)),
+                    match value {
+                        Some(value) => Span::raw(value),
+                        None => Span::styled("unknown", Style::default().fg(Color::DarkGray)),
+                    },
+                ])
+            })
+            .collect();
+
+        let area = Self::centered_rect(size, (size.width * 4 / 5).max(40), lines.len() as u16 + 4);
+        let popup = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!(
+                    " Track Info - {}/Esc: Close ",
+                    state.config.keybinds.track_info
+                ))
+                .border_style(Style::default().fg(accent))
+                .style(Style::default().fg(foreground)),
+        );
+
+        f.render_widget(Clear, area);
+        f.render_widget(popup, area);
+    }
+
+    /// Render recently played tracks, most recent first
+    fn render_history(f: &mut Frame, state: &PlayerState) {
+        let size = f.area();
+        let accent = state.config.colors.accent();
+        let foreground = state.config.colors.foreground();
+
+        let visible_height = size.height.saturating_sub(2) as usize;
+        let selected = state.history_selected;
+        let scroll_offset = (selected + 1).saturating_sub(visible_height);
+        let now = SystemTime::now();
+
+        let items: Vec<ListItem> = state
+            .history
+            .iter()
+            .rev()
+            .enumerate()
+            .skip(scroll_offset)
+            .take(visible_height)
+            .map(|(i, (path, played_at))| {
+                let title = state
+                    .database
+                    .index_of(path)
+                    .map(|idx| state.database.get_tracks()[idx].title.clone())
+                    .unwrap_or_else(|| {
+                        path.file_stem()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .to_string()
+                    });
+                let ago = now
+                    .duration_since(*played_at)
+                    .map(|d| Self::format_ago(d.as_secs()))
+                    .unwrap_or_default();
+
+                let style = if i == selected {
+                    Self::selected_style(state)
+                } else {
+                    Style::default().fg(foreground)
+                };
+                ListItem::new(Line::from(vec![
+                    Span::styled(format!("{:>8}  ", ago), Style::default().fg(Color::Gray)),
+                    Span::styled(title, style),
+                ]))
+            })
+            .collect();
+
+        let title = format!(
+            " Recently Played ({}) - Enter: Play | Esc: Close ",
+            state.history.len()
+        );
+        let list = List::new(items).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .border_style(Style::default().fg(accent)),
+        );
+
+        f.render_widget(list, size);
+    }
+
+    /// Format an age in seconds as a short "5m ago" style string
+    fn format_ago(secs: u64) -> String {
+        match secs {
+            0..=59 => "just now".to_string(),
+            60..=3599 => format!("{}m ago", secs / 60),
+            3600..=86399 => format!("{}h ago", secs / 3600),
+            _ => format!("{}d ago", secs / 86400),
+        }
+    }
+
+    /// Split a fitted title into spans, styling the characters at `matches`
+    /// (positions in the full title) that survived truncation
+    fn highlight_matches(
+        shown: &str,
+        title: &str,
+        matches: &[usize],
+        style: Style,
+    ) -> Vec<Span<'static>> {
+        let mut title_chars = title.chars();
+        shown
+            .chars()
+            .enumerate()
+            .map(|(pos, c)| {
+                let span = Span::raw(c.to_string());
+                // Past the cut the shown text is the ellipsis and padding
+                if title_chars.next() == Some(c) && matches.contains(&pos) {
+                    span.style(style)
+                } else {
+                    span
+                }
+            })
+            .collect()
+    }
+
+    /// "Artist — Title", or just the title when the artist isn't tagged
+    fn track_label(track: &Track) -> String {
+        match &track.artist {
+            Some(artist) => format!("{} — {}", artist, track.title),
+            None => track.title.clone(),
+        }
+    }
+
+    /// `fancy` normally, or `ascii` when drawing with ASCII only
+    fn glyph(state: &PlayerState, fancy: &'static str, ascii: &'static str) -> &'static str {
+        if state.config.symbols.ascii() {
+            ascii
+        } else {
+            fancy
+        }
+    }
+
+    /// Marker before a track row (play state, then problems, then selection),
+    /// padded so every title starts in the same column
+    fn track_prefix(
+        state: &PlayerState,
+        path: &Path,
+        is_current: bool,
+        is_selected: bool,
+        mark_duplicates: bool,
+    ) -> String {
+        let symbols = &state.config.symbols;
+        let symbol = if is_current && state.is_playing {
+            symbols.playing()
+        } else if is_current {
+            symbols.paused()
+        } else if state.is_unplayable(path) {
+            symbols.unplayable()
+        } else if mark_duplicates && state.database.is_duplicate(path) {
+            symbols.duplicate()
+        } else if is_selected {
+            symbols.selected()
+        } else {
+            ""
+        };
+        Self::fit_width(symbol, Self::prefix_width(state))
+    }
+
+    /// Columns taken by the row markers: the widest symbol and a space
+    fn prefix_width(state: &PlayerState) -> usize {
+        let symbols = &state.config.symbols;
+        [
+            symbols.playing(),
+            symbols.paused(),
+            symbols.selected(),
+            symbols.unplayable(),
+            symbols.duplicate(),
+        ]
+        .iter()
+        .map(|symbol| symbol.width())
+        .max()
+        .unwrap_or(0)
+            + 1
+    }
+
+    /// Cut `text` to `width` terminal columns, ending in "…" if it was too
+    /// long, and pad it with spaces to exactly that width
+    fn fit_width(text: &str, width: usize) -> String {
+        if text.width() <= width {
+            return format!("{}{}", text, " ".repeat(width - text.width()));
+        }
+
+        let mut fitted = String::new();
+        let mut used = 0;
+        for c in text.chars() {
+            let w = c.width().unwrap_or(0);
+            if used + w + 1 > width {
+                break;
+            }
+            fitted.push(c);
+            used += w;
+        }
+        if width > 0 {
+            fitted.push('…');
+            used += 1;
+        }
+        fitted.push_str(&" ".repeat(width - used));
+        fitted
     }
 
     /// Format seconds to MM:SS
@@ -473,4 +1766,12 @@ samsit-phew",
         let seconds = secs % 60;
         format!("{:02}:{:02}", minutes, seconds)
     }
+
+    /// Like `format_time`, with hours once there are any
+    fn format_long_time(secs: u64) -> String {
+        if secs < 3600 {
+            return Self::format_time(secs);
+        }
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    }
 }
@@ -1,59 +1,375 @@
+use rodio::source::SeekError;
+use rodio::Source;
 use rustfft::{FftPlanner, num_complex::Complex};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How the audio is drawn in the visualizer panel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VisualizerMode {
+    /// FFT bars growing from the bottom
+    #[default]
+    Spectrum,
+    /// Time-domain oscilloscope of the raw samples
+    Waveform,
+    /// Spectrum bars reflected up and down from the middle
+    Mirror,
+    /// Recent spectra scrolling left, one column per frame, colored by intensity
+    Spectrogram,
+}
+
+impl VisualizerMode {
+    /// Next mode in the cycle
+    pub fn next(self) -> Self {
+        match self {
+            VisualizerMode::Spectrum => VisualizerMode::Waveform,
+            VisualizerMode::Waveform => VisualizerMode::Mirror,
+            VisualizerMode::Mirror => VisualizerMode::Spectrogram,
+            VisualizerMode::Spectrogram => VisualizerMode::Spectrum,
+        }
+    }
+
+    /// Display name for the panel title
+    pub fn name(self) -> &'static str {
+        match self {
+            VisualizerMode::Spectrum => "Spectrum",
+            VisualizerMode::Waveform => "Waveform",
+            VisualizerMode::Mirror => "Mirror",
+            VisualizerMode::Spectrogram => "Spectrogram",
+        }
+    }
+}
+
+/// Which way the spectrum bars grow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VisualizerOrientation {
+    /// One bar per column, low frequencies on the left
+    #[default]
+    Vertical,
+    /// One bar per row growing rightward, low frequencies at the top
+    Horizontal,
+}
+
+/// Divisor used to normalize magnitudes when automatic gain is off
+const FIXED_GAIN: f32 = 100.0;
+
+/// How quickly the tracked peak falls back after loud passages (per reference step)
+const PEAK_DECAY: f32 = 0.995;
+
+/// Update interval the smoothing factors and peak decay are tuned for; other
+/// intervals scale them so the bars move at the same speed
+const REFERENCE_STEP: Duration = Duration::from_millis(50);
+
+/// Longest gap one update accounts for, so bars ease back in after a pause
+/// instead of jumping
+const MAX_STEP: Duration = Duration::from_millis(250);
+
+/// Lowest peak the gain adapts to, so silence isn't amplified into noise
+const MIN_PEAK: f32 = 1.0;
+
+/// Frames the sample ring keeps, enough for the largest FFT window
+const RING_FRAMES: usize = 4096;
+
+/// Largest number of frames analyzed per update
+const FFT_SIZE: usize = 2048;
+
+/// Most bars the spectrum can be split into, one per FFT frequency bin
+pub const MAX_BARS: usize = FFT_SIZE / 2;
+
+/// Frames a tap collects before handing them to the ring in one lock
+const TAP_BATCH: usize = 256;
+
+/// Spectrogram columns kept, enough to fill a wide terminal
+const SPECTROGRAM_COLUMNS: usize = 512;
+
+/// Time between spectrogram columns, so it scrolls at a steady speed
+const SPECTROGRAM_STEP: Duration = Duration::from_millis(50);
+
+/// Fixed-size history of the most recent left/right frames, written by the
+/// playing track's `SampleTap` and read directly by the visualizer
+pub struct SampleRing {
+    frames: Vec<[f32; 2]>,
+    next: usize,   // Slot the next frame goes into
+    filled: usize, // Frames held since the last clear
+    written: u64,  // Frames ever written, so readers can tell when new ones arrive
+}
+
+impl Default for SampleRing {
+    fn default() -> Self {
+        Self {
+            frames: vec![[0.0; 2]; RING_FRAMES],
+            next: 0,
+            filled: 0,
+            written: 0,
+        }
+    }
+}
+
+impl SampleRing {
+    /// Add a frame, overwriting the oldest once full
+    pub fn push(&mut self, frame: [f32; 2]) {
+        self.frames[self.next] = frame;
+        self.next = (self.next + 1) % self.frames.len();
+        self.filled = (self.filled + 1).min(self.frames.len());
+        self.written += 1;
+    }
+
+    /// Forget the held frames (the write count keeps going)
+    pub fn clear(&mut self) {
+        self.filled = 0;
+    }
+
+    /// The most recent `count` frames, oldest first; fewer if not that many are held
+    fn latest(&self, count: usize) -> Vec<[f32; 2]> {
+        let count = count.min(self.filled);
+        let len = self.frames.len();
+        let start = (self.next + len - count) % len;
+        (0..count).map(|i| self.frames[(start + i) % len]).collect()
+    }
+}
+
+/// Source adapter that copies samples into a `SampleRing` as the sink plays
+/// them, so the visualizer follows the actual playback position, pauses and
+/// seeks included
+pub struct SampleTap<S> {
+    source: S,
+    ring: Arc<Mutex<SampleRing>>,
+    cancelled: Arc<AtomicBool>, // Set once another track takes over the ring
+    channels: usize,
+    channel: usize, // Channel of the next sample
+    frame: [f32; 2],
+    pending: Vec<[f32; 2]>, // Frames not yet handed to the ring
+}
+
+impl<S: Source<Item = f32>> SampleTap<S> {
+    pub fn new(source: S, ring: Arc<Mutex<SampleRing>>, cancelled: Arc<AtomicBool>) -> Self {
+        Self {
+            source,
+            ring,
+            cancelled,
+            channels: 1,
+            channel: 0,
+            frame: [0.0; 2],
+            pending: Vec::with_capacity(TAP_BATCH),
+        }
+    }
+
+    fn flush(&mut self) {
+        let mut ring = self.ring.lock().unwrap();
+        for frame in self.pending.drain(..) {
+            ring.push(frame);
+        }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for SampleTap<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        // A replaced track ends right away rather than writing stale samples
+        if self.cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+        let Some(sample) = self.source.next() else {
+            self.flush();
+            return None;
+        };
+
+        // Left and right are the first two channels; mono plays on both sides
+        match self.channel {
+            0 => {
+                self.channels = self.source.channels().max(1) as usize;
+                self.frame = [sample; 2];
+            }
+            1 => self.frame[1] = sample,
+            _ => {}
+        }
+        self.channel += 1;
+        if self.channel >= self.channels {
+            self.channel = 0;
+            self.pending.push(self.frame);
+            if self.pending.len() >= TAP_BATCH {
+                self.flush();
+            }
+        }
+
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.source.size_hint()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for SampleTap<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.channel = 0;
+        self.pending.clear();
+        Ok(())
+    }
+}
 
 /// CAVA-style audio visualizer with real FFT analysis
 pub struct Visualizer {
-    bars: Vec<f32>,
+    bars: Vec<f32>,       // Left channel when stereo, otherwise both mixed
+    right_bars: Vec<f32>, // Right channel, only filled when stereo
+    stereo: bool,
+    waveform: Vec<f32>,
     bar_count: usize,
-    smoothing: f32,
-    audio_buffer: Arc<Mutex<Vec<f32>>>,
+    attack: f32,  // Smoothing while a bar rises
+    release: f32, // Smoothing while a bar falls
+    auto_gain: bool,
+    peak: f32, // Running maximum magnitude for automatic gain
+    samples: Arc<Mutex<SampleRing>>,
+    last_written: u64, // Ring write count at the previous update
     fft_planner: FftPlanner<f32>,
+    spectrogram: VecDeque<Vec<f32>>, // Past spectra, oldest first
+    last_column: Instant,            // When the newest spectrogram column was taken
+    last_update: Instant,
+    eq: Vec<f32>,       // Weight of each bar, empty when flat
+    eq_curve: Vec<f32>, // Points of the configured curve, from bass to treble
 }
 
 impl Visualizer {
-    /// Create a new visualizer
-    pub fn new(bar_count: usize, smoothing: f32) -> Self {
+    /// Create a new visualizer reading from the player's sample ring
+    pub fn new(
+        bar_count: usize,
+        attack: f32,
+        release: f32,
+        samples: Arc<Mutex<SampleRing>>,
+    ) -> Self {
+        let bar_count = bar_count.clamp(1, MAX_BARS);
         Self {
             bars: vec![0.0; bar_count],
+            right_bars: Vec::new(),
+            stereo: false,
+            waveform: Vec::new(),
             bar_count,
-            smoothing,
-            audio_buffer: Arc::new(Mutex::new(Vec::new())),
+            attack: attack.clamp(0.0, 1.0),
+            release: release.clamp(0.0, 1.0),
+            auto_gain: true,
+            peak: MIN_PEAK,
+            samples,
+            last_written: 0,
             fft_planner: FftPlanner::new(),
+            spectrogram: VecDeque::with_capacity(SPECTROGRAM_COLUMNS),
+            last_column: Instant::now(),
+            last_update: Instant::now(),
+            eq: Vec::new(),
+            eq_curve: Vec::new(),
         }
     }
 
-    /// Get audio buffer reference for audio capture
-    pub fn get_audio_buffer(&self) -> Arc<Mutex<Vec<f32>>> {
-        Arc::clone(&self.audio_buffer)
-    }
-
     /// Update visualization using FFT of audio samples
     pub fn update(&mut self) {
-        let mut buffer = self.audio_buffer.lock().unwrap();
-        
-        if buffer.is_empty() {
-            // Smooth decay when no audio
-            for bar in &mut self.bars {
-                *bar *= self.smoothing;
+        // Scale the per-step factors to the time since the last update
+        let elapsed = self.last_update.elapsed().min(MAX_STEP);
+        self.last_update = Instant::now();
+        let steps = elapsed.as_secs_f32() / REFERENCE_STEP.as_secs_f32();
+        let attack = self.attack.powf(steps);
+        let release = self.release.powf(steps);
+        let peak_decay = PEAK_DECAY.powf(steps);
+
+        self.analyze(attack, release, peak_decay);
+        if self.last_column.elapsed() >= SPECTROGRAM_STEP {
+            self.last_column = Instant::now();
+            self.push_spectrogram_column();
+        }
+    }
+
+    /// Recompute the bars and waveform from the newest samples, with the
+    /// smoothing factors for this update
+    fn analyze(&mut self, attack: f32, release: f32, peak_decay: f32) {
+        let ring = self.samples.lock().unwrap();
+        let written = ring.written;
+
+        if written == self.last_written || ring.filled == 0 {
+            // Smooth decay when no new audio
+            for bar in self.bars.iter_mut().chain(&mut self.right_bars) {
+                *bar *= release;
+            }
+            for sample in &mut self.waveform {
+                *sample *= release;
             }
             return;
         }
 
-        // Take samples for FFT (power of 2)
-        let fft_size = 2048.min(buffer.len().next_power_of_two());
-        let samples: Vec<f32> = if buffer.len() >= fft_size {
-            buffer.drain(..fft_size).collect()
+        // Analyze the newest samples (power of 2), so the bars keep up with the audio
+        let fft_size = FFT_SIZE.min(ring.filled.next_power_of_two());
+        let mut frames = ring.latest(fft_size);
+        drop(ring);
+        self.last_written = written;
+        frames.resize(fft_size, [0.0; 2]);
+        let mono: Vec<f32> = frames
+            .iter()
+            .map(|[left, right]| (left + right) / 2.0)
+            .collect();
+
+        if !self.stereo {
+            let magnitudes = self.magnitudes(&mono);
+            let gain = self.gain(&magnitudes, peak_decay);
+            Self::smooth(&mut self.bars, &magnitudes, gain, attack, release);
+            self.waveform = mono;
+            return;
+        }
+        self.waveform = mono;
+
+        let left: Vec<f32> = frames.iter().map(|frame| frame[0]).collect();
+        let right: Vec<f32> = frames.iter().map(|frame| frame[1]).collect();
+        let left = self.magnitudes(&left);
+        let right = self.magnitudes(&right);
+
+        // One gain for both sides so their levels stay comparable
+        let gain = self.gain(&[left.as_slice(), right.as_slice()].concat(), peak_decay);
+        Self::smooth(&mut self.bars, &left, gain, attack, release);
+        Self::smooth(&mut self.right_bars, &right, gain, attack, release);
+    }
+
+    /// Record the current bars as the newest spectrogram column, channels mixed
+    fn push_spectrogram_column(&mut self) {
+        if self.spectrogram.len() == SPECTROGRAM_COLUMNS {
+            self.spectrogram.pop_front();
+        }
+        let column = if self.stereo {
+            self.bars
+                .iter()
+                .zip(&self.right_bars)
+                .map(|(left, right)| (left + right) / 2.0)
+                .collect()
         } else {
-            let mut samples = buffer.drain(..).collect::<Vec<_>>();
-            samples.resize(fft_size, 0.0);
-            samples
+            self.bars.clone()
         };
+        self.spectrogram.push_back(column);
+    }
+
+    /// Average FFT magnitude in each bar's frequency range
+    fn magnitudes(&mut self, samples: &[f32]) -> Vec<f32> {
+        let fft_size = samples.len();
 
         // Prepare complex input for FFT
-        let mut input: Vec<Complex<f32>> = samples
-            .iter()
-            .map(|&s| Complex::new(s, 0.0))
-            .collect();
+        let mut input: Vec<Complex<f32>> = samples.iter().map(|&s| Complex::new(s, 0.0)).collect();
 
         // Perform FFT
         let fft = self.fft_planner.plan_fft_forward(fft_size);
@@ -61,35 +377,52 @@ impl Visualizer {
 
         // Calculate magnitude spectrum and map to bars
         let spectrum_size = fft_size / 2;
-        let freqs_per_bar = spectrum_size / self.bar_count;
-
-        for (i, bar) in self.bars.iter_mut().enumerate() {
-            let start_idx = i * freqs_per_bar;
-            let end_idx = ((i + 1) * freqs_per_bar).min(spectrum_size);
-            
-            if start_idx >= spectrum_size {
-                break;
-            }
 
-            // Average magnitude for this bar's frequency range
-            let mut sum = 0.0;
-            for j in start_idx..end_idx {
-                let magnitude = input[j].norm();
-                sum += magnitude;
-            }
-            
-            let avg_magnitude = sum / (end_idx - start_idx) as f32;
-            
+        // Average magnitude for each bar's frequency range; with more bars
+        // than frequencies, neighbouring bars share one
+        (0..self.bar_count)
+            .map(|i| {
+                let start_idx = (i * spectrum_size / self.bar_count).min(spectrum_size);
+                let end_idx = ((i + 1) * spectrum_size / self.bar_count)
+                    .max(start_idx + 1)
+                    .min(spectrum_size);
+                if start_idx >= end_idx {
+                    return 0.0;
+                }
+                let sum: f32 = input[start_idx..end_idx].iter().map(|c| c.norm()).sum();
+                let weight = self.eq.get(i).copied().unwrap_or(1.0);
+                sum / (end_idx - start_idx) as f32 * weight
+            })
+            .collect()
+    }
+
+    /// Divisor that normalizes this update's magnitudes
+    fn gain(&mut self, magnitudes: &[f32], peak_decay: f32) -> f32 {
+        // Auto-range to the loudest recent bar, letting the peak fall slowly
+        if self.auto_gain {
+            let frame_peak = magnitudes.iter().copied().fold(0.0, f32::max);
+            self.peak = (self.peak * peak_decay).max(frame_peak).max(MIN_PEAK);
+            self.peak
+        } else {
+            FIXED_GAIN
+        }
+    }
+
+    /// Move bars toward the new magnitudes
+    fn smooth(bars: &mut [f32], magnitudes: &[f32], gain: f32, attack: f32, release: f32) {
+        for (bar, &avg_magnitude) in bars.iter_mut().zip(magnitudes) {
             // Normalize and apply logarithmic scaling for better visualization
-            let normalized = (avg_magnitude / 100.0).min(1.0);
+            let normalized = (avg_magnitude / gain).min(1.0);
             let log_scaled = if normalized > 0.0 {
                 (normalized.log10() + 2.0) / 2.0 // Scale from -2..0 to 0..1
             } else {
                 0.0
             }.max(0.0).min(1.0);
 
-            // Smooth interpolation with previous value
-            *bar = *bar * self.smoothing + log_scaled * (1.0 - self.smoothing);
+            // Smooth interpolation with previous value, snapping up on
+            // transients and decaying slowly depending on attack/release
+            let smoothing = if log_scaled > *bar { attack } else { release };
+            *bar = *bar * smoothing + log_scaled * (1.0 - smoothing);
         }
     }
 
@@ -98,16 +431,110 @@ impl Visualizer {
         &self.bars
     }
 
-    /// Set bar count
-    #[allow(dead_code)]
+    /// Right channel bar heights, when showing the channels separately
+    pub fn get_right_bars(&self) -> Option<&[f32]> {
+        self.stereo.then_some(self.right_bars.as_slice())
+    }
+
+    /// Past spectra for the spectrogram, oldest first, each low to high frequency
+    pub fn get_spectrogram(&self) -> &VecDeque<Vec<f32>> {
+        &self.spectrogram
+    }
+
+    /// Get the most recent raw mono samples (-1.0 to 1.0)
+    pub fn get_waveform(&self) -> &[f32] {
+        &self.waveform
+    }
+
+    /// Whether the bars and waveform have decayed too far to be seen
+    pub fn is_settled(&self) -> bool {
+        const VISIBLE: f32 = 1e-3;
+        self.bars
+            .iter()
+            .chain(&self.right_bars)
+            .chain(&self.waveform)
+            .all(|v| v.abs() < VISIBLE)
+    }
+
+    /// Set bar count (at least one), keeping the heights of the bars that stay
     pub fn set_bar_count(&mut self, count: usize) {
+        let count = count.clamp(1, MAX_BARS);
+        if count == self.bar_count {
+            return;
+        }
         self.bar_count = count;
+        self.stretch_eq();
         self.bars.resize(count, 0.0);
+        if self.stereo {
+            self.right_bars.resize(count, 0.0);
+        }
+    }
+
+    /// Analyze the left and right channels separately, or mixed together
+    pub fn set_stereo(&mut self, stereo: bool) {
+        self.stereo = stereo;
+        self.right_bars = if stereo {
+            vec![0.0; self.bar_count]
+        } else {
+            Vec::new()
+        };
+    }
+
+    /// Turn automatic gain on, or off to use the fixed divisor
+    pub fn set_auto_gain(&mut self, enabled: bool) {
+        self.auto_gain = enabled;
+        self.peak = MIN_PEAK;
+    }
+
+    /// Weight the bars by a curve of multipliers from bass to treble
+    pub fn set_eq(&mut self, curve: &[f32]) {
+        self.eq_curve = curve.iter().map(|w| w.max(0.0)).collect();
+        self.stretch_eq();
+    }
+
+    /// Spread the eq curve over the bars, interpolating between its points
+    fn stretch_eq(&mut self) {
+        let curve = &self.eq_curve;
+        self.eq = match curve.len() {
+            0 => Vec::new(),
+            1 => vec![curve[0]; self.bar_count],
+            _ => (0..self.bar_count)
+                .map(|i| {
+                    let pos =
+                        i as f32 / (self.bar_count - 1).max(1) as f32 * (curve.len() - 1) as f32;
+                    let idx = (pos as usize).min(curve.len() - 2);
+                    let frac = pos - idx as f32;
+                    curve[idx] + (curve[idx + 1] - curve[idx]) * frac
+                })
+                .collect(),
+        };
+    }
+
+    /// Set rise and fall smoothing factors
+    pub fn set_smoothing(&mut self, attack: f32, release: f32) {
+        self.attack = attack.clamp(0.0, 1.0);
+        self.release = release.clamp(0.0, 1.0);
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    /// Set smoothing factor
-    #[allow(dead_code)]
-    pub fn set_smoothing(&mut self, smoothing: f32) {
-        self.smoothing = smoothing.clamp(0.0, 1.0);
+    #[test]
+    fn bar_count_is_limited_to_the_frequency_bins() {
+        let ring = Arc::new(Mutex::new(SampleRing::default()));
+        let mut visualizer = Visualizer::new(4096, 0.5, 0.5, ring);
+        assert_eq!(visualizer.get_bars().len(), MAX_BARS);
+
+        // An impulse has energy at every frequency, so no bar may come up empty,
+        // even from a short window with fewer bins than bars
+        for fft_size in [FFT_SIZE, 256] {
+            let mut impulse = vec![0.0; fft_size];
+            impulse[0] = 1.0;
+            let magnitudes = visualizer.magnitudes(&impulse);
+            assert_eq!(magnitudes.len(), MAX_BARS);
+            assert!(magnitudes.iter().all(|&m| m > 0.0));
+        }
     }
 }